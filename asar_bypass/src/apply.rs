@@ -0,0 +1,243 @@
+//! Applies patch files, such as those written by [`crate::export`], so the
+//! same workflow works for receiving a patch as for sharing one.
+
+use goblin::pe::PE;
+
+use crate::{Error, Result, rva_to_file_offset};
+
+/// Applies `patch` to `data`, detecting its format (IPS, BPS or x64dbg `.1337`)
+/// from its header.
+pub fn apply(data: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(b"BPS1") {
+        return apply_bps(&data, patch);
+    }
+
+    let mut data = data;
+    if patch.starts_with(b"PATCH") {
+        apply_ips(&mut data, patch)?;
+    } else if patch.starts_with(b">") {
+        let patch =
+            std::str::from_utf8(patch).map_err(|_| invalid("x64dbg patches must be text"))?;
+        apply_x64dbg(&mut data, patch)?;
+    } else {
+        return Err(invalid("unknown patch format"));
+    }
+    Ok(data)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidPatchFile(reason.to_string())
+}
+
+/// Applies an IPS patch, growing `data` if a record writes past its end.
+pub fn apply_ips(data: &mut Vec<u8>, patch: &[u8]) -> Result<()> {
+    let mut reader = Reader {
+        rest: patch
+            .strip_prefix(b"PATCH")
+            .ok_or_else(|| invalid("missing IPS header"))?,
+    };
+
+    loop {
+        let offset = reader.take(3)?;
+        if offset == b"EOF" {
+            return Ok(());
+        }
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let size = u16::from_be_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+
+        // a zero size marks a run of a single repeated byte
+        let (len, bytes) = if size == 0 {
+            let len = u16::from_be_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+            (len, None)
+        } else {
+            (size, Some(reader.take(size)?))
+        };
+        if data.len() < offset + len {
+            data.resize(offset + len, 0);
+        }
+        match bytes {
+            Some(bytes) => data[offset..offset + len].copy_from_slice(bytes),
+            None => data[offset..offset + len].fill(reader.take(1)?[0]),
+        }
+    }
+}
+
+/// Applies a BPS patch to `source`, verifying the source, target and patch checksums.
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let body = patch
+        .strip_prefix(b"BPS1")
+        .ok_or_else(|| invalid("missing BPS header"))?;
+    let (body, footer) = body
+        .split_at_checked(body.len().saturating_sub(12))
+        .filter(|(_, footer)| footer.len() == 12)
+        .ok_or_else(|| invalid("truncated BPS footer"))?;
+    let checksum =
+        |idx: usize| u32::from_le_bytes(footer[idx * 4..idx * 4 + 4].try_into().unwrap());
+
+    verify_crc32(checksum(2), crc32fast::hash(&patch[..patch.len() - 4]))?;
+    verify_crc32(checksum(0), crc32fast::hash(source))?;
+
+    let mut reader = Reader { rest: body };
+    let source_size = reader.number()? as usize;
+    let target_size = reader.number()? as usize;
+    let metadata_size = reader.number()? as usize;
+    reader.take(metadata_size)?;
+    if source_size != source.len() {
+        return Err(invalid("the patch was made for a file of a different size"));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_rel, mut target_rel) = (0usize, 0usize);
+    while !reader.rest.is_empty() {
+        let action = reader.number()?;
+        let len = (action >> 2) as usize + 1;
+        match action & 3 {
+            // SourceRead
+            0 => {
+                let at = target.len();
+                target.extend_from_slice(
+                    source
+                        .get(at..at + len)
+                        .ok_or_else(|| invalid("SourceRead out of range"))?,
+                );
+            }
+            // TargetRead
+            1 => target.extend_from_slice(reader.take(len)?),
+            // SourceCopy
+            2 => {
+                source_rel = source_rel.wrapping_add_signed(reader.signed()?);
+                let bytes = source
+                    .get(source_rel..source_rel + len)
+                    .ok_or_else(|| invalid("SourceCopy out of range"))?;
+                target.extend_from_slice(bytes);
+                source_rel += len;
+            }
+            // TargetCopy, which can overlap with what it writes, so goes byte by byte
+            _ => {
+                target_rel = target_rel.wrapping_add_signed(reader.signed()?);
+                for _ in 0..len {
+                    let byte = *target
+                        .get(target_rel)
+                        .ok_or_else(|| invalid("TargetCopy out of range"))?;
+                    target.push(byte);
+                    target_rel += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid("the patched file has the wrong size"));
+    }
+    verify_crc32(checksum(1), crc32fast::hash(&target))?;
+    Ok(target)
+}
+
+/// Reads through the records of a patch file.
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let (head, tail) = self
+            .rest
+            .split_at_checked(len)
+            .ok_or_else(|| invalid("truncated patch"))?;
+        self.rest = tail;
+        Ok(head)
+    }
+
+    /// Reads a BPS variable-length number.
+    fn number(&mut self) -> Result<u64> {
+        let (mut value, mut shift) = (0u64, 1u64);
+        loop {
+            let x = self.take(1)?[0] as u64;
+            value = value
+                .checked_add((x & 0x7F) * shift)
+                .ok_or_else(|| invalid("BPS number overflowed"))?;
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+
+    /// Reads a BPS signed offset, whose lowest bit is the sign.
+    fn signed(&mut self) -> Result<isize> {
+        let value = self.number()?;
+        let magnitude = (value >> 1) as isize;
+        Ok(if value & 1 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+}
+
+/// Applies an x64dbg `.1337` patch, refusing if any byte isn't what the patch expects.
+pub fn apply_x64dbg(data: &mut [u8], patch: &str) -> Result<()> {
+    let pe = PE::parse(data)?;
+
+    let mut writes = Vec::new();
+    for line in patch
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('>'))
+    {
+        let parsed = line.split_once(':').and_then(|(rva, change)| {
+            let (old, new) = change.split_once("->")?;
+            Some((
+                u32::from_str_radix(rva, 16).ok()?,
+                u8::from_str_radix(old, 16).ok()?,
+                u8::from_str_radix(new, 16).ok()?,
+            ))
+        });
+        let (rva, old, new) = parsed.ok_or_else(|| invalid(&format!("malformed line {line:?}")))?;
+        let file_off = rva_to_file_offset(&pe, rva).ok_or(Error::RvaNotFound)?;
+        writes.push((file_off, old, new));
+    }
+
+    // a truncated file can map an RVA past its end, so every byte is checked before any is written
+    for &(file_off, old, _) in &writes {
+        match data.get(file_off) {
+            Some(&x) if x == old => {}
+            Some(_) => return Err(Error::MetaMismatch(file_off)),
+            None => {
+                return Err(invalid(&format!(
+                    "0x{file_off:x} is past the end of the file"
+                )));
+            }
+        }
+    }
+    for (file_off, _, new) in writes {
+        data[file_off] = new;
+    }
+    Ok(())
+}
+
+/// Fails unless `actual` matches the `expected` CRC32.
+pub fn verify_crc32(expected: u32, actual: u32) -> Result<()> {
+    if expected != actual {
+        return Err(Error::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::PeBuilder;
+
+    #[test]
+    fn refuses_x64dbg_write_past_truncated_end() {
+        let fixture = PeBuilder::new().image_base(0x1_4000_0000).build();
+        let rva = fixture.string_va - 0x1_4000_0000;
+        // everything from the string's section onwards is cut off
+        let mut data = fixture.data[..fixture.func_end].to_vec();
+        let patch = format!(">module\n{rva:016X}:00->01\n");
+        let err = apply_x64dbg(&mut data, &patch).unwrap_err();
+        assert!(matches!(err, Error::InvalidPatchFile(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+
+use capstone::prelude::*;
+use capstone::{Instructions, arch};
+use goblin::pe::PE;
+use log::{debug, info};
+
+use crate::dump::hexdump;
+use crate::layout::{self, SectionLayout};
+use crate::locate::{BoundsSource, FunctionBounds};
+use crate::signatures::Prologue;
+use crate::strategy::Reference;
+use crate::{Error, PatchOptions, Result, rva_to_file_offset};
+
+/// What an instruction means when looking for function boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Push,
+    Ret,
+    Int3,
+    /// `ud2` or `hlt`, which never fall through
+    Halt,
+    Jmp,
+    /// Conditional branches, including `loop` and `jrcxz`
+    Jcc,
+    /// `sub rsp, imm`
+    StackAlloc,
+    /// `mov rbp, rsp`
+    FrameSetup,
+    Other,
+}
+
+impl Kind {
+    fn classify(mnemonic: &str, op_str: &str) -> Self {
+        match mnemonic {
+            "ret" | "retf" => Kind::Ret,
+            "int3" => Kind::Int3,
+            "ud2" | "hlt" => Kind::Halt,
+            "jmp" => Kind::Jmp,
+            mn if mn.starts_with('j') || mn.starts_with("loop") => Kind::Jcc,
+            "sub" if op_str.contains("rsp") => Kind::StackAlloc,
+            "mov" if op_str.contains("rbp") && op_str.contains("rsp") => Kind::FrameSetup,
+            mn if mn.starts_with("push") => Kind::Push,
+            _ => Kind::Other,
+        }
+    }
+}
+
+/// The parts of an instruction the heuristics need, so a window can be kept
+/// inside of a reusable buffer instead of holding onto Capstone allocations.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    address: u64,
+    kind: Kind,
+}
+
+/// Disassembles `code` into `window`, stopping at the first instruction at or past `stop_va`.
+fn fill_window(cs: &Capstone, window: &mut Vec<Slot>, code: &[u8], vabase: u64, stop_va: u64) {
+    window.clear();
+    let Ok(insns) = cs.disasm_all(code, vabase) else {
+        return;
+    };
+    for insn in insns.iter() {
+        if insn.address() >= stop_va {
+            break;
+        }
+        window.push(Slot {
+            address: insn.address(),
+            kind: Kind::classify(
+                insn.mnemonic().unwrap_or_default(),
+                insn.op_str().unwrap_or_default(),
+            ),
+        });
+    }
+}
+
+/// The most instructions [`BoundsFinder::explore`] will visit before giving up.
+const MAX_FLOW_INSNS: usize = 0x10000;
+
+/// Instructions decoded at a time when walking forwards, as only the start of
+/// what is handed to Capstone is usually looked at before stopping.
+const DISASM_BATCH: usize = 0x100;
+
+/// The most instructions [`BoundsFinder::is_prologue`] looks at, enough for
+/// every non-volatile register to be pushed before the stack allocation.
+const PROLOGUE_INSNS: usize = 0x10;
+
+/// Disassembles `code` as if it were loaded at `va`, [`DISASM_BATCH`]
+/// instructions at a time, stopping at the first undecodable byte.
+fn disasm_batches<'cs>(
+    cs: &'cs Capstone,
+    mut code: &[u8],
+    mut va: u64,
+) -> impl Iterator<Item = Instructions<'cs>> {
+    std::iter::from_fn(move || {
+        let insns = cs
+            .disasm_count(code, va, DISASM_BATCH)
+            .ok()
+            .filter(|x| !x.is_empty())?;
+        let decoded: usize = insns.iter().map(|x| x.bytes().len()).sum();
+        // fewer than asked for means Capstone stopped at something it couldn't decode
+        code = match insns.len() < DISASM_BATCH {
+            true => &[],
+            false => &code[decoded..],
+        };
+        va += decoded as u64;
+        Some(insns)
+    })
+}
+
+/// How many zero bytes after a `ret` or `jmp` count as padding, since a
+/// shorter run may well be the start of an `add [rax], al`.
+const MIN_ZERO_PADDING: usize = 4;
+
+/// Whether `rest`, the bytes after an instruction that never falls through,
+/// is the padding compilers place between functions.
+fn is_padding(rest: &[u8]) -> bool {
+    match rest {
+        [] | [0xCC, ..] => true,
+        _ => rest.len() >= MIN_ZERO_PADDING && rest[..MIN_ZERO_PADDING].iter().all(|&x| x == 0),
+    }
+}
+
+/// Parses the target of a direct branch, such as `0x140001234`.
+fn parse_branch_target(op_str: &str) -> Option<u64> {
+    u64::from_str_radix(op_str.strip_prefix("0x")?, 16).ok()
+}
+
+/// Returns the index of the first instruction in the run of pushes ending at `idx`.
+fn push_run_start(window: &[Slot], mut idx: usize) -> usize {
+    while idx > 0 && window[idx - 1].kind == Kind::Push {
+        idx -= 1;
+    }
+    idx
+}
+
+/// Looks up the function containing `ref_va` inside of the x64 exception
+/// directory (`.pdata`), returning its exact file offset range.
+///
+/// Cold fragments split out of a function chain back to it through their
+/// unwind info, so the chain is followed to the primary entry.
+fn find_unwind_bounds(pe: &PE, ref_va: u64) -> Option<(usize, usize)> {
+    let exception_data = pe.exception_data.as_ref()?;
+    let rva = u32::try_from(ref_va.checked_sub(pe.image_base)?).ok()?;
+    let mut function = exception_data.find_function(rva).ok()??;
+
+    // bounded, in case of a malformed chain pointing back at itself
+    for _ in 0..32 {
+        let Ok(info) = exception_data.get_unwind_info(function, &pe.sections) else {
+            break;
+        };
+        match info.chained_info {
+            Some(parent) => function = parent,
+            None => break,
+        }
+    }
+
+    if function.end_address <= function.begin_address {
+        return None;
+    }
+    // the end may sit in the zero-filled space past the raw data, which isn't in the file to patch
+    let start = rva_to_file_offset(pe, function.begin_address)?;
+    let sect = layout::sections(pe).find(|x| x.contains_file_offset(start))?;
+    let end = sect.raw_start + function.end_address.saturating_sub(sect.rva) as usize;
+    Some((start, end.min(sect.raw_end())))
+}
+
+/// Where the reference being bounded lives.
+struct Target<'a> {
+    sect: SectionLayout<'a>,
+    section_va_base: u64,
+    ref_va: u64,
+    ref_file_off: usize,
+}
+
+impl Target<'_> {
+    fn va_to_file_off(&self, va: u64) -> usize {
+        (va - self.section_va_base) as usize + self.sect.raw_start
+    }
+
+    fn file_off_to_va(&self, file_off: usize) -> u64 {
+        self.section_va_base + (file_off - self.sect.raw_start) as u64
+    }
+}
+
+/// Finds function bounds around a reference, reusing one Capstone handle and
+/// instruction buffer across every lookup.
+pub(crate) struct BoundsFinder {
+    cs: Capstone,
+    window: Vec<Slot>,
+    visited: HashSet<u64>,
+    worklist: Vec<u64>,
+}
+
+impl BoundsFinder {
+    pub fn new(is_64: bool) -> Result<Self> {
+        let cs = Capstone::new()
+            .x86()
+            .mode(if is_64 {
+                arch::x86::ArchMode::Mode64
+            } else {
+                arch::x86::ArchMode::Mode32
+            })
+            .detail(false)
+            .build()?;
+        Ok(Self {
+            cs,
+            window: Vec::new(),
+            visited: HashSet::new(),
+            worklist: Vec::new(),
+        })
+    }
+
+    /// Given a parsed `PE` and a reference VA inside a section, find the
+    /// function start/end (file offsets) containing the reference. Uses the
+    /// bounds carried by the reference or the exception directory when present,
+    /// otherwise the control flow from candidate entry points found by a small
+    /// backwards scan for a common prologue or padding. The reference's
+    /// prologue decides which is looked for first.
+    ///
+    /// Ranges larger than [`PatchOptions::max_patch_size`] are refused, unless
+    /// [`PatchOptions::allow_heuristic_shrink`] is set.
+    pub fn find(
+        &mut self,
+        pe: &PE,
+        reference: &Reference,
+        data: &[u8],
+        options: &PatchOptions,
+    ) -> Result<FunctionBounds> {
+        let image_base = pe.image_base;
+        let ref_va = reference.va;
+
+        // find containing section
+        let ref_rva = ref_va
+            .checked_sub(image_base)
+            .and_then(|x| u32::try_from(x).ok());
+        let sect = ref_rva
+            .and_then(|rva| layout::sections(pe).find(|sect| sect.contains_rva(rva)))
+            .ok_or(Error::SectionNotFound(ref_va))?;
+        let section_va_base = image_base + sect.rva as u64;
+
+        // past the raw data, the section is zero-filled when loaded, so there's nothing in the file there
+        let ref_file_off = ref_rva
+            .and_then(|rva| sect.rva_to_file_offset(rva))
+            .ok_or(Error::VirtualOnly(ref_va))?;
+        let target = Target {
+            sect,
+            section_va_base,
+            ref_va,
+            ref_file_off,
+        };
+
+        let sect_file_end = sect.raw_end().min(data.len());
+
+        // symbols and unwind data are exact, so only guess when there is neither
+        let ((func_start, func_end), source) = if let Some(end_va) = reference.end_va {
+            (
+                (
+                    ref_file_off,
+                    target.va_to_file_off(end_va).min(sect_file_end),
+                ),
+                BoundsSource::Reference,
+            )
+        } else if let Some(bounds) = find_unwind_bounds(pe, ref_va) {
+            debug!(
+                "Found function bounds 0x{:x}-0x{:x} in the exception directory",
+                bounds.0, bounds.1
+            );
+            (bounds, BoundsSource::Unwind)
+        } else {
+            let bounds = self.find_flow_bounds(
+                &target,
+                data,
+                reference.prologue,
+                sect_file_end,
+                options.max_patch_size,
+            );
+            (bounds, BoundsSource::ControlFlow)
+        };
+
+        // refuse (or shrink, if allowed) when absurdly large
+        if func_end.saturating_sub(func_start) > options.max_patch_size {
+            if !options.allow_heuristic_shrink {
+                let dump_end = func_end.min(func_start + 0x100);
+                return Err(Error::PatchTooLarge {
+                    start: func_start,
+                    end: func_end,
+                    max: options.max_patch_size,
+                    dump: hexdump(&data[func_start..dump_end], func_start),
+                });
+            }
+
+            let new_start = ref_file_off.saturating_sub(0x2000).max(sect.raw_start);
+            let new_end = (ref_file_off.saturating_add(0x2000)).min(sect_file_end);
+            info!(
+                "Function range too large (0x{:x}); shrinking to 0x{:x}-0x{:x}",
+                func_start, new_start, new_end
+            );
+            return Ok(FunctionBounds {
+                start: new_start,
+                end: new_end,
+                source: BoundsSource::Shrunk,
+            });
+        }
+
+        Ok(FunctionBounds {
+            start: func_start,
+            end: func_end,
+            source,
+        })
+    }
+
+    /// Derives the bounds from control flow: each candidate entry point is
+    /// explored, and the nearest one whose reachable code includes the
+    /// reference becomes the start, with the end of its reachable code as the end.
+    fn find_flow_bounds(
+        &mut self,
+        target: &Target,
+        data: &[u8],
+        prologue: Prologue,
+        sect_file_end: usize,
+        max_size: usize,
+    ) -> (usize, usize) {
+        // prologue matches first, in the preferred order, then function boundaries marked by padding
+        let mut candidates = match prologue {
+            Prologue::PushRun => [
+                self.find_push_run(target, data),
+                self.find_stack_alloc(target, data),
+            ],
+            Prologue::StackAlloc => [
+                self.find_stack_alloc(target, data),
+                self.find_push_run(target, data),
+            ],
+        }
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        candidates.extend(self.find_padding_entries(target, data));
+
+        for &entry in &candidates {
+            let entry_va = target.file_off_to_va(entry);
+            if let Some(end_va) = self.explore(target, data, entry_va, sect_file_end, max_size) {
+                return (entry, target.va_to_file_off(end_va));
+            }
+        }
+
+        // nothing reached the reference, so default to bounded window below ref
+        let func_start = candidates.first().copied().unwrap_or_else(|| {
+            let lower = target.ref_file_off.saturating_sub(0x2000);
+            let sect_start = target.sect.raw_start;
+            if lower < sect_start {
+                sect_start
+            } else {
+                lower
+            }
+        });
+
+        // and the first RET after it that ends the function
+        let func_end = self
+            .find_end(target, target.ref_file_off, sect_file_end, data)
+            // final fallback: bounded window after reference
+            .unwrap_or_else(|| (target.ref_file_off.saturating_add(0x2000)).min(sect_file_end));
+
+        (func_start, func_end)
+    }
+
+    /// Returns the instructions directly after INT3 padding before the
+    /// reference, which is how MSVC separates functions, nearest first.
+    fn find_padding_entries(&mut self, target: &Target, data: &[u8]) -> Vec<usize> {
+        let sect_start = target.sect.raw_start;
+        let search_file_start = target.ref_file_off.saturating_sub(4096).max(sect_start);
+        let search_file_end = target
+            .ref_file_off
+            .min(target.sect.raw_end())
+            .min(data.len());
+        if search_file_start >= search_file_end {
+            return Vec::new();
+        }
+
+        let code = &data[search_file_start..search_file_end];
+        fill_window(
+            &self.cs,
+            &mut self.window,
+            code,
+            target.file_off_to_va(search_file_start),
+            target.ref_va,
+        );
+
+        self.window
+            .windows(2)
+            .rev()
+            .filter(|pair| pair[0].kind == Kind::Int3 && pair[1].kind != Kind::Int3)
+            .map(|pair| target.va_to_file_off(pair[1].address))
+            .collect()
+    }
+
+    /// Follows every branch reachable from `entry_va` into
+    /// `entry_va..entry_va + max_size`, returning the end of the reachable code
+    /// if it includes the reference.
+    ///
+    /// The walk stops once the code runs past `max_size`, returning how far it
+    /// got, so a function too large to patch is noticed rather than cut short.
+    fn explore(
+        &mut self,
+        target: &Target,
+        data: &[u8],
+        entry_va: u64,
+        sect_file_end: usize,
+        max_size: usize,
+    ) -> Option<u64> {
+        let limit_va = target.file_off_to_va(sect_file_end);
+        let max_va = entry_va + max_size as u64;
+        let in_bounds = |va: &u64| (entry_va..max_va).contains(va);
+
+        self.visited.clear();
+        self.worklist.clear();
+        self.worklist.push(entry_va);
+
+        let mut end_va = entry_va;
+        'walk: while let Some(va) = self.worklist.pop() {
+            if self.visited.contains(&va) {
+                continue;
+            }
+
+            let code = &data[target.va_to_file_off(va)..target.va_to_file_off(limit_va)];
+            'block: for insns in disasm_batches(&self.cs, code, va) {
+                for insn in insns.iter() {
+                    if !self.visited.insert(insn.address()) || self.visited.len() > MAX_FLOW_INSNS {
+                        break 'block;
+                    }
+                    end_va = end_va.max(insn.address() + insn.bytes().len() as u64);
+                    if end_va > max_va {
+                        break 'walk;
+                    }
+
+                    let op_str = insn.op_str().unwrap_or_default();
+                    let branch = parse_branch_target(op_str).filter(in_bounds);
+                    match Kind::classify(insn.mnemonic().unwrap_or_default(), op_str) {
+                        Kind::Ret | Kind::Int3 | Kind::Halt => break 'block,
+                        // targets outside of the function are tail calls, so aren't followed
+                        Kind::Jmp => {
+                            self.worklist.extend(branch);
+                            break 'block;
+                        }
+                        Kind::Jcc => self.worklist.extend(branch),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        self.visited.contains(&target.ref_va).then_some(end_va)
+    }
+
+    /// Looks backwards for a run of PUSH instructions directly before the reference.
+    fn find_push_run(&mut self, target: &Target, data: &[u8]) -> Option<usize> {
+        let sect_start = target.sect.raw_start;
+        let search_file_start = target.ref_file_off.saturating_sub(4096).max(sect_start);
+        let search_file_end = target
+            .ref_file_off
+            .min(target.sect.raw_end())
+            .min(data.len());
+        if search_file_start >= search_file_end {
+            return None;
+        }
+
+        let code = &data[search_file_start..search_file_end];
+        fill_window(
+            &self.cs,
+            &mut self.window,
+            code,
+            target.file_off_to_va(search_file_start),
+            target.ref_va,
+        );
+
+        // walk backward from the last instruction before the reference while we see PUSH instructions
+        let last_idx = self.window.len().checked_sub(1)?;
+        let start = self.window[push_run_start(&self.window, last_idx)];
+        (start.kind == Kind::Push).then(|| target.va_to_file_off(start.address))
+    }
+
+    /// Looks for `sub rsp, imm` or `push rbp; mov rbp, rsp` shortly before the reference.
+    fn find_stack_alloc(&mut self, target: &Target, data: &[u8]) -> Option<usize> {
+        let sect_start = target.sect.raw_start;
+        let small_start = target.ref_file_off.saturating_sub(1024).max(sect_start);
+        let small_end = target
+            .ref_file_off
+            .min(target.sect.raw_end())
+            .min(data.len());
+        if small_start >= small_end {
+            return None;
+        }
+
+        let code = &data[small_start..small_end];
+        fill_window(
+            &self.cs,
+            &mut self.window,
+            code,
+            target.file_off_to_va(small_start),
+            target.ref_va,
+        );
+
+        for (idx, slot) in self.window.iter().enumerate() {
+            let start = match slot.kind {
+                // choose first push before it if present
+                Kind::StackAlloc => self.window[push_run_start(&self.window, idx)],
+                Kind::Push
+                    if self
+                        .window
+                        .get(idx + 1)
+                        .is_some_and(|x| x.kind == Kind::FrameSetup) =>
+                {
+                    *slot
+                }
+                _ => continue,
+            };
+            return Some(target.va_to_file_off(start.address));
+        }
+        None
+    }
+
+    /// Disassembles forward from `from` and returns the file offset the function
+    /// ends at: just past the first RET or JMP followed by padding or by the
+    /// next function's prologue, or at the padding itself when the code runs into it.
+    ///
+    /// The RETs of early returns and switch cases are followed by more of the
+    /// function, so are skipped, with the first RET only used when no boundary is found.
+    fn find_end(&self, target: &Target, from: usize, to: usize, data: &[u8]) -> Option<usize> {
+        if from >= to {
+            return None;
+        }
+
+        let mut first_ret = None;
+        for insns in disasm_batches(&self.cs, &data[from..to], target.file_off_to_va(from)) {
+            for insn in insns.iter() {
+                let start = target.va_to_file_off(insn.address());
+                let end = start + insn.bytes().len();
+                match Kind::classify(
+                    insn.mnemonic().unwrap_or_default(),
+                    insn.op_str().unwrap_or_default(),
+                ) {
+                    Kind::Int3 => return Some(start),
+                    Kind::Ret => {
+                        first_ret.get_or_insert(end);
+                    }
+                    Kind::Jmp | Kind::Halt => {}
+                    _ => continue,
+                }
+                if is_padding(&data[end..to]) || self.is_prologue(target, data, end, to) {
+                    return Some(end);
+                }
+            }
+        }
+        first_ret
+    }
+
+    /// Whether a function starts at `at`, with `sub rsp, imm`, `push rbp; mov rbp, rsp`,
+    /// pushes followed by a stack allocation, or a spill into the x64 shadow space.
+    fn is_prologue(&self, target: &Target, data: &[u8], at: usize, to: usize) -> bool {
+        let Ok(insns) =
+            self.cs
+                .disasm_count(&data[at..to], target.file_off_to_va(at), PROLOGUE_INSNS)
+        else {
+            return false;
+        };
+        let mut pushed = false;
+        for insn in insns.iter() {
+            let mnemonic = insn.mnemonic().unwrap_or_default();
+            let op_str = insn.op_str().unwrap_or_default();
+            let stack_alloc =
+                mnemonic == "sub" && (op_str.starts_with("rsp, ") || op_str.starts_with("esp, "));
+            let frame_setup = mnemonic == "mov" && matches!(op_str, "rbp, rsp" | "ebp, esp");
+            let shadow_spill = mnemonic == "mov" && op_str.starts_with("qword ptr [rsp + ");
+            match (pushed, mnemonic == "push") {
+                (_, true) => pushed = true,
+                (true, false) => return stack_alloc || frame_setup,
+                (false, false) => return stack_alloc || shadow_spill,
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,201 @@
+//! The parsed executable shared by every search phase, so each one can be
+//! run on its own without parsing the PE again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use goblin::pe::PE;
+
+use crate::bounds::BoundsFinder;
+use crate::layout;
+use crate::limits::ScanLimits;
+use crate::locate::{Candidate, FunctionBounds, StringMatch};
+use crate::methods;
+use crate::progress::{NoProgress, ProgressSink};
+use crate::signatures::{EXTRA_CANDIDATES, SIGNATURES};
+use crate::strategy::Reference;
+use crate::{
+    Error, PatchOptions, Result, file_offset_to_rva, locate_all_strings, locate_string, trace,
+    xrefs,
+};
+
+/// An executable parsed once, alongside the disassembler used to resolve
+/// function bounds, reused across string location, xref scanning and bounds finding.
+pub struct PatchContext<'a> {
+    data: &'a [u8],
+    pe: PE<'a>,
+    progress: &'a dyn ProgressSink,
+    limits: ScanLimits,
+    bounds: BoundsFinder,
+    /// Every `call rel32` inside of the executable sections, as (target RVA, file offset), sorted
+    calls: OnceLock<Vec<(i64, usize)>>,
+}
+
+impl<'a> PatchContext<'a> {
+    /// Parses `data` as a PE.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        Self::with_progress(data, &NoProgress)
+    }
+
+    /// Parses `data` as a PE, reporting any lengthy scans to `progress`.
+    pub fn with_progress(data: &'a [u8], progress: &'a dyn ProgressSink) -> Result<Self> {
+        let pe = PE::parse(data)?;
+        let bounds = BoundsFinder::new(pe.is_64)?;
+        Ok(Self {
+            data,
+            pe,
+            progress,
+            limits: ScanLimits::default(),
+            bounds,
+            calls: OnceLock::new(),
+        })
+    }
+
+    /// Limits every xref scan run through this context to `limits`.
+    pub fn with_limits(mut self, limits: ScanLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The raw bytes of the executable.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The parsed executable.
+    pub fn pe(&self) -> &PE<'a> {
+        &self.pe
+    }
+
+    /// Where progress should be reported.
+    pub fn progress(&self) -> &dyn ProgressSink {
+        self.progress
+    }
+
+    /// The virtual address of `file_off`, if it is inside of a section.
+    pub fn va_of(&self, file_off: usize) -> Option<u64> {
+        file_offset_to_rva(&self.pe, file_off).map(|rva| self.pe.image_base + rva as u64)
+    }
+
+    /// The name of the section containing `va`, if any.
+    pub fn section_name(&self, va: u64) -> Option<&str> {
+        let rva = u32::try_from(va.checked_sub(self.pe.image_base)?).ok()?;
+        layout::sections(&self.pe)
+            .find(|x| x.contains_rva(rva))
+            .and_then(|x| x.header.name().ok())
+    }
+
+    /// How many bytes of code the xref scan covers, from every executable section.
+    pub fn code_size(&self) -> usize {
+        layout::sections(&self.pe)
+            .filter(|x| x.is_executable())
+            .map(|x| x.raw_end().min(self.data.len()).saturating_sub(x.raw_start))
+            .sum()
+    }
+
+    /// Finds the first string of any known [`crate::signatures::Signature`].
+    pub fn locate_integrity_string(&self) -> Result<StringMatch> {
+        for signature in SIGNATURES {
+            let Ok(file_off) = locate_string(self.data, signature.needle) else {
+                continue;
+            };
+            return Ok(StringMatch {
+                signature: signature.name,
+                file_off,
+                va: self.va_of(file_off).ok_or(Error::RvaNotFound)?,
+            });
+        }
+        let needles: Vec<&str> = SIGNATURES.iter().map(|x| x.needle).collect();
+        Err(Error::StringNotFound(needles.join(" or ")))
+    }
+
+    /// Finds every instruction referencing the data at `file_off`, returning
+    /// their virtual addresses in ascending order.
+    pub fn find_xrefs(&self, file_off: usize) -> Result<Vec<u64>> {
+        let target_va = self.va_of(file_off).ok_or(Error::RvaNotFound)?;
+        let mut xrefs = self.find_xrefs_to_many(&[target_va])?;
+        Ok(xrefs.remove(&target_va).unwrap_or_default())
+    }
+
+    /// Finds every instruction referencing any of `target_vas` in a single
+    /// pass, keyed by the target they reference.
+    pub fn find_xrefs_to_many(&self, target_vas: &[u64]) -> Result<HashMap<u64, Vec<u64>>> {
+        xrefs::find_xrefs_to_many(&self.pe, self.data, target_vas, self.progress, &self.limits)
+    }
+
+    /// File offsets of every `call rel32` to the function starting at `func_start`.
+    ///
+    /// The calls are collected from every executable section on first use, so
+    /// each method looking for the callers of a function shares the one scan.
+    pub(crate) fn calls_to(&self, func_start: usize) -> Vec<usize> {
+        let Some(func_rva) = file_offset_to_rva(&self.pe, func_start) else {
+            return Vec::new();
+        };
+        let calls = self.calls.get_or_init(|| methods::all_calls(&self.pe, self.data));
+        let first = calls.partition_point(|(target, _)| *target < func_rva as i64);
+        calls[first..]
+            .iter()
+            .take_while(|(target, _)| *target == func_rva as i64)
+            .map(|(_, call)| *call)
+            .collect()
+    }
+
+    /// Resolves the bounds of the function `reference` points into, the same way patching does.
+    pub fn find_function_bounds(
+        &mut self,
+        reference: &Reference,
+        options: &PatchOptions,
+    ) -> Result<FunctionBounds> {
+        let _span = trace::phase("bounds", "");
+        self.bounds.find(&self.pe, reference, self.data, options)
+    }
+
+    /// Finds every occurrence of the strings used by the [`SIGNATURES`], plus a
+    /// few others related to the integrity check, alongside their code references.
+    pub fn scan_candidates(&self) -> Result<Vec<Candidate>> {
+        let needles = SIGNATURES
+            .iter()
+            .map(|x| x.needle)
+            .chain(EXTRA_CANDIDATES.iter().copied());
+
+        let mut candidates = Vec::new();
+        for needle in needles {
+            for (file_off, wide) in locate_all_strings(self.data, needle) {
+                let section =
+                    layout::sections(&self.pe).find(|sect| sect.contains_file_offset(file_off));
+                candidates.push(Candidate {
+                    needle,
+                    wide,
+                    file_off,
+                    va: self.va_of(file_off),
+                    section: section
+                        .and_then(|x| x.header.name().ok())
+                        .map(str::to_string),
+                    xrefs: Vec::new(),
+                });
+            }
+        }
+
+        // find the references to every occurrence in one pass, as disassembling is the slow part
+        let vas: Vec<u64> = candidates.iter().filter_map(|x| x.va).collect();
+        let mut xrefs = self.find_xrefs_to_many(&vas)?;
+        for candidate in &mut candidates {
+            if let Some(va) = candidate.va {
+                candidate.xrefs = xrefs.remove(&va).unwrap_or_default();
+            }
+        }
+
+        Ok(candidates)
+    }
+}
+
+impl fmt::Debug for PatchContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PatchContext")
+            .field("len", &self.data.len())
+            .field("image_base", &self.pe.image_base)
+            .field("is_64", &self.pe.is_64)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,134 @@
+use capstone::arch::x86::ArchMode;
+use capstone::prelude::*;
+
+use crate::PatchReport;
+
+/// Formats `data` as a classic hexdump, with offsets starting at `base`.
+pub fn hexdump(data: &[u8], base: usize) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<47}  |{ascii}|\n",
+            base + i * 16,
+            hex.join(" ")
+        ));
+    }
+    out
+}
+
+/// Formats `original` and `patched` as side-by-side hexdumps, with offsets
+/// starting at `base`, marking every line that differs with a `*`.
+pub fn side_by_side(original: &[u8], patched: &[u8], base: usize) -> String {
+    let hex = |chunk: &[u8]| {
+        chunk
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut out = String::new();
+    for (i, (before, after)) in original.chunks(16).zip(patched.chunks(16)).enumerate() {
+        let marker = if before == after { ' ' } else { '*' };
+        out.push_str(&format!(
+            "{marker} {:08x}  {:<47}  |  {:<47}\n",
+            base + i * 16,
+            hex(before),
+            hex(after)
+        ));
+    }
+    out
+}
+
+/// A single disassembled instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Instruction {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operands: String,
+}
+
+/// Disassembles `code` as if it were loaded at `va`, stopping at the first
+/// undecodable byte.
+pub fn instructions(code: &[u8], va: u64, is_64: bool) -> crate::Result<Vec<Instruction>> {
+    let cs = Capstone::new()
+        .x86()
+        .mode(if is_64 {
+            ArchMode::Mode64
+        } else {
+            ArchMode::Mode32
+        })
+        .build()?;
+
+    Ok(cs
+        .disasm_all(code, va)?
+        .iter()
+        .map(|insn| Instruction {
+            address: insn.address(),
+            bytes: insn.bytes().to_vec(),
+            mnemonic: insn.mnemonic().unwrap_or_default().to_string(),
+            operands: insn.op_str().unwrap_or_default().to_string(),
+        })
+        .collect())
+}
+
+/// Formats `instructions` one per line, collapsing runs of NOPs into a single line.
+pub fn format_instructions(instructions: &[Instruction]) -> String {
+    let mut out = String::new();
+    let mut nops = 0;
+    for insn in instructions {
+        if insn.mnemonic == "nop" {
+            nops += 1;
+            continue;
+        }
+        if nops > 0 {
+            out.push_str(&format!("{:>16}  ({nops} nops)\n", "..."));
+            nops = 0;
+        }
+        let bytes: Vec<String> = insn.bytes.iter().map(|b| format!("{b:02x}")).collect();
+        out.push_str(&format!(
+            "{:016x}  {:<30}  {} {}\n",
+            insn.address,
+            bytes.join(" "),
+            insn.mnemonic,
+            insn.operands
+        ));
+    }
+    if nops > 0 {
+        out.push_str(&format!("{:>16}  ({nops} nops)\n", "..."));
+    }
+    out
+}
+
+/// Formats the disassembly of the function in `report` before and after patching.
+pub fn listing(report: &PatchReport) -> crate::Result<String> {
+    let (before, after) = report.disassembly()?;
+    Ok(format!(
+        "--- original\n{}\n+++ patched\n{}",
+        format_instructions(&before),
+        format_instructions(&after)
+    ))
+}
+
+/// Formats everything `report` changed: a side-by-side hexdump of the
+/// function, followed by its disassembly before and after patching.
+pub fn diff(report: &PatchReport) -> crate::Result<String> {
+    Ok(format!(
+        "{}\n{}",
+        side_by_side(&report.original, &report.patched, report.func_start),
+        listing(report)?
+    ))
+}
@@ -1,4 +1,5 @@
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
 	#[error(transparent)]
 	IO(#[from] std::io::Error),
@@ -6,17 +7,111 @@ pub enum Error {
 	Goblin(#[from] goblin::error::Error),
 	#[error(transparent)]
 	Capstone(#[from] capstone::Error),
+	#[error(transparent)]
+	Json(#[from] serde_json::Error),
+	#[error(transparent)]
+	Pdb(#[from] pdb::Error),
 
 	#[error("file offset not found in any section")]
 	RvaNotFound,
-	#[error("could not find xref to data")]
-	XrefNotFound,
-	#[error("could not find section containing ref_va")]
-	SectionNotFound,
+	#[error("could not find the string {0:?} in the image")]
+	StringNotFound(String),
+	#[error("none of the known signatures matched{}", list_failures(.0))]
+	NoSignatureMatched(Vec<StrategyFailure>),
+	#[error("the best patch found only had {0:?} confidence")]
+	LowConfidence(crate::methods::Confidence),
+	#[error("the patched code has `{found}` at 0x{address:x}, so the bounds were likely wrong")]
+	UnexpectedInstruction { address: u64, found: String },
+	#[error("the executable is {0}, while only native x86 and x64 code can be patched")]
+	UnsupportedBinaryKind(String),
+	#[error("the executable looks to be packed with {0}, unpack it first (for UPX, run `upx -d <file>`) and try again")]
+	PackedBinary(&'static str),
+	#[error("invalid byte pattern: {0}")]
+	InvalidPattern(String),
+	#[error("could not find the byte pattern in the image")]
+	PatternNotFound,
+	#[error(
+		"could not find code referencing the string at 0x{data_va:x} in {section}, checking {occurrences} copies of it against {code_size} bytes of code"
+	)]
+	XrefNotFound {
+		data_va: u64,
+		section: String,
+		occurrences: usize,
+		code_size: usize,
+	},
+	#[error("0x{0:x} is only in the zero-filled part of its section past the raw data, so is not in the file")]
+	VirtualOnly(u64),
+	#[error("refusing to patch 0x{start:x}-0x{end:x}, as it runs into the overlay data at 0x{overlay:x}")]
+	OverlayWrite { start: usize, end: usize, overlay: usize },
+	#[error("gave up scanning for xrefs, as it {0}")]
+	ScanLimitExceeded(String),
+	#[error("{0} was built from a different executable")]
+	PdbMismatch(String),
+	#[error("could not find the symbol {0}")]
+	SymbolNotFound(&'static str),
+	#[error("could not find the section containing 0x{0:x}")]
+	SectionNotFound(u64),
 	#[error("function start out of range")]
 	InvalidFunctionStart,
 	#[error("empty function found")]
-	EmptyFunction
+	EmptyFunction,
+	#[error("function range 0x{start:x}-0x{end:x} is larger than the 0x{max:x} byte limit, starting with:\n{dump}")]
+	PatchTooLarge {
+		start: usize,
+		end: usize,
+		max: usize,
+		dump: String,
+	},
+	#[error("could not find a running process named {0}")]
+	ProcessNotFound(String),
+	#[error("could not find module {0} in the target process")]
+	ModuleNotFound(String),
+	#[error("the bytes at 0x{0:x} do not match the recorded patch")]
+	MetaMismatch(usize),
+	#[error("invalid patch metadata: {0}")]
+	InvalidMeta(String),
+	#[error("0x{0:x} is past the 16 MiB an IPS patch can address, use BPS instead")]
+	IpsOutOfRange(usize),
+	#[error("invalid patch file: {0}")]
+	InvalidPatchFile(String),
+	#[error("checksum mismatch, expected {expected:08x} but found {actual:08x}")]
+	ChecksumMismatch { expected: u32, actual: u32 },
+	#[error("{} is open in another program, such as Steam or the app itself, close it and try again", .0.display())]
+	FileInUse(std::path::PathBuf),
+	#[error("the patch is {found} bytes long, but replaces {expected} bytes")]
+	LengthChanged { expected: usize, found: usize },
+}
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+/// Why one strategy failed to locate the function, kept in [`Error::NoSignatureMatched`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StrategyFailure {
+	/// Name of the strategy
+	pub strategy: &'static str,
+	/// What the strategy was doing, either `locating` or `resolving bounds`
+	pub phase: &'static str,
+	pub error: Box<Error>,
+}
+
+impl StrategyFailure {
+	pub(crate) fn new(strategy: &'static str, phase: &'static str, error: Error) -> Self {
+		Self {
+			strategy,
+			phase,
+			error: Box::new(error),
+		}
+	}
+}
+
+impl std::fmt::Display for StrategyFailure {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} failed while {}: {}", self.strategy, self.phase, self.error)
+	}
 }
 
-pub type Result<T, E = Error> = core::result::Result<T, E>;
\ No newline at end of file
+/// One failure per line, for [`Error::NoSignatureMatched`].
+fn list_failures(failures: &[StrategyFailure]) -> String {
+	failures.iter().map(|x| format!("\n  {x}")).collect()
+}
\ No newline at end of file
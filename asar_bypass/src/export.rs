@@ -0,0 +1,316 @@
+//! Formats a [`PatchReport`] as a patch file, so the changes can be reviewed or
+//! shared without the patched executable.
+
+use std::fmt::Write;
+
+use crate::methods::PatchMethod;
+use crate::{Error, PatchReport, Result};
+
+/// The furthest offset an IPS record can address.
+const IPS_MAX_OFFSET: usize = 0xFF_FFFF;
+
+/// The longest IPS record.
+const IPS_MAX_RECORD: usize = 0xFFFF;
+
+/// An offset that reads as the `EOF` marker, so can't start a record.
+const IPS_EOF: usize = 0x45_4F46;
+
+/// Formats `report` as an x64dbg `.1337` patch for the module named `module`.
+///
+/// Each changed byte is listed as `{rva}:{old}->{new}`, which x64dbg applies
+/// through the "Import" button of its patches dialog.
+pub fn x64dbg(report: &PatchReport, module: &str) -> String {
+    let mut out = format!(">{module}\n");
+    for edit in report.edits() {
+        let changed = edit
+            .original
+            .iter()
+            .zip(&edit.patched)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new);
+        for (i, (old, new)) in changed {
+            let _ = writeln!(out, "{:016X}:{old:02X}->{new:02X}", edit.rva as usize + i);
+        }
+    }
+    out
+}
+
+/// Formats `report` as an IPS patch, with a record per run of changed bytes.
+///
+/// IPS can only address the first 16 MiB of a file, so [`Error::IpsOutOfRange`]
+/// is returned for functions past that, where [`bps`] should be used instead.
+pub fn ips(report: &PatchReport) -> Result<Vec<u8>> {
+    let mut out = b"PATCH".to_vec();
+    for (edit, (start, end)) in report
+        .edits()
+        .flat_map(|edit| changed_runs(edit).into_iter().map(move |run| (edit, run)))
+    {
+        let mut offset = edit.func_start + start;
+        let mut bytes = &edit.patched[start..end];
+        // records can't start at the marker, so begin one byte earlier, which
+        // is only known when it is still inside of the function
+        if offset == IPS_EOF {
+            let prev = start.checked_sub(1).ok_or(Error::IpsOutOfRange(offset))?;
+            offset -= 1;
+            bytes = &edit.patched[prev..end];
+        }
+
+        for chunk in bytes.chunks(IPS_MAX_RECORD) {
+            if offset + chunk.len() > IPS_MAX_OFFSET {
+                return Err(Error::IpsOutOfRange(offset));
+            }
+            out.extend_from_slice(&offset.to_be_bytes()[size_of::<usize>() - 3..]);
+            out.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            out.extend_from_slice(chunk);
+            offset += chunk.len();
+        }
+    }
+    out.extend_from_slice(b"EOF");
+    Ok(out)
+}
+
+/// Formats `report` as a BPS patch against `source`, the unpatched file.
+pub fn bps(source: &[u8], report: &PatchReport) -> Vec<u8> {
+    let mut out = b"BPS1".to_vec();
+    bps_number(&mut out, source.len() as u64);
+    bps_number(&mut out, source.len() as u64);
+    bps_number(&mut out, 0); // no metadata
+
+    // copy everything from the source, except the patched ranges which are written out
+    let mut edits: Vec<&PatchReport> = report.edits().collect();
+    edits.sort_by_key(|x| x.func_start);
+    let mut target = crc32fast::Hasher::new();
+    let mut copied = 0;
+    for edit in edits {
+        bps_source_read(&mut out, edit.func_start - copied);
+        target.update(&source[copied..edit.func_start]);
+        if !edit.patched.is_empty() {
+            bps_number(&mut out, ((edit.patched.len() as u64 - 1) << 2) | 1);
+            out.extend_from_slice(&edit.patched);
+        }
+        target.update(&edit.patched);
+        copied = edit.func_end;
+    }
+    bps_source_read(&mut out, source.len() - copied);
+    target.update(&source[copied..]);
+    out.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+    out.extend_from_slice(&target.finalize().to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(&out).to_le_bytes());
+    out
+}
+
+/// Returns the ranges, relative to the function, of every run of changed bytes.
+fn changed_runs(report: &PatchReport) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (i, (old, new)) in report.original.iter().zip(&report.patched).enumerate() {
+        if old == new {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((_, end)) if *end == i => *end += 1,
+            _ => runs.push((i, i + 1)),
+        }
+    }
+    runs
+}
+
+/// Writes a BPS variable-length number.
+fn bps_number(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | x);
+            return;
+        }
+        out.push(x);
+        value -= 1;
+    }
+}
+
+/// Writes a BPS `SourceRead` action, copying `len` bytes from the same offset in the source.
+fn bps_source_read(out: &mut Vec<u8>, len: usize) {
+    if len > 0 {
+        bps_number(out, (len as u64 - 1) << 2);
+    }
+}
+
+/// Formats `report` as a Ghidra Python script that labels the patched
+/// function and comments the reference and string it was located by.
+pub fn ghidra_script(report: &PatchReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# Annotates the integrity bypass applied by asar_bypass {}",
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(out, "# @category asar_bypass");
+    let _ = writeln!(out, "from ghidra.program.model.listing import CodeUnit");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "base = currentProgram.getImageBase()");
+    let _ = writeln!(out, "listing = currentProgram.getListing()");
+    let _ = writeln!(out);
+    for (rva, label, comment) in annotations(report) {
+        let _ = writeln!(out, "addr = base.add(0x{rva:x})");
+        if let Some(label) = label {
+            let _ = writeln!(out, "createLabel(addr, \"{label}\", True)");
+        }
+        let _ = writeln!(
+            out,
+            "listing.setComment(addr, CodeUnit.PLATE_COMMENT, \"{comment}\")"
+        );
+        let _ = writeln!(out, "createBookmark(addr, \"asar_bypass\", \"{comment}\")");
+    }
+    out
+}
+
+/// Formats `report` as an IDC script that names the patched function and
+/// comments the reference and string it was located by.
+pub fn idc_script(report: &PatchReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// Annotates the integrity bypass applied by asar_bypass {}",
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(out, "#include <idc.idc>");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "static main() {{");
+    let _ = writeln!(out, "    auto base = get_imagebase();");
+    for (rva, label, comment) in annotations(report) {
+        if let Some(label) = label {
+            let _ = writeln!(
+                out,
+                "    set_name(base + 0x{rva:x}, \"{label}\", SN_NOWARN);"
+            );
+        }
+        let _ = writeln!(out, "    set_cmt(base + 0x{rva:x}, \"{comment}\", 0);");
+    }
+    let _ = writeln!(out, "    jumpto(base + 0x{:x});", report.rva);
+    let _ = writeln!(out, "}}");
+    out
+}
+
+/// Describes what was done to the code at the start of `edit`.
+fn describe(edit: &PatchReport) -> String {
+    match edit.method {
+        PatchMethod::FunctionStub => format!(
+            "stubbed 0x{:x} bytes with {}",
+            edit.patched.len(),
+            stub(edit)
+        ),
+        PatchMethod::CallSite => "NOPed the only call to the function".to_string(),
+        PatchMethod::BranchFlip => {
+            "made the branch skipping the failure path unconditional".to_string()
+        }
+        PatchMethod::FuseFlip => {
+            "turned off the EnableEmbeddedAsarIntegrityValidation fuse".to_string()
+        }
+    }
+}
+
+/// The instructions a [`PatchMethod::FunctionStub`] starts with, up to its `ret`.
+fn stub(edit: &PatchReport) -> String {
+    let Ok((_, patched)) = edit.disassembly() else {
+        return "a stub".to_string();
+    };
+    let mut out = Vec::new();
+    for insn in patched {
+        out.push(
+            format!("{} {}", insn.mnemonic, insn.operands)
+                .trim_end()
+                .to_string(),
+        );
+        if insn.mnemonic == "ret" {
+            break;
+        }
+    }
+    out.join("; ")
+}
+
+/// Returns the RVA, label and comment of everything worth pointing out in `report`.
+fn annotations(report: &PatchReport) -> Vec<(u64, Option<&'static str>, String)> {
+    let label = (report.method == PatchMethod::FunctionStub).then_some("ValidateIntegrityOrDie");
+    let mut out = vec![(
+        report.rva as u64,
+        label,
+        format!(
+            "asar_bypass: {} (located by {})",
+            describe(report),
+            report.signature
+        ),
+    )];
+    // the fuse wire is found on its own, rather than through a reference
+    if report.method != PatchMethod::FuseFlip {
+        out.push((
+            report.reference_va - report.image_base,
+            None,
+            "asar_bypass: the reference the function was located by".to_string(),
+        ));
+    }
+    if let Some(data_va) = report.data_va {
+        out.push((
+            data_va - report.image_base,
+            None,
+            "asar_bypass: the string referenced from the function".to_string(),
+        ));
+    }
+    if let Some(dialog) = &report.dialog {
+        out.push((
+            dialog.rva as u64,
+            None,
+            format!(
+                "asar_bypass: silenced the failure dialog, {} (located by {})",
+                describe(dialog),
+                dialog.signature
+            ),
+        ));
+    }
+    for site in &report.sites {
+        out.push((
+            site.rva as u64,
+            None,
+            format!(
+                "asar_bypass: flipped the branch guarding the reference at {:#x}",
+                site.reference_va
+            ),
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PatchOptions;
+    use crate::fixture::PeBuilder;
+
+    #[test]
+    fn annotates_the_stub_written() {
+        let mut data = PeBuilder::new().build().data;
+        let report = crate::patch(&mut data).unwrap();
+        let (rva, label, comment) = &annotations(&report)[0];
+        assert_eq!(
+            (*rva, *label),
+            (report.rva as u64, Some("ValidateIntegrityOrDie"))
+        );
+        assert!(comment.contains("with xor eax, eax; ret ("), "{comment}");
+    }
+
+    #[test]
+    fn annotates_the_dialog_call() {
+        let mut data = PeBuilder::new().dialog().build().data;
+        let options = PatchOptions {
+            patch_dialog: true,
+            ..Default::default()
+        };
+        let report = crate::patch_with_options(&mut data, &options).unwrap();
+        let dialog = report.dialog.as_ref().unwrap();
+        let (_, label, comment) = annotations(&report)
+            .into_iter()
+            .find(|(rva, ..)| *rva == dialog.rva as u64)
+            .unwrap();
+        assert_eq!(label, None);
+        assert!(comment.contains("NOPed the only call"), "{comment}");
+    }
+}
@@ -0,0 +1,367 @@
+//! Patching executables on disk, which are memory-mapped so only the patched
+//! range is ever copied into memory.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use memmap2::Mmap;
+
+use crate::meta::PatchMeta;
+use crate::progress::Phase;
+use crate::{Error, PatchOptions, PatchReport, Result, methods, patched_region, trace};
+
+/// A file removed when dropped, unless it was renamed over its destination.
+pub(crate) struct TempFile(pub PathBuf);
+
+impl TempFile {
+    /// A temporary path in the same directory as `path`, so renaming over it is atomic.
+    pub fn beside(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Self(path.with_file_name(format!(".{name}.{}.tmp", std::process::id())))
+    }
+
+    /// Atomically replaces `dest` with the temporary file.
+    pub fn persist(mut self, dest: &Path) -> Result<()> {
+        std::fs::rename(&self.0, dest)?;
+        self.0 = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.0.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Replaces the contents of `path` with `data` through a temporary file beside
+/// it, so a crash part way through leaves the old contents rather than a
+/// truncated file.
+pub fn replace_file(path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let temp = TempFile::beside(path);
+    let mut file = File::create(&temp.0)?;
+    file.write_all(data)?;
+    if let Ok(meta) = std::fs::metadata(path) {
+        file.set_permissions(meta.permissions())?;
+    }
+    file.sync_all()?;
+    drop(file);
+    temp.persist(path)
+}
+
+/// Runs `edit` over a copy of `path`, then renames the copy over `path`, so a
+/// crash part way through leaves the file as it was.
+pub fn edit_file(path: impl AsRef<Path>, edit: impl FnOnce(&mut File) -> std::io::Result<()>) -> Result<()> {
+    let path = path.as_ref();
+    let temp = TempFile::beside(path);
+    std::fs::copy(path, &temp.0)?;
+    let mut file = OpenOptions::new().write(true).open(&temp.0)?;
+    edit(&mut file)?;
+    file.sync_all()?;
+    drop(file);
+    temp.persist(path)
+}
+
+/// Same as [`crate::patch`], but operates on a file.
+///
+/// The input is memory-mapped for the search phases, so only the patched
+/// function is ever copied into memory. The input is copied (along with its
+/// permissions) to a temporary file beside the output, the patched region is
+/// written on top of the copy, then the copy is renamed over the output, so a
+/// crash part way through never leaves a corrupt executable behind. Any overlay
+/// after the last section is copied across untouched.
+pub fn patch_file<P: AsRef<Path>>(input_path: P, output_path: Option<P>) -> Result<PatchReport> {
+    patch_file_with_options(input_path, output_path, &PatchOptions::default())
+}
+
+/// Same as [`patch_file_with_options`], but only reports what would change,
+/// leaving the file untouched.
+pub fn preview_file<P: AsRef<Path>>(input_path: P, options: &PatchOptions) -> Result<PatchReport> {
+    let input_path = input_path.as_ref();
+    let input_file = File::open(input_path)?;
+    // SAFETY: the mapping is only read from
+    let map = unsafe { Mmap::map(&input_file)? };
+    patched_region(&map, &options.with_pdb_beside(input_path))
+}
+
+/// Writes `report` into a copy of `input_path` next to `output_path`, backing
+/// the input up to `backup` first, then swaps the copy in.
+fn write_patched(
+    input_path: &Path,
+    output_path: &Path,
+    report: &PatchReport,
+    backup: Option<&Path>,
+    options: &PatchOptions,
+) -> Result<()> {
+    options.progress().phase(Phase::Writing);
+    let _span = trace::phase("apply", report.signature);
+    if let Some(backup) = backup {
+        let mut backup_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(backup)?;
+        std::io::copy(&mut File::open(input_path)?, &mut backup_file)?;
+    }
+    let temp = TempFile::beside(output_path);
+    std::fs::copy(input_path, &temp.0)?;
+    let mut temp_file = OpenOptions::new().write(true).open(&temp.0)?;
+    for edit in report.edits() {
+        temp_file.seek(SeekFrom::Start(edit.func_start as u64))?;
+        temp_file.write_all(&edit.patched)?;
+    }
+    temp_file.sync_all()?;
+    drop(temp_file);
+
+    // Read the ranges back to check they decode to the patch, before anything is replaced
+    if options.verify {
+        let mut temp_file = File::open(&temp.0)?;
+        for edit in report.edits() {
+            let mut written = vec![0; edit.patched.len()];
+            temp_file.seek(SeekFrom::Start(edit.func_start as u64))?;
+            temp_file.read_exact(&mut written)?;
+            methods::verify(edit, &written, options.fill)?;
+        }
+    }
+    temp.persist(output_path)?;
+    if options.write_meta {
+        PatchMeta::from(report).save(&PatchMeta::sidecar_path(output_path))?;
+    }
+    Ok(())
+}
+
+/// Extensions of the modules searched by [`PatchOptions::scan_siblings`].
+const SIBLING_EXTENSIONS: &[&str] = &["dll", "node"];
+
+/// Searches the other modules beside `input_path` for the integrity check,
+/// patching the first one it's found in into the directory of `output_path`.
+pub(crate) fn patch_sibling(
+    input_path: &Path,
+    output_path: &Path,
+    options: &PatchOptions,
+) -> Result<Option<PatchReport>> {
+    let dir = input_path
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut siblings: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|x| x.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|x| x.to_str())
+                .is_some_and(|ext| {
+                    SIBLING_EXTENSIONS
+                        .iter()
+                        .any(|x| x.eq_ignore_ascii_case(ext))
+                })
+        })
+        .collect();
+    siblings.sort();
+
+    for sibling in siblings {
+        let file = File::open(&sibling)?;
+        // SAFETY: the mapping is only read from, and is dropped before anything is written back to disk
+        let map = unsafe { Mmap::map(&file)? };
+        let found = patched_region(&map, &options.with_pdb_beside(&sibling));
+        drop(map);
+        let mut report = match found {
+            Ok(report) => report,
+            Err(err) => {
+                debug!("{} did not match: {err}", sibling.display());
+                continue;
+            }
+        };
+
+        let name = sibling.file_name().unwrap_or_default();
+        let dest = output_path.with_file_name(name);
+        let backup = options.backup.as_ref().map(|_| {
+            let mut backup = sibling.clone().into_os_string();
+            backup.push(".bak");
+            PathBuf::from(backup)
+        });
+        write_patched(&sibling, &dest, &report, backup.as_deref(), options)?;
+        info!(
+            "Patched ValidateIntegrityOrDie inside of {} at file 0x{:x}-0x{:x}",
+            dest.display(),
+            report.func_start,
+            report.func_end
+        );
+        report.module = Some(dest);
+        return Ok(Some(report));
+    }
+    Ok(None)
+}
+
+/// Same as [`patch_file`], but with the given `options`.
+pub fn patch_file_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_path: Option<P>,
+    options: &PatchOptions,
+) -> Result<PatchReport> {
+    let input_path = input_path.as_ref();
+    let output_path = output_path.as_ref().map_or(input_path, |x| x.as_ref());
+    let options = &options.with_pdb_beside(input_path);
+
+    // Search the mapped file, only materialising the function we patch
+    let input_file = File::open(input_path)?;
+    // SAFETY: the mapping is only read from, and is dropped before anything is written back to disk
+    let map = unsafe { Mmap::map(&input_file)? };
+    let found = patched_region(&map, options);
+    drop(map);
+    drop(input_file);
+
+    let report = match found {
+        Err(err @ (Error::NoSignatureMatched(_) | Error::LowConfidence(_)))
+            if options.scan_siblings =>
+        {
+            return patch_sibling(input_path, output_path, options)?.ok_or(err);
+        }
+        found => found?,
+    };
+    write_patched(
+        input_path,
+        output_path,
+        &report,
+        options.backup.as_deref(),
+        options,
+    )?;
+
+    info!(
+        "Patched ValidateIntegrityOrDie at file 0x{:x}-0x{:x} using signature {}",
+        report.func_start, report.func_end, report.signature
+    );
+
+    Ok(report)
+}
+
+/// Same as [`patch_file_with_options`], but writes only the changed ranges
+/// straight into `path`, holding an exclusive lock on it throughout.
+///
+/// Fails fast with [`Error::FileInUse`] when another program (such as Steam,
+/// or the app itself) has the file open, rather than patching a file that is
+/// about to be replaced or is mapped into a running process. The original bytes
+/// are written back if the result doesn't verify. [`PatchOptions::scan_siblings`]
+/// is ignored, as only `path` is locked.
+pub fn patch_file_in_place<P: AsRef<Path>>(path: P, options: &PatchOptions) -> Result<PatchReport> {
+    let path = path.as_ref();
+    let options = &options.with_pdb_beside(path);
+    let mut file = open_locked(path)?;
+
+    // SAFETY: the file is locked, and the mapping is dropped before anything is written
+    let map = unsafe { Mmap::map(&file)? };
+    let found = patched_region(&map, options);
+    drop(map);
+    let report = found?;
+
+    options.progress().phase(Phase::Writing);
+    let _span = trace::phase("apply", report.signature);
+    // copied through the locked handle, as the file can't be opened again
+    if let Some(backup) = &options.backup {
+        let mut backup_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(backup)?;
+        file.seek(SeekFrom::Start(0))?;
+        std::io::copy(&mut file, &mut backup_file)?;
+    }
+
+    for edit in report.edits() {
+        write_at(&file, edit.func_start, &edit.patched)?;
+    }
+    file.sync_all()?;
+    let checked = report.edits().try_for_each(|edit| {
+        let mut written = vec![0; edit.patched.len()];
+        read_at(&file, edit.func_start, &mut written)?;
+        if options.verify {
+            methods::verify(edit, &written, options.fill)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = checked {
+        for edit in report.edits() {
+            write_at(&file, edit.func_start, &edit.original)?;
+        }
+        file.sync_all()?;
+        return Err(err);
+    }
+    if options.write_meta {
+        PatchMeta::from(&report).save(&PatchMeta::sidecar_path(path))?;
+    }
+
+    info!(
+        "Patched ValidateIntegrityOrDie in place at file 0x{:x}-0x{:x} using signature {}",
+        report.func_start, report.func_end, report.signature
+    );
+    Ok(report)
+}
+
+/// Opens `path` for reading and writing, with an exclusive lock that is
+/// released when the file is closed.
+fn open_locked(path: &Path) -> Result<File> {
+    let mut open = OpenOptions::new();
+    open.read(true).write(true);
+    // Windows only refuses while someone else has the file open if nothing is shared
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::share_mode(&mut open, 0);
+
+    let in_use = || Error::FileInUse(path.to_path_buf());
+    let file = match open.open(path) {
+        Ok(file) => file,
+        Err(err) if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION) && cfg!(windows) => {
+            return Err(in_use());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(in_use()),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+/// `ERROR_SHARING_VIOLATION`, returned by Windows when another process has the file open.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+fn write_at(file: &File, offset: usize, buf: &[u8]) -> Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset as u64)?;
+    #[cfg(windows)]
+    {
+        let mut written = 0;
+        while written < buf.len() {
+            match std::os::windows::fs::FileExt::seek_write(
+                file,
+                &buf[written..],
+                (offset + written) as u64,
+            )? {
+                0 => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero))?,
+                n => written += n,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_at(file: &File, offset: usize, buf: &mut [u8]) -> Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset as u64)?;
+    #[cfg(windows)]
+    {
+        let mut read = 0;
+        while read < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(
+                file,
+                &mut buf[read..],
+                (offset + read) as u64,
+            )? {
+                0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?,
+                n => read += n,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! [`patch_file_with_options`](crate::patch_file_with_options) for async
+//! runtimes, so copying large executables doesn't block the caller's executor.
+
+use std::path::Path;
+
+use log::info;
+use memmap2::Mmap;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::file::{TempFile, patch_sibling};
+use crate::meta::PatchMeta;
+use crate::progress::Phase;
+use crate::{Error, PatchOptions, PatchReport, Result, methods, patched_region};
+
+/// Runs `f` on tokio's blocking pool, as the search phases are CPU bound.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// Same as [`crate::patch_file_with_options`], but copies and writes the file
+/// with tokio, running the search on the blocking pool.
+pub async fn patch_file_async(
+    input_path: impl AsRef<Path>,
+    output_path: Option<impl AsRef<Path>>,
+    options: &PatchOptions,
+) -> Result<PatchReport> {
+    let input_path = input_path.as_ref().to_path_buf();
+    let output_path = output_path.map_or_else(|| input_path.clone(), |x| x.as_ref().to_path_buf());
+    let options = options.with_pdb_beside(&input_path);
+
+    let found = {
+        let (input_path, options) = (input_path.clone(), options.clone());
+        blocking(move || {
+            let input_file = std::fs::File::open(&input_path)?;
+            // SAFETY: the mapping is only read from, and is dropped before anything is written back to disk
+            let map = unsafe { Mmap::map(&input_file)? };
+            Ok(patched_region(&map, &options))
+        })
+        .await?
+    };
+    let report = match found {
+        Err(err @ (Error::NoSignatureMatched(_) | Error::LowConfidence(_)))
+            if options.scan_siblings =>
+        {
+            return blocking(move || {
+                patch_sibling(&input_path, &output_path, &options)?.ok_or(err)
+            })
+            .await;
+        }
+        found => found?,
+    };
+
+    options.progress().phase(Phase::Writing);
+    if let Some(backup) = &options.backup {
+        let mut backup_file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(backup)
+            .await?;
+        tokio::io::copy(
+            &mut tokio::fs::File::open(&input_path).await?,
+            &mut backup_file,
+        )
+        .await?;
+    }
+    let temp = TempFile::beside(&output_path);
+    tokio::fs::copy(&input_path, &temp.0).await?;
+    let mut temp_file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&temp.0)
+        .await?;
+    for edit in report.edits() {
+        temp_file
+            .seek(std::io::SeekFrom::Start(edit.func_start as u64))
+            .await?;
+        temp_file.write_all(&edit.patched).await?;
+    }
+    temp_file.sync_all().await?;
+
+    // Read the ranges back to check they decode to the patch, before anything is replaced
+    if options.verify {
+        for edit in report.edits() {
+            let mut written = vec![0; edit.patched.len()];
+            temp_file
+                .seek(std::io::SeekFrom::Start(edit.func_start as u64))
+                .await?;
+            temp_file.read_exact(&mut written).await?;
+            methods::verify(edit, &written, options.fill)?;
+        }
+    }
+    drop(temp_file);
+
+    // Renaming and the metadata are tiny, so aren't worth a trip to the blocking pool
+    temp.persist(&output_path)?;
+    if options.write_meta {
+        PatchMeta::from(&report).save(&PatchMeta::sidecar_path(&output_path))?;
+    }
+
+    info!(
+        "Patched ValidateIntegrityOrDie at file 0x{:x}-0x{:x} using signature {}",
+        report.func_start, report.func_end, report.signature
+    );
+    Ok(report)
+}
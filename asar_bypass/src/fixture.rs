@@ -0,0 +1,564 @@
+//! Builds minimal PE images containing the string, a reference to it and a
+//! tiny function around that reference, so the search and patch logic can be
+//! exercised deterministically without shipping real executables.
+//!
+//! ```
+//! let fixture = asar_bypass::fixture::PeBuilder::new().build();
+//! let mut data = fixture.data.clone();
+//! let report = asar_bypass::patch(&mut data)?;
+//! assert_eq!((report.func_start, report.func_end), (fixture.func_start, fixture.func_end));
+//! # Ok::<(), asar_bypass::Error>(())
+//! ```
+
+use crate::signatures::{DIALOG_SIGNATURES, SIGNATURES};
+
+/// The `int3` padding placed before the function, as compilers do.
+const PADDING: usize = 0x10;
+
+/// `IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ`
+const TEXT_CHARACTERISTICS: u32 = 0x6000_0020;
+/// `IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ`
+const RDATA_CHARACTERISTICS: u32 = 0x4000_0040;
+
+/// A synthetic PE image, alongside where everything inside of it is.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Fixture {
+    /// The whole image, as it would be on disk
+    pub data: Vec<u8>,
+    /// File offset of the start of the function
+    pub func_start: usize,
+    /// File offset just past the function's `ret`
+    pub func_end: usize,
+    /// VA of the instruction referencing the string
+    pub reference_va: u64,
+    /// VA of the string
+    pub string_va: u64,
+    /// File offset of the branch skipping over the second reference, with [`PeBuilder::guarded_site`]
+    pub guard: Option<usize>,
+    /// File offset of the only call to the dialog routine, with [`PeBuilder::dialog`]
+    pub dialog_call: Option<usize>,
+}
+
+/// Configures a [`Fixture`].
+#[derive(Debug, Clone)]
+pub struct PeBuilder {
+    is_64: bool,
+    image_base: u64,
+    timestamp: u32,
+    needle: String,
+    wide: bool,
+    body: usize,
+    guarded_site: bool,
+    jecxz: bool,
+    dialog: bool,
+    file_alignment: usize,
+    section_alignment: usize,
+}
+
+impl Default for PeBuilder {
+    fn default() -> Self {
+        Self {
+            is_64: true,
+            image_base: 0x1_4000_0000,
+            timestamp: 0,
+            needle: SIGNATURES[0].needle.to_string(),
+            wide: false,
+            body: 0,
+            guarded_site: false,
+            jecxz: false,
+            dialog: false,
+            file_alignment: 0x200,
+            section_alignment: 0x1000,
+        }
+    }
+}
+
+impl PeBuilder {
+    /// A 64-bit image referencing the string of the first built-in signature.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a 32-bit image instead, moving the image base to `0x400000`.
+    pub fn x86(mut self) -> Self {
+        self.is_64 = false;
+        self.image_base = 0x40_0000;
+        self
+    }
+
+    pub fn image_base(mut self, image_base: u64) -> Self {
+        self.image_base = image_base;
+        self
+    }
+
+    /// The `TimeDateStamp` of the COFF header.
+    pub fn timestamp(mut self, timestamp: u32) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// The string referenced from inside of the function.
+    pub fn string(mut self, needle: &str) -> Self {
+        self.needle = needle.to_string();
+        self
+    }
+
+    /// Stores the string as UTF-16LE.
+    pub fn wide(mut self) -> Self {
+        self.wide = true;
+        self
+    }
+
+    /// Pads the body of the function with this many NOPs, to exercise large functions.
+    pub fn body_size(mut self, size: usize) -> Self {
+        self.body = size;
+        self
+    }
+
+    /// Adds a second function referencing the string, behind a `jz` skipping over the reference.
+    pub fn guarded_site(mut self) -> Self {
+        self.guarded_site = true;
+        self
+    }
+
+    /// Follows the `jz` of [`Self::guarded_site`] with a `jecxz` over the
+    /// reference too, which has no unconditional form to be flipped to.
+    pub fn jecxz(mut self) -> Self {
+        self.jecxz = true;
+        self
+    }
+
+    /// Adds the string of the first dialog signature, referenced from a
+    /// function called from exactly one place.
+    pub fn dialog(mut self) -> Self {
+        self.dialog = true;
+        self
+    }
+
+    /// The `FileAlignment` and `SectionAlignment`, `0x200` and `0x1000` by default.
+    ///
+    /// With a section alignment below `0x1000` the sections are laid out
+    /// exactly as they are mapped, so both should be the same.
+    pub fn alignment(mut self, file_alignment: usize, section_alignment: usize) -> Self {
+        self.file_alignment = file_alignment;
+        self.section_alignment = section_alignment;
+        self
+    }
+
+    pub fn build(&self) -> Fixture {
+        let headers_raw = align(self.headers_len(), self.file_alignment);
+        let text = self.text();
+        let text_raw = align(text.code.len(), self.file_alignment);
+        let text_rva = align(headers_raw, self.section_alignment) as u32;
+        let rdata_rva = text_rva + align(text_raw, self.section_alignment) as u32;
+        let string_va = self.image_base + rdata_rva as u64;
+
+        let mut rdata = Vec::new();
+        let mut strings = Vec::new();
+        for needle in std::iter::once(self.needle.as_str())
+            .chain(self.dialog.then_some(DIALOG_SIGNATURES[0].needle))
+        {
+            strings.push(string_va + rdata.len() as u64);
+            match self.wide {
+                true => rdata.extend(needle.encode_utf16().flat_map(u16::to_le_bytes)),
+                false => rdata.extend(needle.as_bytes()),
+            }
+            rdata.extend([0, 0]);
+        }
+
+        // the references can only be filled in once the strings' addresses are known
+        let mut code = text.code;
+        let va_of = |offset: usize| self.image_base + text_rva as u64 + offset as u64;
+        for &(reference, string) in &text.references {
+            let operand = match self.is_64 {
+                // RIP relative, from the end of `lea rcx, [rip + disp32]`
+                true => (strings[string] as i64 - (va_of(reference) as i64 + 7)) as i32 as u32,
+                false => strings[string] as u32,
+            };
+            let operand_at = reference + if self.is_64 { 3 } else { 2 };
+            code[operand_at..operand_at + 4].copy_from_slice(&operand.to_le_bytes());
+        }
+
+        let rdata_raw = align(rdata.len(), self.file_alignment);
+        let image_size = rdata_rva + align(rdata_raw, self.section_alignment) as u32;
+
+        let layout = Layout {
+            headers_raw,
+            text_rva,
+            text_raw,
+            rdata_rva,
+            rdata_raw,
+            image_size,
+        };
+        let mut data = self.headers(&layout);
+        data.resize(headers_raw, 0);
+        data.extend(&code);
+        data.resize(headers_raw + text_raw, 0xCC);
+        data.extend(&rdata);
+        data.resize(headers_raw + text_raw + rdata_raw, 0);
+
+        Fixture {
+            data,
+            func_start: headers_raw + text.func_start,
+            func_end: headers_raw + text.func_end,
+            reference_va: va_of(text.references[0].0),
+            string_va,
+            guard: text.guard.map(|x| headers_raw + x),
+            dialog_call: text.dialog_call.map(|x| headers_raw + x),
+        }
+    }
+
+    /// Lays out the `.text` section, with the references' operands left zeroed.
+    fn text(&self) -> Text {
+        let mut code = vec![0xCC; PADDING];
+        let func_start = code.len();
+        let reference = self.function(&mut code, self.body, false);
+        let func_end = code.len();
+        let mut references = vec![(reference, 0)];
+
+        let guard = self.guarded_site.then(|| {
+            code.extend([0xCC; PADDING]);
+            let reference = self.function(&mut code, 0, true);
+            references.push((reference, 0));
+            // the `jz` is directly before the reference, or the `jecxz`
+            reference - if self.jecxz { 4 } else { 2 }
+        });
+
+        let dialog_call = self.dialog.then(|| {
+            code.extend([0xCC; PADDING]);
+            let routine = code.len();
+            let reference = self.function(&mut code, 0, false);
+            references.push((reference, 1));
+
+            // sub rsp, 0x28; call routine; add rsp, 0x28; ret
+            code.extend([0xCC; PADDING]);
+            let (alloc, free): (&[u8], &[u8]) = match self.is_64 {
+                true => (&[0x48, 0x83, 0xEC, 0x28], &[0x48, 0x83, 0xC4, 0x28]),
+                false => (&[0x83, 0xEC, 0x28], &[0x83, 0xC4, 0x28]),
+            };
+            code.extend(alloc);
+            let call = code.len();
+            code.push(0xE8);
+            code.extend((routine as i32 - (call as i32 + 5)).to_le_bytes());
+            code.extend(free);
+            code.push(0xC3);
+            call
+        });
+        code.extend([0xCC; PADDING]);
+
+        Text {
+            code,
+            func_start,
+            func_end,
+            references,
+            guard,
+            dialog_call,
+        }
+    }
+
+    /// Appends a function referencing a string, padded with `body` NOPs and
+    /// optionally skipping the reference with a `jz`, returning where the reference is.
+    fn function(&self, code: &mut Vec<u8>, body: usize, guarded: bool) -> usize {
+        // push rbx; push rsi; sub rsp, 0x28
+        code.extend([0x53, 0x56]);
+        code.extend(if self.is_64 {
+            &[0x48, 0x83, 0xEC, 0x28][..]
+        } else {
+            &[0x83, 0xEC, 0x28]
+        });
+
+        let lea: &[u8] = if self.is_64 {
+            &[0x48, 0x8D, 0x0D]
+        } else {
+            &[0x8D, 0x0D]
+        };
+        if guarded {
+            // test ecx, ecx; jz past the reference (and jecxz past it too)
+            let jecxz = if self.jecxz { 2 } else { 0 };
+            code.extend([0x85, 0xC9, 0x74, lea.len() as u8 + 4 + jecxz]);
+            if self.jecxz {
+                code.extend([0xE3, lea.len() as u8 + 4]);
+            }
+        }
+
+        // lea rcx, [rip + disp32], or lea ecx, [abs32] for 32-bit
+        let reference = code.len();
+        code.extend(lea);
+        code.extend([0; 4]);
+
+        code.extend(std::iter::repeat_n(0x90, body));
+
+        // add rsp, 0x28; pop rsi; pop rbx; ret
+        code.extend(if self.is_64 {
+            &[0x48, 0x83, 0xC4, 0x28][..]
+        } else {
+            &[0x83, 0xC4, 0x28]
+        });
+        code.extend([0x5E, 0x5B, 0xC3]);
+        reference
+    }
+
+    /// Bytes taken up by [`Self::headers`], before they are aligned.
+    fn headers_len(&self) -> usize {
+        let optional_size = if self.is_64 { 240 } else { 224 };
+        0x40 + 4 + 20 + optional_size + 2 * 40
+    }
+
+    /// The DOS, COFF and optional headers, then the section table.
+    fn headers(&self, layout: &Layout) -> Vec<u8> {
+        let Layout {
+            headers_raw,
+            text_rva,
+            text_raw,
+            rdata_rva,
+            rdata_raw,
+            image_size,
+        } = *layout;
+        let mut out = vec![0; 0x40];
+        out[..2].copy_from_slice(b"MZ");
+        out[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        out.extend(b"PE\0\0");
+
+        // COFF header
+        let optional_size: u16 = if self.is_64 { 240 } else { 224 };
+        out.extend(if self.is_64 { 0x8664u16 } else { 0x14C }.to_le_bytes());
+        out.extend(2u16.to_le_bytes());
+        out.extend(self.timestamp.to_le_bytes());
+        out.extend([0; 8]);
+        out.extend(optional_size.to_le_bytes());
+        // IMAGE_FILE_EXECUTABLE_IMAGE, plus LARGE_ADDRESS_AWARE or 32BIT_MACHINE
+        out.extend(if self.is_64 { 0x22u16 } else { 0x102 }.to_le_bytes());
+
+        // Optional header
+        out.extend(if self.is_64 { 0x20Bu16 } else { 0x10B }.to_le_bytes());
+        out.extend([14, 0]);
+        out.extend((text_raw as u32).to_le_bytes());
+        out.extend((rdata_raw as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend((text_rva + PADDING as u32).to_le_bytes());
+        out.extend(text_rva.to_le_bytes());
+        if self.is_64 {
+            out.extend(self.image_base.to_le_bytes());
+        } else {
+            out.extend(rdata_rva.to_le_bytes());
+            out.extend((self.image_base as u32).to_le_bytes());
+        }
+        out.extend((self.section_alignment as u32).to_le_bytes());
+        out.extend((self.file_alignment as u32).to_le_bytes());
+        out.extend([6, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0]);
+        out.extend(0u32.to_le_bytes());
+        out.extend(image_size.to_le_bytes());
+        out.extend((headers_raw as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        // IMAGE_SUBSYSTEM_WINDOWS_CUI, with no DLL characteristics
+        out.extend(3u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        for size in [0x10_0000u64, 0x1000, 0x10_0000, 0x1000] {
+            match self.is_64 {
+                true => out.extend(size.to_le_bytes()),
+                false => out.extend((size as u32).to_le_bytes()),
+            }
+        }
+        out.extend(0u32.to_le_bytes());
+        out.extend(16u32.to_le_bytes());
+        out.extend([0; 16 * 8]);
+
+        // Section table
+        let sections = [
+            (
+                b".text\0\0\0",
+                text_rva,
+                text_raw,
+                headers_raw,
+                TEXT_CHARACTERISTICS,
+            ),
+            (
+                b".rdata\0\0",
+                rdata_rva,
+                rdata_raw,
+                headers_raw + text_raw,
+                RDATA_CHARACTERISTICS,
+            ),
+        ];
+        for (name, rva, size, offset, characteristics) in sections {
+            out.extend(name);
+            out.extend((size as u32).to_le_bytes());
+            out.extend(rva.to_le_bytes());
+            out.extend((size as u32).to_le_bytes());
+            out.extend((offset as u32).to_le_bytes());
+            out.extend([0; 12]);
+            out.extend(characteristics.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Where the headers and sections are, in the file and in memory.
+#[derive(Clone, Copy)]
+struct Layout {
+    headers_raw: usize,
+    text_rva: u32,
+    text_raw: usize,
+    rdata_rva: u32,
+    rdata_raw: usize,
+    image_size: u32,
+}
+
+/// The laid out `.text` section.
+struct Text {
+    code: Vec<u8>,
+    /// Offsets inside of `code`
+    func_start: usize,
+    func_end: usize,
+    /// Each reference, by the index of the string it references
+    references: Vec<(usize, usize)>,
+    guard: Option<usize>,
+    dialog_call: Option<usize>,
+}
+
+fn align(size: usize, alignment: usize) -> usize {
+    let alignment = alignment.max(1);
+    size.div_ceil(alignment).max(1) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::{Confidence, PatchMethod};
+    use crate::{Error, PatchOptions, patch, patch_with_options, preview};
+
+    /// `xor eax, eax; ret`
+    const RETURN_ZERO: [u8; 3] = [0x31, 0xC0, 0xC3];
+
+    fn assert_stubbed(fixture: &Fixture) {
+        let mut data = fixture.data.clone();
+        let report = patch(&mut data).unwrap();
+        assert_eq!(report.method, PatchMethod::FunctionStub);
+        assert_eq!(
+            (report.func_start, report.func_end),
+            (fixture.func_start, fixture.func_end)
+        );
+        assert_eq!(report.reference_va, fixture.reference_va);
+        assert_eq!(report.data_va, Some(fixture.string_va));
+        assert_eq!(
+            data[fixture.func_start..fixture.func_start + 3],
+            RETURN_ZERO
+        );
+    }
+
+    #[test]
+    fn stubs_x64() {
+        assert_stubbed(&PeBuilder::new().build());
+    }
+
+    #[test]
+    fn stubs_x86() {
+        assert_stubbed(&PeBuilder::new().x86().build());
+    }
+
+    #[test]
+    fn finds_wide_string() {
+        assert_stubbed(&PeBuilder::new().wide().build());
+        assert_stubbed(&PeBuilder::new().x86().wide().build());
+    }
+
+    #[test]
+    fn previews_without_writing() {
+        let fixture = PeBuilder::new().build();
+        let report = preview(&fixture.data).unwrap();
+        let mut data = fixture.data.clone();
+        assert_eq!(report.patched, patch(&mut data).unwrap().patched);
+        assert_eq!(data[report.func_start..report.func_end], report.patched);
+    }
+
+    #[test]
+    fn refuses_function_larger_than_max() {
+        let fixture = PeBuilder::new().body_size(0x200).build();
+        let mut data = fixture.data.clone();
+        let options = PatchOptions {
+            max_patch_size: 0x100,
+            ..Default::default()
+        };
+        let err = patch_with_options(&mut data, &options).unwrap_err();
+        assert!(
+            matches!(err, Error::PatchTooLarge { max: 0x100, .. }),
+            "{err:?}"
+        );
+        assert_eq!(data, fixture.data);
+    }
+
+    #[test]
+    fn shrunk_function_is_low_confidence() {
+        let fixture = PeBuilder::new().body_size(0x200).build();
+        let mut data = fixture.data.clone();
+        let options = PatchOptions {
+            max_patch_size: 0x100,
+            allow_heuristic_shrink: true,
+            min_confidence: Confidence::Medium,
+            ..Default::default()
+        };
+        let err = patch_with_options(&mut data, &options).unwrap_err();
+        assert!(
+            matches!(err, Error::LowConfidence(Confidence::Low)),
+            "{err:?}"
+        );
+        assert_eq!(data, fixture.data);
+    }
+
+    #[test]
+    fn nops_only_call_to_dialog() {
+        for builder in [PeBuilder::new(), PeBuilder::new().x86()] {
+            let fixture = builder.dialog().build();
+            let mut data = fixture.data.clone();
+            let options = PatchOptions {
+                patch_dialog: true,
+                ..Default::default()
+            };
+            let report = patch_with_options(&mut data, &options).unwrap();
+            let dialog = report.dialog.expect("the dialog routine is found");
+            let call = fixture.dialog_call.unwrap();
+            assert_eq!(dialog.method, PatchMethod::CallSite);
+            assert_eq!((dialog.func_start, dialog.func_end), (call, call + 5));
+            assert!(data[call..call + 5].iter().all(|x| *x != 0xE8));
+        }
+    }
+
+    #[test]
+    fn flips_integrity_fuse() {
+        let mut data = vec![0xCC; 0x40];
+        data.extend_from_slice(b"dL7pKGdnNz796PbbjQWNKmHXBZaB9tsX\x01\x0501011");
+        let edit = crate::methods::fuse_flip(&data, 4).expect("the fuse is on");
+        assert_eq!(edit.method, PatchMethod::FuseFlip);
+        assert_eq!(edit.start, data.len() - 1);
+        assert!(crate::methods::fuse_flip(&data, 2).is_none());
+    }
+
+    #[test]
+    fn flips_branch_guarding_other_site() {
+        // the `jz` is flipped even when a `jecxz`, which can't be, comes after it
+        let builders = [
+            PeBuilder::new(),
+            PeBuilder::new().x86(),
+            PeBuilder::new().jecxz(),
+            PeBuilder::new().x86().jecxz(),
+        ];
+        for builder in builders {
+            let fixture = builder.guarded_site().build();
+            let mut data = fixture.data.clone();
+            let options = PatchOptions {
+                multi_site: true,
+                ..Default::default()
+            };
+            let report = patch_with_options(&mut data, &options).unwrap();
+            let guard = fixture.guard.unwrap();
+            let [site] = &report.sites[..] else {
+                panic!("expected a single site, found {:?}", report.sites);
+            };
+            assert_eq!(site.method, PatchMethod::BranchFlip);
+            assert_eq!(site.func_start, guard);
+            // jz becomes jmp, keeping where it jumps to
+            assert_eq!(data[guard..guard + 2], [0xEB, fixture.data[guard + 1]]);
+        }
+    }
+}
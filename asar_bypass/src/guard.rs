@@ -0,0 +1,99 @@
+//! Reads the Control Flow Guard function table, to tell whether the stubbed
+//! function can be reached through an indirect call.
+//!
+//! Nothing here is changed by patching: the stub is written at the start of the
+//! function, so an indirect call the loader allows still lands on the stub.
+
+use goblin::pe::PE;
+
+use crate::rva_to_file_offset;
+
+/// `IMAGE_GUARD_CF_INSTRUMENTED`, set when the image was built with Control Flow Guard.
+const IMAGE_GUARD_CF_INSTRUMENTED: u32 = 0x100;
+
+/// The bits of `GuardFlags` holding how many bytes of metadata follow each table entry.
+const IMAGE_GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT: u32 = 28;
+
+/// `IMAGE_GUARD_FLAG_FID_SUPPRESSED`, set in an entry's metadata when the loader should refuse it.
+const IMAGE_GUARD_FLAG_FID_SUPPRESSED: u8 = 0x1;
+
+/// Whether Control Flow Guard lets the patched function be called indirectly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CfgStatus {
+    /// The image wasn't built with Control Flow Guard, or its table couldn't be read
+    #[default]
+    Disabled,
+    /// The function isn't in the function table, so can only be called directly
+    NotATarget,
+    /// The function is a valid indirect call target
+    ValidTarget,
+    /// The function is in the table, but marked for the loader to refuse
+    Suppressed,
+}
+
+/// Where `GuardCFFunctionTable`, `GuardCFFunctionCount` and `GuardFlags` are
+/// inside of the load config directory, for 32-bit and 64-bit images.
+const FIELDS_32: (usize, usize, usize) = (0x50, 0x54, 0x58);
+const FIELDS_64: (usize, usize, usize) = (0x80, 0x88, 0x90);
+
+/// Looks up the function starting at `rva` in the Control Flow Guard function table of `pe`.
+pub fn cfg_status(pe: &PE, data: &[u8], rva: u32) -> CfgStatus {
+    let Some(mut table) = function_table(pe, data) else {
+        return CfgStatus::Disabled;
+    };
+    table
+        .find(|(entry, _)| *entry == rva)
+        .map_or(CfgStatus::NotATarget, |(_, metadata)| match metadata {
+            Some(flags) if flags & IMAGE_GUARD_FLAG_FID_SUPPRESSED != 0 => CfgStatus::Suppressed,
+            _ => CfgStatus::ValidTarget,
+        })
+}
+
+/// Every entry of the function table, with the first byte of its metadata if it has any.
+fn function_table<'a>(
+    pe: &PE,
+    data: &'a [u8],
+) -> Option<impl Iterator<Item = (u32, Option<u8>)> + 'a> {
+    let directory = pe
+        .header
+        .optional_header?
+        .data_directories
+        .get_load_config_table()
+        .copied()?;
+    let config = rva_to_file_offset(pe, directory.virtual_address)?;
+    let read_u32 = |off: usize| Some(u32::from_le_bytes(data.get(off..off + 4)?.try_into().ok()?));
+    let read_u64 = |off: usize| Some(u64::from_le_bytes(data.get(off..off + 8)?.try_into().ok()?));
+
+    // older images have a shorter directory, without the Control Flow Guard fields
+    let size = read_u32(config)? as usize;
+    let (table_field, count_field, flags_field) = if pe.is_64 { FIELDS_64 } else { FIELDS_32 };
+    if size < flags_field + 4 {
+        return None;
+    }
+    let flags = read_u32(config + flags_field)?;
+    if flags & IMAGE_GUARD_CF_INSTRUMENTED == 0 {
+        return None;
+    }
+    let (table_va, count) = match pe.is_64 {
+        true => (
+            read_u64(config + table_field)?,
+            read_u64(config + count_field)?,
+        ),
+        false => (
+            read_u32(config + table_field)? as u64,
+            read_u32(config + count_field)? as u64,
+        ),
+    };
+    let table = rva_to_file_offset(
+        pe,
+        u32::try_from(table_va.checked_sub(pe.image_base)?).ok()?,
+    )?;
+
+    let stride = 4 + (flags >> IMAGE_GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT) as usize;
+    let entries = data.get(table..)?.chunks_exact(stride).take(count as usize);
+    Some(entries.map(|entry| {
+        let rva = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        (rva, entry.get(4).copied())
+    }))
+}
@@ -0,0 +1,32 @@
+//! Rejects executables the bypass can't work on, before the scan fails on
+//! them in a less obvious way.
+
+use goblin::pe::PE;
+use goblin::pe::header::{
+    COFF_MACHINE_ARM, COFF_MACHINE_ARM64, COFF_MACHINE_X86, COFF_MACHINE_X86_64,
+};
+
+use crate::{Error, Result};
+
+/// Returns [`Error::UnsupportedBinaryKind`] unless `pe` is native x86 or x64 code.
+pub(crate) fn check(pe: &PE) -> Result<()> {
+    let clr = pe
+        .header
+        .optional_header
+        .and_then(|x| x.data_directories.get_clr_runtime_header().copied())
+        .is_some_and(|x| x.virtual_address != 0);
+    if clr {
+        return Err(Error::UnsupportedBinaryKind(
+            "a .NET assembly, whose code is compiled at runtime".to_string(),
+        ));
+    }
+
+    match pe.header.coff_header.machine {
+        COFF_MACHINE_X86 | COFF_MACHINE_X86_64 => Ok(()),
+        COFF_MACHINE_ARM64 => Err(Error::UnsupportedBinaryKind("built for ARM64".to_string())),
+        COFF_MACHINE_ARM => Err(Error::UnsupportedBinaryKind("built for ARM".to_string())),
+        machine => Err(Error::UnsupportedBinaryKind(format!(
+            "built for machine type 0x{machine:04x}"
+        ))),
+    }
+}
@@ -0,0 +1,117 @@
+//! Where each section lives in the file and in memory, following the loader's
+//! alignment rules instead of trusting the raw header fields, which repacked
+//! and installer-produced executables don't always keep aligned.
+
+use goblin::pe::PE;
+use goblin::pe::section_table::{IMAGE_SCN_MEM_EXECUTE, SectionTable};
+
+/// The loader rounds `PointerToRawData` down to this, whatever the `FileAlignment`.
+const RAW_POINTER_ALIGNMENT: usize = 0x200;
+
+/// Below this `SectionAlignment`, sections are mapped exactly as they are laid out in the file.
+const PAGE_SIZE: u32 = 0x1000;
+
+/// A section, as the loader maps it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SectionLayout<'a> {
+    pub header: &'a SectionTable,
+    /// File offset the raw data is read from
+    pub raw_start: usize,
+    /// Bytes of raw data read from the file, which may run past its end
+    pub raw_size: usize,
+    /// RVA the section is mapped at
+    pub rva: u32,
+    /// Bytes the section takes up in memory, including the zero-filled part past the raw data
+    pub virtual_size: u32,
+}
+
+impl SectionLayout<'_> {
+    pub fn raw_end(&self) -> usize {
+        self.raw_start + self.raw_size
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.header.characteristics & IMAGE_SCN_MEM_EXECUTE != 0
+    }
+
+    /// Whether `rva` is mapped by this section, from the file or zero-filled.
+    pub fn contains_rva(&self, rva: u32) -> bool {
+        rva >= self.rva && rva - self.rva < self.virtual_size
+    }
+
+    pub fn contains_file_offset(&self, file_off: usize) -> bool {
+        file_off >= self.raw_start && file_off < self.raw_end()
+    }
+
+    /// The file offset `rva` is loaded from, if it isn't zero-filled.
+    pub fn rva_to_file_offset(&self, rva: u32) -> Option<usize> {
+        let offset = rva.checked_sub(self.rva)? as usize;
+        (offset < self.raw_size && rva - self.rva < self.virtual_size)
+            .then(|| self.raw_start + offset)
+    }
+
+    /// The RVA `file_off` is loaded at, if this section loads it.
+    pub fn file_offset_to_rva(&self, file_off: usize) -> Option<u32> {
+        let offset = file_off.checked_sub(self.raw_start)?;
+        (offset < self.raw_size && (offset as u64) < self.virtual_size as u64)
+            .then(|| self.rva + offset as u32)
+    }
+}
+
+/// Every section of `pe`, as the loader maps it.
+pub(crate) fn sections<'a>(pe: &'a PE) -> impl Iterator<Item = SectionLayout<'a>> {
+    let (file_alignment, section_alignment) = pe
+        .header
+        .optional_header
+        .map(|x| {
+            (
+                x.windows_fields.file_alignment,
+                x.windows_fields.section_alignment,
+            )
+        })
+        .unwrap_or((RAW_POINTER_ALIGNMENT as u32, PAGE_SIZE));
+    pe.sections
+        .iter()
+        .map(move |header| layout(header, file_alignment, section_alignment))
+}
+
+fn layout(header: &SectionTable, file_alignment: u32, section_alignment: u32) -> SectionLayout<'_> {
+    // a section without a virtual size is as large as its raw data
+    let virtual_size = match header.virtual_size {
+        0 => header.size_of_raw_data,
+        size => size,
+    };
+
+    // with less than a page of alignment, the file is mapped as is
+    if section_alignment < PAGE_SIZE {
+        return SectionLayout {
+            header,
+            raw_start: header.pointer_to_raw_data as usize,
+            raw_size: header.size_of_raw_data.min(virtual_size) as usize,
+            rva: header.virtual_address,
+            virtual_size,
+        };
+    }
+
+    // otherwise the raw data starts at the rounded down pointer, and is only
+    // read as far as the smaller of the aligned raw and virtual sizes
+    let pointer = header.pointer_to_raw_data as usize;
+    let raw_start = pointer & !(RAW_POINTER_ALIGNMENT - 1);
+    let raw_size = align_up(
+        header.size_of_raw_data as u64 + (pointer - raw_start) as u64,
+        file_alignment,
+    )
+    .min(align_up(virtual_size as u64, section_alignment));
+    SectionLayout {
+        header,
+        raw_start,
+        raw_size: raw_size as usize,
+        rva: header.virtual_address,
+        virtual_size: align_up(virtual_size as u64, section_alignment).min(u32::MAX as u64) as u32,
+    }
+}
+
+/// Rounds `value` up to `alignment`, treating a malformed alignment of zero as none.
+fn align_up(value: u64, alignment: u32) -> u64 {
+    value.next_multiple_of(alignment.max(1) as u64)
+}
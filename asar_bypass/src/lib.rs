@@ -1,250 +1,329 @@
-use std::path::Path;
+//! Bypasses Electron's ASAR integrity check by stubbing out
+//! `ValidateIntegrityOrDie` inside of the executable.
+//!
+//! # Stability
+//!
+//! Everything exported from this crate follows semver. Types marked
+//! `#[non_exhaustive]` ([`Error`], [`PatchOptions`], [`PatchReport`] and
+//! friends) may gain variants or fields in a minor release, so match them with a
+//! wildcard and build them from their `Default` or constructor.
+//!
+//! The contents of [`signatures::SIGNATURES`] and the exact output of
+//! [`dump`] are not covered, they change whenever a new Electron release needs them to.
+
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use capstone::prelude::*;
-use capstone::arch;
-use goblin::pe::section_table::SectionTable;
 use goblin::pe::PE;
-use lightningscanner::{Scanner, pattern::Pattern};
-use log::info;
+use memchr::memmem;
+use log::{debug, info, warn};
 
 pub mod error;
 pub use error::*;
+pub mod signatures;
+use signatures::{DIALOG_SIGNATURES, Prologue, SIGNATURES};
+pub mod strategy;
+use strategy::{BytePattern, ManualTarget, Reference, Strategy};
+pub mod apply;
+pub mod context;
+pub use context::PatchContext;
+pub mod guard;
+use guard::CfgStatus;
+pub mod limits;
+use limits::ScanLimits;
+pub mod locate;
+pub use locate::{
+    BoundsSource, Candidate, FunctionBounds, StringMatch, find_function_bounds, find_xrefs, locate_integrity_string,
+    scan_candidates,
+};
+pub mod meta;
+pub mod methods;
+use methods::{Confidence, Fill, PatchMethod, StubKind};
+pub mod packed;
+pub mod patcher;
+pub use patcher::Patcher;
+pub mod progress;
+use progress::{NoProgress, Phase, ProgressSink};
+pub mod symbols;
+use symbols::PdbSymbol;
+pub mod version;
+use version::ElectronVersion;
+
+/// Re-exported as it appears in [`Strategy::locate`].
+pub use goblin;
 
-use crate::xrefs::XrefIterator;
 
+mod bounds;
+mod kind;
+mod layout;
+mod trace;
+pub mod dump;
+pub mod export;
 mod xrefs;
+#[cfg(feature = "fs")]
+mod file;
+#[cfg(feature = "fs")]
+pub use file::{edit_file, patch_file, patch_file_in_place, patch_file_with_options, preview_file, replace_file};
+#[cfg(feature = "tokio")]
+mod file_async;
+#[cfg(feature = "tokio")]
+pub use file_async::patch_file_async;
+#[cfg(all(windows, feature = "fs"))]
+pub mod live;
+#[cfg(any(test, feature = "fixture"))]
+pub mod fixture;
 
-/// Find the string in the image and return a file offset inside `data`.
-fn locate_string(data: &[u8]) -> Result<usize> {
-    let pattern = Pattern::new_string("Unsupported hashing algorithm in ValidateIntegrityOrDie");
-    let scanner = Scanner::from(pattern);
-    let result = unsafe { scanner.find(None, data.as_ptr(), data.len()) };
-    Ok(result.get_addr() as usize - data.as_ptr() as usize)
-}
+/// Name reported in [`PatchReport::signature`] when [`PatchOptions::pattern`] located the function.
+pub const CUSTOM_PATTERN: &str = "custom-pattern";
 
-/// Return the first xref VA to the string located at `file_off`.
-fn find_first_xref_va(data: &[u8], file_off: usize) -> Result<Option<u64>> {
-    let mut iter = XrefIterator::new(data, file_off)?;
-    match iter.next() {
-        Some(Ok(v)) => Ok(Some(v)),
-        Some(Err(e)) => Err(e),
-        None => Ok(None),
-    }
+/// Name reported in [`PatchReport::signature`] by [`stub_function_by_string`].
+pub const STRING_STUB: &str = "string-stub";
+
+/// Options for [`patch_with_options`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PatchOptions {
+    /// An IDA-style byte pattern (e.g. `E8 ?? ?? ?? ?? 48 8D 0D`) matching code
+    /// inside of the target function, used when none of the signatures match.
+    pub pattern: Option<String>,
+    /// The largest function, in bytes, that will be patched.
+    pub max_patch_size: usize,
+    /// When the function is larger than `max_patch_size`, patch a window around
+    /// the reference instead of returning [`Error::PatchTooLarge`].
+    pub allow_heuristic_shrink: bool,
+    /// Where the function is, skipping every other way of locating it.
+    pub target: Option<ManualTarget>,
+    /// A PDB to locate the function by name with, before trying anything else.
+    ///
+    /// [`patch_file`] uses the PDB next to the input when this is unset.
+    pub pdb: Option<PathBuf>,
+    /// The least confidence a [`PatchMethod`] needs to be used, with the less
+    /// direct methods only tried while none are confident enough.
+    pub min_confidence: Confidence,
+    /// Extra strategies, tried after the built-in signatures and before `pattern`.
+    pub strategies: Vec<Arc<dyn Strategy>>,
+    /// Try the built-in signatures, which locate the function through
+    /// references to its strings and are the slowest strategies.
+    pub scan_xrefs: bool,
+    /// Check the patched range disassembles to what was meant to be written,
+    /// returning [`Error::UnexpectedInstruction`] and undoing it if not.
+    pub verify: bool,
+    /// Copy the input here before [`patch_file`] writes anything, failing if
+    /// something is already there.
+    pub backup: Option<PathBuf>,
+    /// How much work each xref scan may do before giving up.
+    pub limits: ScanLimits,
+    /// When nothing matches inside of the input, search the other modules
+    /// (`.dll` and `.node` files) beside it, for apps that move the integrity
+    /// check into a helper module. The first match is patched into the
+    /// directory of the output, and named by [`PatchReport::module`].
+    pub scan_siblings: bool,
+    /// Write `{output}.patchmeta.json` next to the output of [`patch_file`],
+    /// recording what changed so [`meta::revert_file`] can undo it.
+    pub write_meta: bool,
+    /// Receives progress while patching.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// What fills a stubbed out function after the stub. The patched range is
+    /// always exactly as long as the original, whichever is picked.
+    pub fill: Fill,
+    /// Also NOP the call to the routine showing the "integrity check failed"
+    /// dialog, for builds which show it from outside of `ValidateIntegrityOrDie`.
+    /// Found in [`PatchReport::dialog`], and skipped if it can't be located.
+    pub patch_dialog: bool,
+    /// When the integrity string is referenced from more than one place, as
+    /// when the check has been inlined into its callers, also flip the branch
+    /// guarding each of the other references. Found in [`PatchReport::sites`].
+    pub multi_site: bool,
 }
 
-/// Given a parsed `PE` and a reference VA inside a section, find a likely
-/// function start/end (file offsets) containing the reference. Uses a small
-/// backwards scan for a common prologue and falls back to disassembly to
-/// locate a return.
-fn find_function_bounds(pe: &PE, ref_va: u64, data: &[u8]) -> Result<(usize, usize)> {
-    let image_base = pe.image_base;
-
-    // find containing section
-    let mut sect_opt: Option<&SectionTable> = None;
-    for sect in &pe.sections {
-        let sec_va_start = image_base + sect.virtual_address as u64;
-        let sec_va_end = sec_va_start + sect.virtual_size as u64;
-        if ref_va >= sec_va_start && ref_va < sec_va_end {
-            sect_opt = Some(sect);
-            break;
-        }
+impl PatchOptions {
+    /// Where progress should be reported.
+    pub(crate) fn progress(&self) -> &dyn ProgressSink {
+        self.progress.as_deref().unwrap_or(&NoProgress)
     }
-    let sect = sect_opt.ok_or_else(|| Error::SectionNotFound)?;
-    let section_va_base = image_base + sect.virtual_address as u64;
-    let ref_file_off = sect.pointer_to_raw_data as usize
-        + (ref_va.saturating_sub(section_va_base) as usize);
-
-    // Prepare a Capstone handle for disassembly
-    let cs = Capstone::new()
-        .x86()
-        .mode(if pe.is_64 {
-            arch::x86::ArchMode::Mode64
-        } else {
-            arch::x86::ArchMode::Mode32
-        })
-        .detail(false)
-        .build()?;
-
-    // --- Find start: look backwards for a run of PUSH instructions followed by a stack alloc ---
-    let search_back = 4096usize.min(ref_file_off);
-    let search_file_start = ref_file_off.saturating_sub(search_back).max(sect.pointer_to_raw_data as usize);
-    let search_file_end = ref_file_off.min(sect.pointer_to_raw_data as usize + sect.size_of_raw_data as usize).min(data.len());
-    let mut func_start: Option<usize> = None;
-    if search_file_start < search_file_end {
-        let code = &data[search_file_start..search_file_end];
-        let vabase = section_va_base + (search_file_start - sect.pointer_to_raw_data as usize) as u64;
-        if let Ok(insns) = cs.disasm_all(code, vabase) {
-            let insns_vec: Vec<_> = insns.iter().collect();
-            // find the last instruction before the reference
-            if let Some((last_idx, _)) = insns_vec.iter().enumerate().rev().find(|(_, i)| i.address() < ref_va) {
-                // walk backward while we see PUSH instructions
-                let mut start_idx = last_idx;
-                while start_idx > 0 {
-                    let prev = insns_vec[start_idx - 1];
-                    if let Some(mn) = prev.mnemonic() {
-                        if mn.starts_with("push") {
-                            start_idx -= 1;
-                            continue;
-                        }
-                    }
-                    break;
-                }
-                // verify that at least one push was found at start_idx..=last_idx
-                if start_idx <= last_idx {
-                    // require that the instruction at start_idx is a push
-                    if let Some(mn) = insns_vec[start_idx].mnemonic() {
-                        if mn.starts_with("push") {
-                            let start_va = insns_vec[start_idx].address();
-                            func_start = Some((start_va - section_va_base) as usize + sect.pointer_to_raw_data as usize);
-                        }
-                    }
-                }
-            }
+
+    /// Fills in [`Self::pdb`] with the PDB next to `exe_path`, if there is one.
+    #[cfg(feature = "fs")]
+    pub(crate) fn with_pdb_beside(&self, exe_path: &std::path::Path) -> Self {
+        let mut options = self.clone();
+        if options.pdb.is_none() {
+            options.pdb = Some(exe_path.with_extension("pdb")).filter(|x| x.is_file());
         }
+        options
     }
+}
 
-    // fallback: if not found, try to locate `sub rsp, imm` or `push rbp; mov rbp, rsp` near reference
-    if func_start.is_none() {
-        // small window before ref
-        let small_start = ref_file_off.saturating_sub(1024).max(sect.pointer_to_raw_data as usize);
-        let small_end = ref_file_off.min(sect.pointer_to_raw_data as usize + sect.size_of_raw_data as usize).min(data.len());
-        if small_start < small_end {
-            let code = &data[small_start..small_end];
-            let vabase = section_va_base + (small_start - sect.pointer_to_raw_data as usize) as u64;
-            if let Ok(insns) = cs.disasm_all(code, vabase) {
-                let insns_vec: Vec<_> = insns.iter().collect();
-                for (idx, insn) in insns_vec.iter().enumerate() {
-                    if insn.address() >= ref_va { break; }
-                    if let (Some(mn), Some(op)) = (insn.mnemonic(), insn.op_str()) {
-                        if mn == "sub" && op.contains("rsp") {
-                            // choose first push before it if present
-                            let mut sidx = idx;
-                            while sidx > 0 {
-                                let prev = insns_vec[sidx - 1];
-                                if let Some(pm) = prev.mnemonic() {
-                                    if pm.starts_with("push") { sidx -= 1; continue; }
-                                }
-                                break;
-                            }
-                            let start_va = insns_vec[sidx].address();
-                            func_start = Some((start_va - section_va_base) as usize + sect.pointer_to_raw_data as usize);
-                            break;
-                        }
-                    }
-                    if let Some(mn) = insn.mnemonic() {
-                        if mn == "push" {
-                            if idx + 1 < insns_vec.len() {
-                                let next = insns_vec[idx + 1];
-                                if let (Some(nmn), Some(nop)) = (next.mnemonic(), next.op_str()) {
-                                    if nmn == "mov" && nop.contains("rbp") && nop.contains("rsp") {
-                                        let start_va = insn.address();
-                                        func_start = Some((start_va - section_va_base) as usize + sect.pointer_to_raw_data as usize);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            pattern: None,
+            max_patch_size: 0x20000, // 128 KiB
+            allow_heuristic_shrink: false,
+            target: None,
+            pdb: None,
+            min_confidence: Confidence::Low,
+            strategies: Vec::new(),
+            scan_xrefs: true,
+            verify: true,
+            backup: None,
+            limits: ScanLimits::default(),
+            scan_siblings: false,
+            write_meta: false,
+            progress: None,
+            fill: Fill::Nop,
+            patch_dialog: false,
+            multi_site: false,
         }
     }
+}
 
-    // if still not found, default to bounded window below ref
-    let func_start = func_start.unwrap_or_else(|| {
-        let lower = ref_file_off.saturating_sub(0x2000);
-        let sect_start = sect.pointer_to_raw_data as usize;
-        if lower < sect_start { sect_start } else { lower }
-    });
+/// Describes what [`patch`] changed.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PatchReport {
+    /// Name of the [`Strategy`] that located the function, [`CUSTOM_PATTERN`] for
+    /// [`PatchOptions::pattern`], or [`methods::FUSE_WIRE`] for fuse flips
+    pub signature: &'static str,
+    /// How the integrity check was disabled
+    pub method: PatchMethod,
+    /// How sure `method` is of having changed the right code
+    pub confidence: Confidence,
+    /// File offset of the start of the patched range, the whole function for [`PatchMethod::FunctionStub`]
+    pub func_start: usize,
+    /// File offset of the end of the patched range
+    pub func_end: usize,
+    /// RVA of the start of the patched range
+    pub rva: u32,
+    /// VA of the instruction the [`Strategy`] located the function by, or the fuse for fuse flips
+    pub reference_va: u64,
+    /// VA of the data referenced by `reference_va`, such as the signature's string
+    pub data_va: Option<u64>,
+    /// The preferred load address of the image
+    pub image_base: u64,
+    /// Whether the image is 64-bit
+    pub is_64: bool,
+    /// The bytes of the function before patching
+    pub original: Vec<u8>,
+    /// The bytes of the function after patching
+    pub patched: Vec<u8>,
+    /// The module that was patched, when it wasn't the input, see [`PatchOptions::scan_siblings`]
+    pub module: Option<PathBuf>,
+    /// The version of Electron the input was built from, if it could be detected
+    pub electron: Option<ElectronVersion>,
+    /// Whether Control Flow Guard allows indirect calls to the start of the patched range
+    pub cfg: CfgStatus,
+    /// The secondary patch silencing the failure dialog, see [`PatchOptions::patch_dialog`]
+    pub dialog: Option<Box<PatchReport>>,
+    /// The branch flips at every other reference to the string, see [`PatchOptions::multi_site`]
+    pub sites: Vec<PatchReport>,
+}
 
-    // --- Find end: look forward for a run of POP instructions followed by RET ---
-    let sect_file_start = sect.pointer_to_raw_data as usize;
-    let sect_file_end = sect_file_start.saturating_add(sect.size_of_raw_data as usize).min(data.len());
-    let mut func_end: Option<usize> = None;
-    if ref_file_off < sect_file_end {
-        let code = &data[ref_file_off..sect_file_end];
-        let vabase = section_va_base + (ref_file_off - sect.pointer_to_raw_data as usize) as u64;
-        if let Ok(insns) = cs.disasm_all(code, vabase) {
-            let insns_vec: Vec<_> = insns.iter().collect();
-            for (idx, insn) in insns_vec.iter().enumerate() {
-                // detect sequence: one or more POP ... ; RET
-                if let Some(mn) = insn.mnemonic() {
-                    if mn.starts_with("pop") {
-                        // check ahead for contiguous pops
-                        let mut end_idx = idx;
-                        while end_idx + 1 < insns_vec.len() {
-                            let nxt = insns_vec[end_idx + 1];
-                            if let Some(nmn) = nxt.mnemonic() {
-                                if nmn.starts_with("pop") { end_idx += 1; continue; }
-                            }
-                            break;
-                        }
-                        // next instruction after pops should be ret
-                        if end_idx + 1 < insns_vec.len() {
-                            let candidate = insns_vec[end_idx + 1];
-                            if let Some(cmn) = candidate.mnemonic() {
-                                if cmn == "ret" {
-                                    let end_va = candidate.address();
-                                    func_end = Some((end_va - section_va_base) as usize + sect.pointer_to_raw_data as usize + candidate.bytes().len());
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    // also accept direct `ret` as end
-                    if mn == "ret" {
-                        let end_va = insn.address();
-                        func_end = Some((end_va - section_va_base) as usize + sect.pointer_to_raw_data as usize + insn.bytes().len());
-                        break;
-                    }
-                }
-            }
-        }
+impl PatchReport {
+    /// This patch, followed by the [`Self::dialog`] patch if there is one, then the [`Self::sites`].
+    pub fn edits(&self) -> impl Iterator<Item = &PatchReport> {
+        std::iter::once(self).chain(self.dialog.as_deref()).chain(&self.sites)
     }
 
-    // fallback: try to find RET by disassembling from func_start
-    if func_end.is_none() {
-        let code = &data[func_start..sect_file_end];
-        let vabase = section_va_base + (func_start - sect.pointer_to_raw_data as usize) as u64;
-        if let Ok(insns) = cs.disasm_all(code, vabase) {
-            for insn in insns.iter() {
-                if let Some(mn) = insn.mnemonic() {
-                    if mn == "ret" {
-                        let end_va = insn.address();
-                        func_end = Some((end_va - section_va_base) as usize + sect.pointer_to_raw_data as usize + insn.bytes().len());
-                        break;
-                    }
-                }
-            }
-        }
+    /// Disassembles the function before and after patching, to confirm the
+    /// right one was stubbed.
+    pub fn disassembly(&self) -> Result<(Vec<dump::Instruction>, Vec<dump::Instruction>)> {
+        let va = self.image_base + self.rva as u64;
+        Ok((
+            dump::instructions(&self.original, va, self.is_64)?,
+            dump::instructions(&self.patched, va, self.is_64)?,
+        ))
     }
+}
 
-    // final fallback: bounded window after reference
-    let func_end = func_end.unwrap_or_else(|| (ref_file_off.saturating_add(0x2000)).min(sect_file_end));
+/// Find the string `needle` in the image and return a file offset inside `data`.
+///
+/// Falls back to the UTF-16LE encoding of `needle`, since some builds store
+/// their diagnostic strings as wide strings.
+pub(crate) fn locate_string(data: &[u8], needle: &str) -> Result<usize> {
+    if let Some(file_off) = memmem::find(data, needle.as_bytes()) {
+        return Ok(file_off);
+    }
+
+    debug!("Could not find {needle:?}, trying its UTF-16 form");
+    memmem::find(data, &wide_bytes(needle)).ok_or_else(|| Error::StringNotFound(needle.to_string()))
+}
+
+/// Returns the UTF-16LE encoding of `needle`.
+fn wide_bytes(needle: &str) -> Vec<u8> {
+    needle.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Find every occurrence of `needle`, in both its ASCII and UTF-16LE forms,
+/// returning their file offsets inside `data` alongside whether they are UTF-16.
+pub(crate) fn locate_all_strings(data: &[u8], needle: &str) -> Vec<(usize, bool)> {
+    let ascii = memmem::find_iter(data, needle.as_bytes()).map(|x| (x, false));
+    let wide = wide_bytes(needle);
+    ascii.chain(memmem::find_iter(data, &wide).map(|x| (x, true))).collect()
+}
 
-    // safety shrink if absurdly large
-    let max_allowed = 0x20000usize; // 128 KiB
-    if func_end.saturating_sub(func_start) > max_allowed {
-        let new_start = ref_file_off.saturating_sub(0x2000).max(sect.pointer_to_raw_data as usize);
-        let new_end = (ref_file_off.saturating_add(0x2000)).min(sect_file_end);
-        info!("Function range too large (0x{:x}); shrinking to 0x{:x}-0x{:x}", func_start, new_start, new_end);
-        return Ok((new_start, new_end));
+/// Find an IDA-style byte pattern, such as `E8 ?? ?? ?? ?? 48 8D 0D`, and
+/// return a file offset inside `data`.
+pub fn locate_pattern(data: &[u8], pattern: &str) -> Result<usize> {
+    check_pattern(pattern)?;
+    let pattern: Vec<Option<u8>> = pattern
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect();
+
+    // Look for the first literal byte with memchr, then check the rest around it
+    let Some((anchor, first)) = pattern.iter().enumerate().find_map(|(i, x)| x.map(|x| (i, x))) else {
+        return (data.len() >= pattern.len()).then_some(0).ok_or(Error::PatternNotFound);
+    };
+    memchr::memchr_iter(first, data)
+        .filter_map(|pos| pos.checked_sub(anchor))
+        .find(|&start| {
+            data.get(start..start + pattern.len()).is_some_and(|window| {
+                window.iter().zip(&pattern).all(|(byte, expected)| expected.is_none_or(|x| x == *byte))
+            })
+        })
+        .ok_or(Error::PatternNotFound)
+}
+
+/// Makes sure `pattern` only contains hex bytes and `?`/`??` wildcards.
+pub(crate) fn check_pattern(pattern: &str) -> Result<()> {
+    let valid = pattern.split_whitespace().all(|byte| {
+        byte == "?" || byte == "??" || (byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit()))
+    });
+    if !valid || pattern.trim().is_empty() {
+        return Err(Error::InvalidPattern(pattern.to_string()));
     }
+    Ok(())
+}
 
-    Ok((func_start, func_end))
+/// Map a file offset inside `data` to its RVA, using the section that contains it.
+pub(crate) fn file_offset_to_rva(pe: &PE, file_off: usize) -> Option<u32> {
+    layout::sections(pe).find_map(|sect| sect.file_offset_to_rva(file_off))
 }
 
-/// Apply the `xor eax,eax; ret` stub and NOP remaining bytes in the target
-/// function range.
-fn apply_stub_patch(data: &mut [u8], func_start: usize, func_end: usize) -> Result<()> {
-    // Instruction bytes: 0x31 0xC0 0xC3
-    let stub: [u8; 3] = [0x31, 0xC0, 0xC3];
+/// File offset of the overlay appended after the last section (such as by an
+/// installer or a signature), or the end of `data` if there is none.
+pub(crate) fn overlay_start(pe: &PE, data: &[u8]) -> usize {
+    layout::sections(pe)
+        .map(|sect| sect.raw_end())
+        .max()
+        .unwrap_or(data.len())
+        .min(data.len())
+}
+
+/// Map an RVA to its file offset inside `data`, using the section that contains it.
+pub(crate) fn rva_to_file_offset(pe: &PE, rva: u32) -> Option<usize> {
+    layout::sections(pe).find_map(|sect| sect.rva_to_file_offset(rva))
+}
+
+/// Apply `stub` (such as `xor eax,eax; ret`) and `fill` the remaining bytes in the
+/// target function range.
+pub(crate) fn apply_stub_patch(data: &mut [u8], func_start: usize, func_end: usize, stub: &[u8], fill: Fill) -> Result<()> {
     if func_start >= data.len() {
-        return Err(Error::InvalidFunctionStart)?;
+        return Err(Error::InvalidFunctionStart);
     }
     let func_len = func_end.saturating_sub(func_start);
     if func_len == 0 {
-        return Err(Error::EmptyFunction)?;
+        return Err(Error::EmptyFunction);
     }
 
     // Write stub, truncated if function is smaller than stub size
@@ -255,38 +334,385 @@ fn apply_stub_patch(data: &mut [u8], func_start: usize, func_end: usize) -> Resu
         data[func_start + i] = b;
     }
 
-    // NOP the remaining bytes in the function (if any)
+    // Fill the remaining bytes in the function (if any)
     if func_len > stub.len() {
-        for b in &mut data[func_start + stub.len()..func_end] {
-            *b = 0x90; // NOP
+        fill.apply(&mut data[func_start + stub.len()..func_end]);
+    }
+
+    Ok(())
+}
+
+/// Runs the read-only search phases over `data`, trying each [`Strategy`]
+/// in order to locate the function responsible for validating the integrity,
+/// then works out its patched bytes without modifying `data`.
+///
+/// Each [`PatchMethod`] is tried in order until one is at least as confident
+/// as [`PatchOptions::min_confidence`].
+fn patched_region(data: &[u8], options: &PatchOptions) -> Result<PatchReport> {
+    let progress = options.progress();
+    progress.phase(Phase::Parsing);
+    let span = trace::phase("parse", "");
+    let mut ctx = PatchContext::with_progress(data, progress)?.with_limits(options.limits.clone());
+    drop(span);
+    kind::check(ctx.pe())?;
+    if let Some(packer) = packed::detect_packer(ctx.pe(), data) {
+        return Err(Error::PackedBinary(packer));
+    }
+    let electron = version::electron_version(data);
+    match electron {
+        Some(version) => debug!("Built from Electron {version}"),
+        None => debug!("Could not detect the Electron version"),
+    }
+
+    // Symbols are exact so are tried first, and the user's pattern points directly into the function so is the last resort
+    let pdb = options.pdb.as_ref().map(PdbSymbol::new);
+    let pattern = options.pattern.as_deref().map(BytePattern::new).transpose()?;
+    let strategies: Vec<&dyn Strategy> = match &options.target {
+        Some(target) => vec![target],
+        None => pdb
+            .as_ref()
+            .map(|x| x as &dyn Strategy)
+            .into_iter()
+            .chain(
+                SIGNATURES
+                    .iter()
+                    .filter(|_| options.scan_xrefs)
+                    .map(|x| x as &dyn Strategy),
+            )
+            .chain(options.strategies.iter().map(|x| x.as_ref()))
+            .chain(pattern.as_ref().map(|x| x as &dyn Strategy))
+            .collect(),
+    };
+
+    let mut located = None;
+    let mut failures = Vec::new();
+    for strategy in strategies {
+        progress.phase(Phase::Locating(strategy.name()));
+        let _span = trace::phase("locate", strategy.name());
+        let found = strategy.locate(&ctx).map_err(|err| ("locating", err)).and_then(|reference| {
+            progress.phase(Phase::ResolvingBounds);
+            let func = ctx.find_function_bounds(&reference, options).map_err(|err| ("resolving bounds", err))?;
+            Ok((reference, func))
+        });
+        match found {
+            Ok((reference, func)) => {
+                located = Some((strategy.name(), reference, func));
+                break;
+            }
+            // The strategy matched, but the bounds were refused, so don't guess with another one
+            Err((_, err @ Error::PatchTooLarge { .. })) => return Err(err),
+            // There's nothing to fall back to when the user said where the function is
+            Err((_, err)) if options.target.is_some() => return Err(err),
+            Err((phase, err)) => {
+                debug!("Strategy {} did not match while {phase}: {err}", strategy.name());
+                failures.push(StrategyFailure::new(strategy.name(), phase, err));
+            }
         }
     }
 
+    // Fall back to less direct methods until one is confident enough, keeping the best in case none are
+    let pe = ctx.pe();
+    let overlay = overlay_start(pe, data);
+    if let Some((.., func)) = &located
+        && func.end > overlay
+    {
+        return Err(Error::OverlayWrite {
+            start: func.start,
+            end: func.end,
+            overlay,
+        });
+    }
+    let fuse = SIGNATURES.iter().find_map(|x| x.integrity_fuse);
+    let mut best: Option<PatchReport> = None;
+    for method in PatchMethod::ALL {
+        let edit = match (method, &located) {
+            (PatchMethod::FunctionStub, Some((.., func))) => {
+                Some(methods::function_stub(&ctx, func, StubKind::ReturnZero, options.fill)?)
+            }
+            (PatchMethod::CallSite, Some((.., func))) => methods::call_site(&ctx, func)?,
+            (PatchMethod::BranchFlip, Some((_, reference, func))) => methods::branch_flip(pe, data, func.start, reference.va)?,
+            (PatchMethod::FuseFlip, _) => fuse.and_then(|fuse| methods::fuse_flip(data, fuse)),
+            _ => None,
+        };
+        let Some(edit) = edit else {
+            continue;
+        };
+        debug!("{:?} is possible with {:?} confidence", edit.method, edit.confidence);
+
+        let reference = match (&located, edit.method) {
+            (Some((name, reference, _)), method) if method != PatchMethod::FuseFlip => Some((*name, reference)),
+            _ => None,
+        };
+        let mut report = edit_report(pe, data, edit, reference)?;
+        report.electron = electron;
+
+        if report.confidence >= options.min_confidence {
+            if options.multi_site
+                && let Some((name, reference, _)) = &located
+                && report.method != PatchMethod::FuseFlip
+            {
+                report.sites = site_reports(&mut ctx, &report, name, reference, options)?;
+            }
+            if options.patch_dialog {
+                report.dialog = dialog_report(&mut ctx, &report, options)?.map(Box::new);
+            }
+            return Ok(report);
+        }
+        if best.as_ref().is_none_or(|x| report.confidence > x.confidence) {
+            best = Some(report);
+        }
+    }
+
+    match best {
+        Some(best) => Err(Error::LowConfidence(best.confidence)),
+        None => Err(Error::NoSignatureMatched(failures)),
+    }
+}
+
+/// Turns `edit` into a report, named after the strategy and reference that
+/// located it, or after the fuse for fuse flips.
+fn edit_report(pe: &PE, data: &[u8], edit: methods::Edit, reference: Option<(&'static str, &Reference)>) -> Result<PatchReport> {
+    let end = edit.start + edit.patched.len();
+    let overlay = overlay_start(pe, data);
+    if end > overlay {
+        return Err(Error::OverlayWrite {
+            start: edit.start,
+            end,
+            overlay,
+        });
+    }
+    let rva = file_offset_to_rva(pe, edit.start).ok_or(Error::RvaNotFound)?;
+    let (signature, reference_va, data_va) = match reference {
+        Some((name, reference)) => (name, reference.va, reference.data_va),
+        None => (methods::FUSE_WIRE, pe.image_base + rva as u64, None),
+    };
+    Ok(PatchReport {
+        signature,
+        method: edit.method,
+        confidence: edit.confidence,
+        func_start: edit.start,
+        func_end: end,
+        rva,
+        reference_va,
+        data_va,
+        image_base: pe.image_base,
+        is_64: pe.is_64,
+        original: data[edit.start..end].to_vec(),
+        patched: edit.patched,
+        module: None,
+        electron: None,
+        cfg: guard::cfg_status(pe, data, rva),
+        dialog: None,
+        sites: Vec::new(),
+    })
+}
+
+/// Works out the patches for [`PatchOptions::multi_site`]: a branch flip at
+/// every reference to the string `reference` was found by, other than itself
+/// and those inside of the range `primary` already patches.
+///
+/// Sites without a branch to flip, or whose flip would overlap an earlier
+/// edit, are skipped with a warning rather than failing the whole patch.
+fn site_reports(ctx: &mut PatchContext, primary: &PatchReport, name: &'static str, reference: &Reference, options: &PatchOptions) -> Result<Vec<PatchReport>> {
+    let Some(data_va) = reference.data_va else {
+        return Ok(Vec::new());
+    };
+    let _span = trace::phase("sites", name);
+    let mut xrefs = ctx.find_xrefs_to_many(&[data_va])?.remove(&data_va).unwrap_or_default();
+    if xrefs.len() < 2 {
+        return Ok(Vec::new());
+    }
+    xrefs.sort_unstable();
+    xrefs.dedup();
+    debug!("The string is referenced from {} places", xrefs.len());
+
+    let mut taken: Vec<std::ops::Range<usize>> = std::iter::once(primary.func_start..primary.func_end).collect();
+    let mut sites = Vec::new();
+    for va in xrefs {
+        if va == reference.va {
+            continue;
+        }
+        let inside = |start: usize, end: usize| taken.iter().any(|x| start < x.end && x.start < end);
+        let pe = ctx.pe();
+        if let Some(off) = va.checked_sub(pe.image_base).and_then(|x| rva_to_file_offset(pe, x as u32))
+            && inside(off, off + 1)
+        {
+            continue;
+        }
+        let site = Reference::new(va, reference.prologue).referencing(data_va);
+        // the branch is decoded from the start of the function around the site, to stay on instruction boundaries
+        let func = match ctx.find_function_bounds(&site, options) {
+            Ok(func) => func,
+            Err(err) => {
+                warn!("Could not find the function around the reference at {va:#x}, so it was left as is: {err}");
+                continue;
+            }
+        };
+        let (pe, data) = (ctx.pe(), ctx.data());
+        let Some(edit) = methods::branch_flip(pe, data, func.start, va)? else {
+            warn!("No branch guards the reference at {va:#x}, so it was left as is");
+            continue;
+        };
+        let end = edit.start + edit.patched.len();
+        if inside(edit.start, end) {
+            debug!("The branch guarding {va:#x} is already patched");
+            continue;
+        }
+        taken.push(edit.start..end);
+        sites.push(edit_report(pe, data, edit, Some((name, &site)))?);
+    }
+    Ok(sites)
+}
+
+/// Works out the patch for [`PatchOptions::patch_dialog`]: the call to the
+/// routine referencing one of the [`DIALOG_SIGNATURES`] is NOPed, or the
+/// routine itself is stubbed when it has several callers.
+///
+/// Nothing is patched when the routine can't be found, or is the function
+/// `primary` already patches.
+fn dialog_report(ctx: &mut PatchContext, primary: &PatchReport, options: &PatchOptions) -> Result<Option<PatchReport>> {
+    for signature in DIALOG_SIGNATURES {
+        ctx.progress().phase(Phase::Locating(signature.name));
+        let _span = trace::phase("locate", signature.name);
+        let found = signature
+            .locate(ctx)
+            .and_then(|reference| Ok((reference, ctx.find_function_bounds(&reference, options)?)));
+        let (reference, func) = match found {
+            Ok(found) => found,
+            Err(err) => {
+                debug!("Dialog signature {} did not match: {err}", signature.name);
+                continue;
+            }
+        };
+        if func.start < primary.func_end && primary.func_start < func.end {
+            debug!("The dialog is shown from the function already patched");
+            return Ok(None);
+        }
+
+        let (pe, data) = (ctx.pe(), ctx.data());
+        let edit = match methods::call_site(ctx, &func)? {
+            Some(edit) => edit,
+            None => methods::function_stub(ctx, &func, StubKind::Return, options.fill)?,
+        };
+        return edit_report(pe, data, edit, Some((signature.name, &reference))).map(Some);
+    }
+    warn!("Could not find the routine showing the failure dialog, so only the integrity check was patched");
+    Ok(None)
+}
+
+/// Writes the patched bytes of `report` into `data`, restoring the original
+/// bytes if the result no longer checks out, so `data` is never left half patched.
+fn write_region(data: &mut [u8], report: &PatchReport, options: &PatchOptions) -> Result<()> {
+    let mut written = Vec::new();
+    for edit in report.edits() {
+        if let Err(err) = write_edit(data, edit, options) {
+            for edit in written.into_iter().rev() {
+                let edit: &PatchReport = edit;
+                data[edit.func_start..edit.func_end].copy_from_slice(&edit.original);
+            }
+            return Err(err);
+        }
+        written.push(edit);
+    }
+    Ok(())
+}
+
+/// Writes a single edit of [`write_region`], restoring it if it doesn't check out.
+fn write_edit(data: &mut [u8], edit: &PatchReport, options: &PatchOptions) -> Result<()> {
+    let range = edit.func_start..edit.func_end;
+    if edit.patched.len() != range.len() || edit.original.len() != range.len() {
+        return Err(Error::LengthChanged {
+            expected: range.len(),
+            found: edit.patched.len(),
+        });
+    }
+    data[range.clone()].copy_from_slice(&edit.patched);
+    let checked = PE::parse(data).map_err(Error::from).and_then(|_| {
+        if options.verify {
+            methods::verify(edit, &data[range.clone()], options.fill)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = checked {
+        data[range].copy_from_slice(&edit.original);
+        return Err(err);
+    }
     Ok(())
 }
 
 /// Given an `.exe` for an Electron app with ASAR integrity enabled,
 /// this function will NOP out the function responsible for validating the integrity: `ValidateIntegrityOrDie`
-pub fn patch(data: &mut [u8]) -> Result<()> {
-    let file_off = locate_string(data)?;
-    let ref_va = find_first_xref_va(data, file_off)?.ok_or(Error::XrefNotFound)?;
+///
+/// Use a [`Patcher`] to change how it is located and patched.
+pub fn patch(data: &mut [u8]) -> Result<PatchReport> {
+    patch_with_options(data, &PatchOptions::default())
+}
+
+/// Locates `ValidateIntegrityOrDie` and works out how [`patch`] would change
+/// `data`, without writing anything.
+pub fn preview(data: &[u8]) -> Result<PatchReport> {
+    preview_with_options(data, &PatchOptions::default())
+}
+
+/// Same as [`preview`], but with the given `options`.
+pub fn preview_with_options(data: &[u8], options: &PatchOptions) -> Result<PatchReport> {
+    patched_region(data, options)
+}
 
-    let pe = PE::parse(data)?;
-    let (func_start, func_end) = find_function_bounds(&pe, ref_va, data)?;
+/// Stubs out the function referencing `needle` with `stub`, located the same
+/// way [`patch`] locates `ValidateIntegrityOrDie`: the string, then the code
+/// referencing it, then the bounds of the function around that code.
+///
+/// Useful for neutralising other checks that log or show a recognisable
+/// string, such as Electron's other fuses and sanity checks.
+pub fn stub_function_by_string(data: &mut [u8], needle: &str, stub: StubKind) -> Result<PatchReport> {
+    stub_function_by_string_with_options(data, needle, stub, &PatchOptions::default())
+}
 
-    apply_stub_patch(data, func_start, func_end)?;
+/// Same as [`stub_function_by_string`], but with the given `options`.
+///
+/// Only the options about the bounds, scan limits, fill, verification and
+/// progress apply, as the function is always located through `needle`.
+pub fn stub_function_by_string_with_options(
+    data: &mut [u8],
+    needle: &str,
+    stub: StubKind,
+    options: &PatchOptions,
+) -> Result<PatchReport> {
+    let report = {
+        let mut ctx = PatchContext::with_progress(data, options.progress())?.with_limits(options.limits.clone());
+        kind::check(ctx.pe())?;
+        ctx.progress().phase(Phase::Locating(STRING_STUB));
+        let reference = strategy::locate_by_string(&ctx, needle, Prologue::PushRun)?;
+        ctx.progress().phase(Phase::ResolvingBounds);
+        let func = ctx.find_function_bounds(&reference, options)?;
+        let edit = methods::function_stub(&ctx, &func, stub, options.fill)?;
+        edit_report(ctx.pe(), ctx.data(), edit, Some((STRING_STUB, &reference)))?
+    };
 
+    options.progress().phase(Phase::Writing);
+    let _span = trace::phase("apply", needle);
+    write_region(data, &report, options)?;
     info!(
-        "Patched ValidateIntegrityOrDie at file 0x{:x}-0x{:x}",
-        func_start, func_end
+        "Stubbed the function referencing {needle:?} at file 0x{:x}-0x{:x}",
+        report.func_start, report.func_end
     );
-
-    Ok(())
+    Ok(report)
 }
 
-pub fn patch_file<P: AsRef<Path>>(input_path: P, output_path: Option<P>) -> Result<()> {
-    let mut input_data = std::fs::read(&input_path)?;
-    patch(&mut input_data)?;
-    std::fs::write(output_path.unwrap_or(input_path), input_data)?;
-    Ok(())
-}
\ No newline at end of file
+/// Same as [`patch`], but with the given `options`.
+pub fn patch_with_options(data: &mut [u8], options: &PatchOptions) -> Result<PatchReport> {
+    let report = patched_region(data, options)?;
+
+    options.progress().phase(Phase::Writing);
+    let span = trace::phase("apply", report.signature);
+    write_region(data, &report, options)?;
+    drop(span);
+
+    info!(
+        "Patched ValidateIntegrityOrDie at file 0x{:x}-0x{:x} using signature {}",
+        report.func_start, report.func_end, report.signature
+    );
+
+    Ok(report)
+}
@@ -0,0 +1,80 @@
+//! Limits on the xref scan, so malformed or enormous binaries fail with an
+//! error instead of hanging.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Result};
+
+/// How much work the xref scan may do before giving up with [`Error::ScanLimitExceeded`].
+///
+/// Every limit is unset by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ScanLimits {
+    /// The most bytes of code disassembled by a single scan
+    pub max_bytes: Option<u64>,
+    /// The longest a single scan may run for
+    pub max_time: Option<Duration>,
+    /// The most instructions decoded by a single scan
+    pub max_instructions: Option<u64>,
+}
+
+/// Instructions decoded between checking the limits, so workers don't contend
+/// on the shared counters.
+pub(crate) const CHECK_INTERVAL: u64 = 0x1000;
+
+/// Tracks a scan against its [`ScanLimits`], shared between the workers.
+pub(crate) struct Budget<'a> {
+    limits: &'a ScanLimits,
+    /// Only read when there is a time limit
+    start: Option<Instant>,
+    bytes: AtomicU64,
+    instructions: AtomicU64,
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(limits: &'a ScanLimits) -> Self {
+        Self {
+            limits,
+            start: limits.max_time.map(|_| Instant::now()),
+            bytes: AtomicU64::new(0),
+            instructions: AtomicU64::new(0),
+        }
+    }
+
+    /// Records `bytes` more bytes of code about to be scanned.
+    pub fn spend_bytes(&self, bytes: u64) -> Result<()> {
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(max) = self.limits.max_bytes
+            && total > max
+        {
+            return Err(Error::ScanLimitExceeded(format!(
+                "scanned more than {max} bytes of code"
+            )));
+        }
+        self.check_time()
+    }
+
+    /// Records `count` more decoded instructions.
+    pub fn spend_instructions(&self, count: u64) -> Result<()> {
+        let total = self.instructions.fetch_add(count, Ordering::Relaxed) + count;
+        if let Some(max) = self.limits.max_instructions
+            && total > max
+        {
+            return Err(Error::ScanLimitExceeded(format!(
+                "decoded more than {max} instructions"
+            )));
+        }
+        self.check_time()
+    }
+
+    fn check_time(&self) -> Result<()> {
+        match (self.limits.max_time, self.start) {
+            (Some(max), Some(start)) if start.elapsed() > max => Err(Error::ScanLimitExceeded(
+                format!("ran for longer than {max:?}"),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
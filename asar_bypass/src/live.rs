@@ -0,0 +1,190 @@
+//! Applies the integrity bypass to an already-running process, leaving the
+//! executable on disk untouched.
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::path::Path;
+
+use log::info;
+use memmap2::Mmap;
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::System::Diagnostics::Debug::{FlushInstructionCache, WriteProcessMemory};
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, MODULEENTRY32W, Module32FirstW, Module32NextW, PROCESSENTRY32W,
+    Process32FirstW, Process32NextW, TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32, TH32CS_SNAPPROCESS,
+};
+use windows_sys::Win32::System::Memory::{
+    PAGE_EXECUTE_READWRITE, PAGE_PROTECTION_FLAGS, VirtualProtectEx,
+};
+use windows_sys::Win32::System::Threading::{
+    OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_WRITE,
+};
+
+use crate::progress::Phase;
+use crate::{Error, PatchOptions, PatchReport, Result, patched_region};
+
+/// Closes the wrapped handle on drop.
+struct OwnedHandle(HANDLE);
+
+impl OwnedHandle {
+    fn new(handle: HANDLE) -> Result<Self> {
+        if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error())?;
+        }
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Converts a nul-terminated UTF-16 buffer into a `String`.
+fn wide_to_string(buf: &[u16]) -> String {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+/// Returns the process ID of the first running process whose executable is named `exe_name`.
+pub fn find_process(exe_name: &str) -> Result<u32> {
+    let snapshot = OwnedHandle::new(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) })?;
+
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = size_of::<PROCESSENTRY32W>() as u32;
+    let mut ok = unsafe { Process32FirstW(snapshot.0, &mut entry) };
+    while ok != 0 {
+        if wide_to_string(&entry.szExeFile).eq_ignore_ascii_case(exe_name) {
+            return Ok(entry.th32ProcessID);
+        }
+        ok = unsafe { Process32NextW(snapshot.0, &mut entry) };
+    }
+
+    Err(Error::ProcessNotFound(exe_name.to_string()))
+}
+
+/// Returns the base address of the module named `module_name` inside the process `pid`.
+fn module_base(pid: u32, module_name: &str) -> Result<usize> {
+    let snapshot = OwnedHandle::new(unsafe {
+        CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, pid)
+    })?;
+
+    let mut entry: MODULEENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = size_of::<MODULEENTRY32W>() as u32;
+    let mut ok = unsafe { Module32FirstW(snapshot.0, &mut entry) };
+    while ok != 0 {
+        if wide_to_string(&entry.szModule).eq_ignore_ascii_case(module_name) {
+            return Ok(entry.modBaseAddr as usize);
+        }
+        ok = unsafe { Module32NextW(snapshot.0, &mut entry) };
+    }
+
+    Err(Error::ModuleNotFound(module_name.to_string()))
+}
+
+/// Applies the same stub as [`crate::patch`] to the process `pid`, which must be running `exe_path`.
+///
+/// The function is located using the executable on disk, then written at its
+/// runtime address (module base + RVA) inside the process.
+pub fn patch_process<P: AsRef<Path>>(
+    pid: u32,
+    exe_path: P,
+    options: &PatchOptions,
+) -> Result<PatchReport> {
+    let exe_path = exe_path.as_ref();
+    let module_name = exe_path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Locate the function on disk and convert it to an RVA
+    let exe_file = File::open(exe_path)?;
+    // SAFETY: the mapping is only read from
+    let map = unsafe { Mmap::map(&exe_file)? };
+    let report = patched_region(&map, &options.with_pdb_beside(exe_path))?;
+    for edit in report.edits() {
+        crate::methods::verify(edit, &edit.patched, options.fill)?;
+    }
+    options.progress().phase(Phase::Writing);
+    let _span = crate::trace::phase("apply", report.signature);
+
+    // Resolve where that RVA lives at runtime
+    let base = module_base(pid, &module_name)?;
+    let process = OwnedHandle::new(unsafe {
+        OpenProcess(
+            PROCESS_VM_OPERATION | PROCESS_VM_WRITE | PROCESS_QUERY_INFORMATION,
+            0,
+            pid,
+        )
+    })?;
+    for edit in report.edits() {
+        let address = (base + edit.rva as usize) as *const c_void;
+        write_remote(&process, address, edit)?;
+        info!(
+            "Patched {:?} in process {pid} at 0x{:x}-0x{:x}",
+            edit.method,
+            address as usize,
+            address as usize + edit.patched.len()
+        );
+    }
+
+    Ok(report)
+}
+
+/// Writes the patched bytes of `report` at `address` inside of `process`,
+/// temporarily making the code writable.
+fn write_remote(process: &OwnedHandle, address: *const c_void, report: &PatchReport) -> Result<()> {
+    let region = &report.patched;
+    let mut old_protect: PAGE_PROTECTION_FLAGS = 0;
+    if unsafe {
+        VirtualProtectEx(
+            process.0,
+            address,
+            region.len(),
+            PAGE_EXECUTE_READWRITE,
+            &mut old_protect,
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error())?;
+    }
+    let mut written = 0usize;
+    let write_ok = unsafe {
+        WriteProcessMemory(
+            process.0,
+            address,
+            region.as_ptr().cast(),
+            region.len(),
+            &mut written,
+        )
+    };
+    let write_err = std::io::Error::last_os_error();
+    if write_ok == 0 && written > 0 {
+        // Put back whatever made it in, rather than leaving the function half patched
+        let original = &report.original;
+        unsafe {
+            WriteProcessMemory(
+                process.0,
+                address,
+                original.as_ptr().cast(),
+                written,
+                std::ptr::null_mut(),
+            );
+        }
+    }
+    unsafe {
+        VirtualProtectEx(
+            process.0,
+            address,
+            region.len(),
+            old_protect,
+            &mut old_protect,
+        );
+        FlushInstructionCache(process.0, address, region.len());
+    }
+    if write_ok == 0 || written != region.len() {
+        return Err(write_err)?;
+    }
+    Ok(())
+}
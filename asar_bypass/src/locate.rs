@@ -0,0 +1,96 @@
+//! The search phases on their own, for tools that want to analyse a binary
+//! without patching it.
+//!
+//! Each of these parses `data` again, so use a [`PatchContext`] to run several
+//! of them on the same binary.
+
+use crate::context::PatchContext;
+use crate::signatures::Prologue;
+use crate::strategy::Reference;
+use crate::{PatchOptions, Result};
+
+/// One of the strings referenced from inside of `ValidateIntegrityOrDie`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StringMatch {
+    /// Name of the [`crate::signatures::Signature`] whose string was found
+    pub signature: &'static str,
+    /// File offset of the string
+    pub file_off: usize,
+    /// Virtual address of the string
+    pub va: u64,
+}
+
+/// An occurrence of a string related to the integrity check, found by [`scan_candidates`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Candidate {
+    /// The string that was found
+    pub needle: &'static str,
+    /// Whether it was found in its UTF-16LE form
+    pub wide: bool,
+    /// File offset of the occurrence
+    pub file_off: usize,
+    /// Virtual address of the occurrence, if it is inside of a section
+    pub va: Option<u64>,
+    /// Name of the section the occurrence is in
+    pub section: Option<String>,
+    /// Virtual addresses of every instruction referencing the occurrence
+    pub xrefs: Vec<u64>,
+}
+
+/// Where the bounds of a function came from, from most to least exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BoundsSource {
+    /// Given by the [`Reference`], such as from a PDB
+    Reference,
+    /// The exception directory (`.pdata`)
+    Unwind,
+    /// Walking the control flow from a guessed entry point
+    ControlFlow,
+    /// A window around the reference, as the function was larger than
+    /// [`PatchOptions::max_patch_size`]
+    Shrunk,
+}
+
+/// The file offsets a function spans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionBounds {
+    /// File offset of the start of the function
+    pub start: usize,
+    /// File offset just past the end of the function
+    pub end: usize,
+    pub source: BoundsSource,
+}
+
+/// Finds the first string of any known [`crate::signatures::Signature`] inside of `data`.
+pub fn locate_integrity_string(data: &[u8]) -> Result<StringMatch> {
+    PatchContext::new(data)?.locate_integrity_string()
+}
+
+/// Finds every instruction referencing the data at `file_off` (a file offset
+/// inside `data`), returning their virtual addresses in ascending order.
+pub fn find_xrefs(data: &[u8], file_off: usize) -> Result<Vec<u64>> {
+    PatchContext::new(data)?.find_xrefs(file_off)
+}
+
+/// Resolves the bounds of the function containing the instruction at `ref_va`,
+/// the same way patching does.
+pub fn find_function_bounds(
+    data: &[u8],
+    ref_va: u64,
+    options: &PatchOptions,
+) -> Result<FunctionBounds> {
+    PatchContext::new(data)?
+        .find_function_bounds(&Reference::new(ref_va, Prologue::PushRun), options)
+}
+
+/// Finds every occurrence of the strings used by the [`crate::signatures::SIGNATURES`],
+/// plus a few others related to the integrity check, alongside their code references.
+///
+/// This is meant for triaging builds that fail to patch, so only reads `data`.
+pub fn scan_candidates(data: &[u8]) -> Result<Vec<Candidate>> {
+    PatchContext::new(data)?.scan_candidates()
+}
@@ -1,22 +1,590 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use clap::Parser;
+use asar_bypass::guard::CfgStatus;
+use asar_bypass::methods::{Confidence, Fill};
+use asar_bypass::progress::{Phase, ProgressSink};
+use asar_bypass::strategy::ManualTarget;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::info;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The file to patch
+    #[arg(required = true)]
+    input: Option<PathBuf>,
+
+    /// Where to output the patched file
+    #[cfg_attr(windows, arg(required_unless_present_any = ["pid", "in_place"]))]
+    #[cfg_attr(not(windows), arg(required_unless_present = "in_place"))]
+    output: Option<PathBuf>,
+
+    /// Patch `input` where it is, writing only the changed bytes while holding an exclusive lock on it.
+    ///
+    /// Fails straight away if Steam or the app still has the file open.
+    #[arg(long, conflicts_with_all = ["output", "emit_patch"])]
+    in_place: bool,
+
+    #[command(flatten)]
+    patch: PatchArgs,
+
+    /// Copy `input` here before patching, defaulting to `{input}.bak`
+    #[cfg_attr(windows, arg(long, value_name = "PATH", conflicts_with_all = ["emit_patch", "pid"]))]
+    #[cfg_attr(not(windows), arg(long, value_name = "PATH", conflicts_with = "emit_patch"))]
+    backup: Option<Option<PathBuf>>,
+
+    /// Overwrite an existing backup
+    #[arg(long, requires = "backup")]
+    force: bool,
+
+    /// Write a Ghidra (`.py`) or IDA (`.idc`) script annotating what was patched to this path
+    #[arg(long)]
+    audit_script: Option<PathBuf>,
+
+    /// Write the changes to `output` as a patch file in this format, instead of a patched copy of `input`
+    #[arg(long, value_enum)]
+    emit_patch: Option<EmitFormat>,
+
+    /// Patch the running process with this ID instead of writing a file.
+    ///
+    /// `input` must be the executable the process was started from.
+    #[cfg(windows)]
+    #[arg(short, long)]
+    pid: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EmitFormat {
+    /// x64dbg's `.1337` format
+    X64dbg,
+    /// International Patching System, limited to the first 16 MiB
+    Ips,
+    /// Beat Patching System
+    Bps,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MinConfidence {
+    /// Accept any patch, including branch flips guessed from the reference
+    Low,
+    /// Require bounds derived from the code, or a unique call site
+    Medium,
+    /// Require exact bounds from unwind data or symbols, or the fuse
+    High,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FillArg {
+    /// Single-byte NOPs
+    Nop,
+    /// INT3s, trapping if anything still jumps into the function
+    Int3,
+    /// The fewest multi-byte NOPs covering the function
+    MultiNop,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Patch several files at once, writing each into `--out-dir`
+    Patch(BatchArgs),
+    /// Restore the original bytes of a file patched with `--write-meta`
+    Unpatch(UnpatchArgs),
+    /// Apply an IPS, BPS or x64dbg `.1337` patch file
+    Apply(ApplyArgs),
+    /// List every integrity-related string and its code references, to include in a bug report when patching fails
+    Scan(ScanArgs),
+}
+
+#[derive(Args)]
+struct ScanArgs {
+    /// The file to scan
+    input: PathBuf,
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// The patch file
+    patch: PathBuf,
+
+    /// The file to apply the patch to
     input: PathBuf,
 
     /// Where to output the patched file
     output: PathBuf,
+
+    /// Refuse to apply the patch unless the CRC32 of `input` is this (in hex).
+    ///
+    /// BPS patches carry their own checksums, which are always verified.
+    #[arg(long, value_parser = parse_crc32)]
+    expect_crc32: Option<u32>,
+}
+
+#[derive(Args)]
+struct UnpatchArgs {
+    /// The patched file
+    input: PathBuf,
+
+    /// The metadata written when patching, defaults to `{input}.patchmeta.json`
+    #[arg(short, long)]
+    meta: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// The files to patch, glob patterns such as `*.exe` are expanded
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// The directory to write the patched files into, under their original names
+    #[arg(short, long)]
+    out_dir: PathBuf,
+
+    #[command(flatten)]
+    patch: PatchArgs,
+}
+
+#[derive(Args)]
+struct PatchArgs {
+    /// An IDA-style byte pattern inside of the target function, used when the string search fails.
+    ///
+    /// For example: "E8 ?? ?? ?? ?? 48 8D 0D"
+    #[arg(long)]
+    pattern: Option<String>,
+
+    /// The largest function, in bytes, that will be patched. Accepts hex with a `0x` prefix
+    #[arg(long, default_value = "0x20000", value_parser = parse_size)]
+    max_patch_size: usize,
+
+    /// Patch the function containing this virtual address, skipping the string and xref search
+    #[arg(long, value_parser = parse_size, conflicts_with_all = ["file_offset", "range"])]
+    target_va: Option<usize>,
+
+    /// Patch the function containing this file offset, skipping the string and xref search
+    #[arg(long, value_parser = parse_size, conflicts_with = "range")]
+    file_offset: Option<usize>,
+
+    /// Patch exactly these file offsets, given as `start:end`
+    #[arg(long, value_parser = parse_range)]
+    range: Option<Range<usize>>,
+
+    /// Locate the function by name inside of this PDB, defaults to the PDB next to the input
+    #[arg(long)]
+    pdb: Option<PathBuf>,
+
+    /// Patch a window around the reference when the function is too large, instead of refusing
+    #[arg(long)]
+    allow_heuristic_shrink: bool,
+
+    /// Print a hexdump and disassembly of the function before and after patching
+    #[arg(long)]
+    show_diff: bool,
+
+    /// Print the disassembly of the function before and after patching
+    #[arg(long)]
+    show_disasm: bool,
+
+    /// Record the original bytes inside of `{output}.patchmeta.json`, so `unpatch` can restore them
+    #[arg(long)]
+    write_meta: bool,
+
+    /// When nothing matches, search and patch the `.dll` and `.node` modules beside the input instead
+    #[arg(long)]
+    scan_siblings: bool,
+
+    /// Also NOP the call to the routine showing the "integrity check failed" dialog, for builds which show it separately
+    #[arg(long)]
+    patch_dialog: bool,
+
+    /// Also flip the branch guarding every other reference to the integrity string, for builds with the check inlined
+    #[arg(long)]
+    multi_site: bool,
+
+    /// Give up once the xref scan has disassembled this many bytes of code. Accepts hex with a `0x` prefix
+    #[arg(long, value_parser = parse_size)]
+    max_scan_bytes: Option<usize>,
+
+    /// Give up once the xref scan has run for this many seconds
+    #[arg(long)]
+    max_scan_secs: Option<u64>,
+
+    /// Give up once the xref scan has decoded this many instructions
+    #[arg(long)]
+    max_scan_instructions: Option<u64>,
+
+    /// The least confidence a patch needs, with call-site, branch and fuse patches tried until one has it
+    #[arg(long, value_enum, default_value = "low")]
+    min_confidence: MinConfidence,
+
+    /// What fills the rest of the function after the stub. The patched range keeps its length either way
+    #[arg(long, value_enum, default_value = "nop")]
+    fill: FillArg,
+}
+
+impl PatchArgs {
+    fn into_options(self) -> asar_bypass::PatchOptions {
+        let mut options = asar_bypass::PatchOptions::default();
+        options.pattern = self.pattern;
+        options.max_patch_size = self.max_patch_size;
+        options.allow_heuristic_shrink = self.allow_heuristic_shrink;
+        options.pdb = self.pdb;
+        options.target = match (self.target_va, self.file_offset, self.range) {
+            (Some(va), ..) => Some(ManualTarget::Va(va as u64)),
+            (_, Some(file_off), _) => Some(ManualTarget::FileOffset(file_off)),
+            (.., Some(range)) => Some(ManualTarget::Range(range)),
+            _ => None,
+        };
+        options.write_meta = self.write_meta;
+        options.scan_siblings = self.scan_siblings;
+        options.patch_dialog = self.patch_dialog;
+        options.multi_site = self.multi_site;
+        options.limits.max_bytes = self.max_scan_bytes.map(|x| x as u64);
+        options.limits.max_time = self.max_scan_secs.map(Duration::from_secs);
+        options.limits.max_instructions = self.max_scan_instructions;
+        options.min_confidence = match self.min_confidence {
+            MinConfidence::Low => Confidence::Low,
+            MinConfidence::Medium => Confidence::Medium,
+            MinConfidence::High => Confidence::High,
+        };
+        options.fill = match self.fill {
+            FillArg::Nop => Fill::Nop,
+            FillArg::Int3 => Fill::Int3,
+            FillArg::MultiNop => Fill::MultiByteNop,
+        };
+        if std::io::stderr().is_terminal() {
+            options.progress = Some(Arc::new(StderrProgress::default()));
+        }
+        options
+    }
+}
+
+/// Prints the progress of the search to stderr.
+#[derive(Debug, Default)]
+struct StderrProgress {
+    last_percent: AtomicU64,
+}
+
+impl ProgressSink for StderrProgress {
+    fn phase(&self, phase: Phase<'_>) {
+        if let Phase::Locating(name) = phase {
+            self.last_percent.store(0, Ordering::Relaxed);
+            eprintln!("Trying {name}...");
+        }
+    }
+
+    fn bytes_scanned(&self, done: u64, total: u64) {
+        // only redraw when the percentage changes, as chunks finish rapidly
+        let percent = done * 100 / total.max(1);
+        if self.last_percent.swap(percent, Ordering::Relaxed) == percent {
+            return;
+        }
+        eprint!("\rScanning for references: {percent:>3}%");
+        if percent == 100 {
+            eprintln!();
+        }
+    }
+}
+
+/// Parses a size in either decimal or `0x` prefixed hex.
+fn parse_size(s: &str) -> Result<usize, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses a `start:end` range of sizes, see [`parse_size`].
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s.split_once(':').ok_or("expected start:end")?;
+    let range = parse_size(start).map_err(|err| err.to_string())?..parse_size(end).map_err(|err| err.to_string())?;
+    if range.is_empty() {
+        return Err("the range is empty".to_string());
+    }
+    Ok(range)
+}
+
+/// Parses a CRC32 in hex, with or without a `0x` prefix.
+fn parse_crc32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16)
+}
+
+/// Expands any glob patterns inside of `inputs`, since Windows shells leave them to us.
+fn expand_inputs(inputs: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        match glob::glob(input) {
+            Ok(matches) if input.contains(['*', '?', '[']) => paths.extend(matches.flatten()),
+            _ => paths.push(PathBuf::from(input)),
+        }
+    }
+    paths
+}
+
+/// Patches every input into `out_dir`, printing a line per file, and returns
+/// how many failed.
+///
+/// Refuses before patching anything when two inputs share a file name, as
+/// one would overwrite the other inside of `out_dir`.
+fn patch_batch(args: BatchArgs) -> asar_bypass::Result<usize> {
+    let inputs = expand_inputs(&args.inputs);
+    let mut jobs = Vec::with_capacity(inputs.len());
+    // by lowercase name, as the names of `App.exe` and `app.exe` clash on Windows too
+    let mut taken: HashMap<String, &Path> = HashMap::new();
+    for input in &inputs {
+        let name = input.file_name().unwrap_or(input.as_os_str());
+        let output = args.out_dir.join(name);
+        if let Some(other) = taken.insert(name.to_string_lossy().to_lowercase(), input) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} would both be written to {}, patch them into separate directories",
+                    other.display(),
+                    input.display(),
+                    output.display()
+                ),
+            )
+            .into());
+        }
+        jobs.push((input, output));
+    }
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    let show_diff = args.patch.show_diff;
+    let show_disasm = args.patch.show_disasm;
+    let options = args.patch.into_options();
+
+    let mut failed = 0;
+    for (input, output) in jobs {
+        match asar_bypass::patch_file_with_options(input.as_path(), Some(output.as_path()), &options) {
+            Ok(report) => {
+                println!(
+                    "ok      {} -> {} (0x{:x}-0x{:x}, {}, {:?} with {:?} confidence)",
+                    input.display(),
+                    output.display(),
+                    report.func_start,
+                    report.func_end,
+                    report.signature,
+                    report.method,
+                    report.confidence
+                );
+                if show_diff {
+                    println!("{}", asar_bypass::dump::diff(&report)?);
+                } else if show_disasm {
+                    println!("{}", asar_bypass::dump::listing(&report)?);
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("failed  {}: {err}", input.display());
+            }
+        }
+    }
+
+    println!("{} patched, {failed} failed", inputs.len() - failed);
+    Ok(failed)
+}
+
+/// Where to back `input` up to, `path` or `{input}.bak`, refusing to replace an earlier backup unless `force` is set.
+///
+/// An earlier backup is left where it is, to be replaced once the new one has been written.
+fn backup_path(input: &Path, path: Option<&Path>, force: bool) -> asar_bypass::Result<PathBuf> {
+    let default = {
+        let mut name = input.as_os_str().to_owned();
+        name.push(".bak");
+        PathBuf::from(name)
+    };
+    let path = path.map_or(default, Path::to_path_buf);
+    if path.exists() && !force {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("the backup {} already exists, pass --force to replace it", path.display()),
+        )
+        .into());
+    }
+    Ok(path)
+}
+
+/// Writes a Ghidra or IDA script annotating `report` to `path`, depending on its extension.
+fn write_audit_script(path: &Path, report: &asar_bypass::PatchReport) -> asar_bypass::Result<()> {
+    let script = match path.extension().and_then(|x| x.to_str()) {
+        Some("idc") => asar_bypass::export::idc_script(report),
+        _ => asar_bypass::export::ghidra_script(report),
+    };
+    std::fs::write(path, script)?;
+    info!("Wrote the audit script to {}.", path.display());
+    Ok(())
+}
+
+/// Prints every candidate string inside of `input` as a table, for pasting into bug reports.
+fn print_scan(input: &Path) -> asar_bypass::Result<()> {
+    let data = std::fs::read(input)?;
+    let candidates = asar_bypass::scan_candidates(&data)?;
+    let pe = asar_bypass::goblin::pe::PE::parse(&data)?;
+
+    println!("asar_bypass {} scan of {}", env!("CARGO_PKG_VERSION"), input.display());
+    println!("timestamp 0x{:08x}", pe.header.coff_header.time_date_stamp);
+    match asar_bypass::version::electron_version(&data) {
+        Some(version) => println!("electron {version}"),
+        None => println!("electron version not found"),
+    }
+    println!("{:<10}  {:<18}  {:<8}  {:<6}  {:>5}  string", "offset", "va", "section", "enc", "xrefs");
+    for candidate in &candidates {
+        println!(
+            "0x{:08x}  {:<18}  {:<8}  {:<6}  {:>5}  {}",
+            candidate.file_off,
+            candidate.va.map(|x| format!("0x{x:x}")).unwrap_or_else(|| "-".to_string()),
+            candidate.section.as_deref().unwrap_or("-"),
+            if candidate.wide { "utf16" } else { "ascii" },
+            candidate.xrefs.len(),
+            candidate.needle,
+        );
+        for xref in &candidate.xrefs {
+            println!("{:12}referenced by 0x{xref:x}", "");
+        }
+    }
+    if candidates.is_empty() {
+        println!("none of the known strings were found");
+    }
+    Ok(())
 }
 
 fn main() -> asar_bypass::Result<()> {
     env_logger::init();
     let cli = Cli::parse();
-    asar_bypass::patch_file(cli.input, Some(cli.output))?;
-    info!("Successfully patched.");
+
+    match cli.command {
+        Some(Command::Patch(args)) => {
+            if patch_batch(args)? > 0 {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Unpatch(args)) => {
+            let meta = asar_bypass::meta::revert_file(&args.input, args.meta.as_deref())?;
+            // The sidecar no longer describes the file
+            if args.meta.is_none() {
+                std::fs::remove_file(asar_bypass::meta::PatchMeta::sidecar_path(&args.input))?;
+            }
+            info!("Restored 0x{:x} bytes at 0x{:x}.", meta.original.len(), meta.func_start);
+            return Ok(());
+        }
+        Some(Command::Apply(args)) => {
+            let data = std::fs::read(&args.input)?;
+            if let Some(expected) = args.expect_crc32 {
+                asar_bypass::apply::verify_crc32(expected, crc32fast::hash(&data))?;
+            }
+            let patched = asar_bypass::apply::apply(data, &std::fs::read(&args.patch)?)?;
+            std::fs::write(&args.output, patched)?;
+            info!("Applied {} to {}.", args.patch.display(), args.output.display());
+            return Ok(());
+        }
+        Some(Command::Scan(args)) => {
+            print_scan(&args.input)?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let input = cli.input.expect("required by clap");
+    let show_diff = cli.patch.show_diff;
+    let show_disasm = cli.patch.show_disasm;
+    let mut options = cli.patch.into_options();
+
+    let report = 'report: {
+        #[cfg(windows)]
+        if let Some(pid) = cli.pid {
+            let report = asar_bypass::live::patch_process(pid, &input, &options)?;
+            info!("Successfully patched process {pid}.");
+            break 'report report;
+        }
+
+        if let Some(format) = cli.emit_patch {
+            let output = cli.output.expect("required by clap");
+            let report = asar_bypass::preview_file(&input, &options)?;
+            let module = input.file_name().unwrap_or_default().to_string_lossy();
+            let patch = match format {
+                EmitFormat::X64dbg => asar_bypass::export::x64dbg(&report, &module).into_bytes(),
+                EmitFormat::Ips => asar_bypass::export::ips(&report)?,
+                EmitFormat::Bps => asar_bypass::export::bps(&std::fs::read(&input)?, &report),
+            };
+            std::fs::write(&output, patch)?;
+            info!("Wrote the patch to {}.", output.display());
+            break 'report report;
+        }
+
+        // an earlier backup is only replaced once the new one is written beside it, so a failure keeps it
+        let mut replaced_backup = None;
+        if let Some(backup) = &cli.backup {
+            let path = backup_path(&input, backup.as_deref(), cli.force)?;
+            if path.exists() {
+                let mut temp = path.clone().into_os_string();
+                temp.push(".tmp");
+                let temp = PathBuf::from(temp);
+                let _ = std::fs::remove_file(&temp);
+                options.backup = Some(temp);
+                replaced_backup = Some(path);
+            } else {
+                options.backup = Some(path);
+            }
+        }
+        let patched = if cli.in_place {
+            asar_bypass::patch_file_in_place(&input, &options)
+        } else {
+            asar_bypass::patch_file_with_options(&input, cli.output.as_ref(), &options)
+        };
+        if let (Some(path), Some(temp)) = (replaced_backup, &options.backup) {
+            match &patched {
+                Ok(_) => std::fs::rename(temp, path)?,
+                Err(_) => {
+                    let _ = std::fs::remove_file(temp);
+                }
+            }
+        }
+        let report = patched?;
+        info!("Successfully patched.");
+        report
+    };
+    info!(
+        "Used {:?} via {} with {:?} confidence.",
+        report.method, report.signature, report.confidence
+    );
+    if let Some(version) = report.electron {
+        info!("The input was built from Electron {version}.");
+    }
+    if report.cfg == CfgStatus::ValidTarget {
+        info!("The patched function is a Control Flow Guard call target, so indirect calls reach the stub.");
+    }
+    if let Some(module) = &report.module {
+        info!("The integrity check was inside of {}.", module.display());
+    }
+    if let Some(dialog) = &report.dialog {
+        info!(
+            "Silenced the failure dialog at file 0x{:x}-0x{:x} using {:?}.",
+            dialog.func_start, dialog.func_end, dialog.method
+        );
+    }
+    for site in &report.sites {
+        info!(
+            "Flipped the branch guarding the reference at 0x{:x}, at file 0x{:x}-0x{:x}.",
+            site.reference_va, site.func_start, site.func_end
+        );
+    }
+
+    if show_diff {
+        println!("{}", asar_bypass::dump::diff(&report)?);
+    } else if show_disasm {
+        println!("{}", asar_bypass::dump::listing(&report)?);
+    }
+    if let Some(path) = &cli.audit_script {
+        write_audit_script(path, &report)?;
+    }
     Ok(())
 }
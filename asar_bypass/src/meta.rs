@@ -0,0 +1,147 @@
+//! Sidecar metadata recording what was patched, so it can be undone without a
+//! full copy of the original executable.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, PatchReport, Result};
+
+/// The version of the metadata format written by this crate.
+pub const META_VERSION: u32 = 1;
+
+/// Everything needed to undo a patch, written next to the executable as
+/// `{name}.patchmeta.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct PatchMeta {
+    /// The version of the format, see [`META_VERSION`]
+    pub version: u32,
+    /// The strategy that located the function
+    pub signature: String,
+    /// File offset of the start of the patched function
+    pub func_start: usize,
+    /// The bytes of the function before patching
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub original: Vec<u8>,
+    /// The bytes of the function after patching
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub patched: Vec<u8>,
+    /// The secondary patch silencing the failure dialog, see [`crate::PatchOptions::patch_dialog`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dialog: Option<Box<PatchMeta>>,
+    /// The branch flips at every other reference to the string, see [`crate::PatchOptions::multi_site`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sites: Vec<PatchMeta>,
+}
+
+fn to_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    serializer.serialize_str(&hex)
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() % 2 != 0 {
+        return Err(serde::de::Error::custom("odd number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+impl From<&PatchReport> for PatchMeta {
+    fn from(report: &PatchReport) -> Self {
+        Self {
+            version: META_VERSION,
+            signature: report.signature.to_string(),
+            func_start: report.func_start,
+            original: report.original.clone(),
+            patched: report.patched.clone(),
+            dialog: report.dialog.as_deref().map(|x| Box::new(x.into())),
+            sites: report.sites.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl PatchMeta {
+    /// This patch, followed by the [`Self::dialog`] patch if there is one, then the [`Self::sites`].
+    pub fn edits(&self) -> impl Iterator<Item = &PatchMeta> {
+        std::iter::once(self)
+            .chain(self.dialog.as_deref())
+            .chain(&self.sites)
+    }
+
+    /// Where the metadata for `exe_path` is kept.
+    pub fn sidecar_path(exe_path: &Path) -> PathBuf {
+        let mut name = exe_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".patchmeta.json");
+        exe_path.with_file_name(name)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let meta: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if meta.version > META_VERSION {
+            return Err(Error::InvalidMeta(format!(
+                "unsupported version {}",
+                meta.version
+            )));
+        }
+        if meta.edits().any(|x| x.original.len() != x.patched.len()) {
+            return Err(Error::InvalidMeta(
+                "original and patched lengths differ".to_string(),
+            ));
+        }
+        Ok(meta)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Restores the original bytes recorded in `meta`, refusing if `data` does not
+/// hold every recorded patch.
+pub fn revert(data: &mut [u8], meta: &PatchMeta) -> Result<()> {
+    for edit in meta.edits() {
+        let range = edit.func_start..edit.func_start + edit.patched.len();
+        if data.get(range) != Some(edit.patched.as_slice()) {
+            return Err(Error::MetaMismatch(edit.func_start));
+        }
+    }
+    for edit in meta.edits() {
+        data[edit.func_start..edit.func_start + edit.original.len()]
+            .copy_from_slice(&edit.original);
+    }
+    Ok(())
+}
+
+/// Same as [`revert`], but operates on a file, using its sidecar metadata
+/// unless `meta_path` is given.
+///
+/// Only the patched ranges are read and written back.
+pub fn revert_file(path: &Path, meta_path: Option<&Path>) -> Result<PatchMeta> {
+    let sidecar = PatchMeta::sidecar_path(path);
+    let meta = PatchMeta::load(meta_path.unwrap_or(&sidecar))?;
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    for edit in meta.edits() {
+        let mut region = vec![0; edit.patched.len()];
+        file.seek(SeekFrom::Start(edit.func_start as u64))?;
+        file.read_exact(&mut region)
+            .map_err(|_| Error::MetaMismatch(edit.func_start))?;
+        if region != edit.patched {
+            return Err(Error::MetaMismatch(edit.func_start));
+        }
+    }
+
+    for edit in meta.edits() {
+        file.seek(SeekFrom::Start(edit.func_start as u64))?;
+        file.write_all(&edit.original)?;
+    }
+    Ok(meta)
+}
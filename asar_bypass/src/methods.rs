@@ -0,0 +1,423 @@
+//! The ways the integrity check can be disabled, from most to least direct,
+//! alongside how confident each one is of having hit the right code.
+
+use capstone::arch::x86::ArchMode;
+use capstone::prelude::*;
+use goblin::pe::PE;
+use memchr::{memchr_iter, memmem};
+
+use crate::context::PatchContext;
+use crate::layout;
+use crate::locate::{BoundsSource, FunctionBounds};
+use crate::{Error, PatchReport, Result, apply_stub_patch, dump};
+
+/// Name reported in [`crate::PatchReport::signature`] when the fuse wire was flipped.
+pub const FUSE_WIRE: &str = "fuse-wire";
+
+/// The sentinel preceding Electron's fuse wire.
+const FUSE_SENTINEL: &[u8] = b"dL7pKGdnNz796PbbjQWNKmHXBZaB9tsX";
+
+/// The only fuse wire layout there is so far.
+const FUSE_VERSION: u8 = 1;
+
+/// Bytes before the reference searched for the branch guarding it.
+const BRANCH_WINDOW: usize = 0x200;
+
+/// A 5-byte NOP (`nop dword ptr [rax + rax]`), the size of a `call rel32`.
+const NOP5: [u8; 5] = [0x0F, 0x1F, 0x44, 0x00, 0x00];
+
+/// How the integrity check was disabled, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatchMethod {
+    /// `ValidateIntegrityOrDie` was replaced with `xor eax, eax; ret`, popping
+    /// its arguments for 32-bit stdcall functions
+    FunctionStub,
+    /// The only call to `ValidateIntegrityOrDie` was replaced with a NOP
+    CallSite,
+    /// The branch skipping the failure path was made unconditional
+    BranchFlip,
+    /// The `EnableEmbeddedAsarIntegrityValidation` fuse was turned off
+    FuseFlip,
+}
+
+impl PatchMethod {
+    pub const ALL: [Self; 4] = [
+        Self::FunctionStub,
+        Self::CallSite,
+        Self::BranchFlip,
+        Self::FuseFlip,
+    ];
+}
+
+/// How sure a [`PatchMethod`] is of having changed the right code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// Guessed, such as a window around the reference
+    Low,
+    /// Derived from the code, such as a prologue match
+    Medium,
+    /// Exact, such as from unwind data or symbols
+    High,
+}
+
+/// What fills the rest of a stubbed out function, after the stub.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fill {
+    /// Single-byte `nop`s
+    #[default]
+    Nop,
+    /// `int3`s, trapping if anything still jumps into the function
+    Int3,
+    /// The fewest multi-byte `nop`s covering the range, as compilers pad with
+    MultiByteNop,
+}
+
+/// The multi-byte `nop`s recommended by Intel, indexed by their length - 1.
+const MULTI_BYTE_NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0F, 0x1F, 0x00],
+    &[0x0F, 0x1F, 0x40, 0x00],
+    &[0x0F, 0x1F, 0x44, 0x00, 0x00],
+    &[0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00],
+    &[0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+impl Fill {
+    /// Overwrites the whole of `buf`, without changing its length.
+    pub fn apply(self, buf: &mut [u8]) {
+        match self {
+            Self::Nop => buf.fill(0x90),
+            Self::Int3 => buf.fill(0xCC),
+            Self::MultiByteNop => {
+                let mut rest = buf;
+                while !rest.is_empty() {
+                    let nop = MULTI_BYTE_NOPS[rest.len().min(MULTI_BYTE_NOPS.len()) - 1];
+                    let (head, tail) = std::mem::take(&mut rest).split_at_mut(nop.len());
+                    head.copy_from_slice(nop);
+                    rest = tail;
+                }
+            }
+        }
+    }
+
+    /// The mnemonic capstone decodes the fill as.
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Int3 => "int3",
+            _ => "nop",
+        }
+    }
+}
+
+/// A change worked out by one of the [`PatchMethod`]s.
+pub(crate) struct Edit {
+    /// File offset of the first changed byte
+    pub start: usize,
+    pub patched: Vec<u8>,
+    pub method: PatchMethod,
+    pub confidence: Confidence,
+}
+
+/// What a stubbed out function does instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StubKind {
+    /// `xor eax, eax; ret`, returning false or null
+    #[default]
+    ReturnZero,
+    /// `mov eax, 1; ret`, returning true
+    ReturnOne,
+    /// `ret`, for functions returning nothing
+    Return,
+}
+
+impl StubKind {
+    /// The stub, followed by `ret` or, for 32-bit stdcall functions which pop
+    /// `cleanup` bytes of their own arguments, `ret imm16`.
+    fn bytes(self, cleanup: u16) -> Vec<u8> {
+        let mut stub = match self {
+            Self::ReturnZero => vec![0x31, 0xC0],
+            Self::ReturnOne => vec![0xB8, 0x01, 0x00, 0x00, 0x00],
+            Self::Return => Vec::new(),
+        };
+        let [lo, hi] = cleanup.to_le_bytes();
+        match cleanup {
+            0 => stub.push(0xC3),
+            _ => stub.extend([0xC2, lo, hi]),
+        }
+        stub
+    }
+}
+
+/// Stubs out the whole function, trusting it as much as its bounds.
+pub(crate) fn function_stub(
+    ctx: &PatchContext,
+    bounds: &FunctionBounds,
+    kind: StubKind,
+    fill: Fill,
+) -> Result<Edit> {
+    let mut patched = ctx.data()[bounds.start..bounds.end].to_vec();
+    let len = patched.len();
+    let cleanup = if ctx.pe().is_64 {
+        0
+    } else {
+        stack_cleanup(ctx, bounds)?
+    };
+    let stub = kind.bytes(cleanup);
+    apply_stub_patch(&mut patched, 0, len, &stub, fill)?;
+    Ok(Edit {
+        start: bounds.start,
+        patched,
+        method: PatchMethod::FunctionStub,
+        confidence: match bounds.source {
+            BoundsSource::Reference | BoundsSource::Unwind => Confidence::High,
+            BoundsSource::ControlFlow => Confidence::Medium,
+            _ => Confidence::Low,
+        },
+    })
+}
+
+/// Bytes before a `call` searched for the `push`es of its arguments.
+const ARGS_WINDOW: usize = 0x40;
+
+/// How many bytes of arguments a 32-bit function pops on return, 0 for cdecl.
+///
+/// The function's own `ret imm16` is trusted first. Failing that, its callers
+/// are checked: stdcall callers don't clean up after the call, so the
+/// arguments are counted from the `push`es before it.
+fn stack_cleanup(ctx: &PatchContext, bounds: &FunctionBounds) -> Result<u16> {
+    let data = ctx.data();
+    let va = |file_off| ctx.va_of(file_off).unwrap_or_default();
+
+    let insns = dump::instructions(&data[bounds.start..bounds.end], va(bounds.start), false)?;
+    if let Some(ret) = insns.iter().find(|x| x.mnemonic == "ret") {
+        return Ok(parse_imm(&ret.operands).unwrap_or_default() as u16);
+    }
+
+    for call in ctx.calls_to(bounds.start) {
+        // cdecl callers pop the arguments themselves, straight after the call
+        let after = dump::instructions(
+            data.get(call + 5..call + 11).unwrap_or_default(),
+            va(call + 5),
+            false,
+        )?;
+        if after
+            .first()
+            .is_some_and(|x| x.mnemonic == "add" && x.operands.starts_with("esp,"))
+        {
+            return Ok(0);
+        }
+
+        // otherwise count the pushes leading up to the call, as each is a 4 byte argument
+        let start = call.saturating_sub(ARGS_WINDOW);
+        let before = dump::instructions(&data[start..call], va(start), false)?;
+        let decoded: usize = before.iter().map(|x| x.bytes.len()).sum();
+        if decoded == call - start {
+            let pushes = before
+                .iter()
+                .rev()
+                .take_while(|x| x.mnemonic == "push")
+                .count();
+            return Ok(pushes as u16 * 4);
+        }
+    }
+    Ok(0)
+}
+
+/// Parses a `0x`-prefixed or decimal immediate operand.
+fn parse_imm(operand: &str) -> Option<u64> {
+    match operand.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => operand.parse().ok(),
+    }
+}
+
+/// Every `call rel32` inside of the executable sections of `pe`, as the RVA
+/// it calls and its file offset, sorted by the RVA.
+pub(crate) fn all_calls(pe: &PE, data: &[u8]) -> Vec<(i64, usize)> {
+    let mut calls = Vec::new();
+    for sect in layout::sections(pe).filter(|x| x.is_executable()) {
+        let start = sect.raw_start;
+        let end = sect.raw_end().min(data.len());
+        let Some(code) = data.get(start..end) else {
+            continue;
+        };
+
+        // call rel32 is relative to the end of the instruction
+        for i in memchr_iter(0xE8, code) {
+            let Some(&[a, b, c, d]) = code.get(i + 1..i + 5) else {
+                continue;
+            };
+            let rel = i32::from_le_bytes([a, b, c, d]) as i64;
+            calls.push((sect.rva as i64 + i as i64 + 5 + rel, start + i));
+        }
+    }
+    calls.sort_unstable();
+    calls
+}
+
+/// NOPs out the `call` to the function starting at `func_start`, when it is
+/// only called from one place.
+///
+/// Only used for cdecl functions on 32-bit, as the arguments pushed for a
+/// stdcall function would never be popped.
+pub(crate) fn call_site(ctx: &PatchContext, bounds: &FunctionBounds) -> Result<Option<Edit>> {
+    let [call] = ctx.calls_to(bounds.start)[..] else {
+        return Ok(None);
+    };
+    if !ctx.pe().is_64 && stack_cleanup(ctx, bounds)? != 0 {
+        return Ok(None);
+    }
+    Ok(Some(Edit {
+        start: call,
+        patched: NOP5.to_vec(),
+        method: PatchMethod::CallSite,
+        confidence: Confidence::Medium,
+    }))
+}
+
+/// Makes the last conditional branch before `ref_va` that jumps over it
+/// unconditional, on the basis that it skips the failure path.
+///
+/// The function is decoded from `func_start`, its first instruction, so the
+/// branch is read from where the instructions really begin rather than from
+/// the middle of one.
+pub(crate) fn branch_flip(pe: &PE, data: &[u8], func_start: usize, ref_va: u64) -> Result<Option<Edit>> {
+    let Some(rva) = ref_va.checked_sub(pe.image_base).map(|x| x as u32) else {
+        return Ok(None);
+    };
+    let Some((sect, ref_off)) =
+        layout::sections(pe).find_map(|x| Some((x, x.rva_to_file_offset(rva)?)))
+    else {
+        return Ok(None);
+    };
+    if !(sect.raw_start..=ref_off).contains(&func_start) {
+        return Ok(None);
+    }
+    let func_va = pe.image_base + (sect.rva as usize + func_start - sect.raw_start) as u64;
+    let window_start = ref_off.saturating_sub(BRANCH_WINDOW);
+
+    let cs = Capstone::new()
+        .x86()
+        .mode(if pe.is_64 {
+            ArchMode::Mode64
+        } else {
+            ArchMode::Mode32
+        })
+        .build()?;
+
+    // jcc rel8 becomes jmp rel8, while jcc rel32 is a byte longer than jmp rel32, so is padded with a NOP first.
+    // Other conditional jumps (jecxz, loop) have no unconditional form, so are never picked
+    let flipped = |bytes: &[u8]| match *bytes {
+        [op, rel] if (0x70..=0x7F).contains(&op) => Some(vec![0xEB, rel]),
+        [0x0F, op, a, b, c, d] if (0x80..=0x8F).contains(&op) => Some(vec![0x90, 0xE9, a, b, c, d]),
+        _ => None,
+    };
+
+    let mut branch = None;
+    for insn in cs
+        .disasm_all(&data[func_start..ref_off.min(data.len())], func_va)?
+        .iter()
+    {
+        let start = func_start + (insn.address() - func_va) as usize;
+        let target = insn
+            .op_str()
+            .and_then(|x| x.strip_prefix("0x"))
+            .and_then(|x| u64::from_str_radix(x, 16).ok());
+        if start >= window_start
+            && target.is_some_and(|x| x > ref_va)
+            && let Some(patched) = flipped(insn.bytes())
+        {
+            branch = Some((start, patched));
+        }
+    }
+    let Some((start, patched)) = branch else {
+        return Ok(None);
+    };
+    Ok(Some(Edit {
+        start,
+        patched,
+        method: PatchMethod::BranchFlip,
+        confidence: Confidence::Low,
+    }))
+}
+
+/// Turns off the fuse at index `fuse` inside of Electron's fuse wire, which
+/// Electron checks before validating anything.
+pub(crate) fn fuse_flip(data: &[u8], fuse: usize) -> Option<Edit> {
+    let sentinel = memmem::find(data, FUSE_SENTINEL)?;
+
+    // the wire is laid out as version, length, then a '0' or '1' for each fuse
+    let wire = sentinel + FUSE_SENTINEL.len();
+    let (&version, &len) = (data.get(wire)?, data.get(wire + 1)?);
+    if version != FUSE_VERSION || fuse >= len as usize {
+        return None;
+    }
+    let start = wire + 2 + fuse;
+    (data.get(start) == Some(&b'1')).then(|| Edit {
+        start,
+        patched: vec![b'0'],
+        method: PatchMethod::FuseFlip,
+        confidence: Confidence::High,
+    })
+}
+
+/// Disassembles `written`, the bytes now at the start of `report`'s range,
+/// checking they decode to exactly what `report.method` should have written.
+///
+/// This catches bounds landing mid-instruction, or anything else having
+/// changed the range since it was patched.
+pub(crate) fn verify(report: &PatchReport, written: &[u8], fill: Fill) -> Result<()> {
+    let va = report.image_base + report.rva as u64;
+    let unexpected = |address: u64, found: &str| Error::UnexpectedInstruction {
+        address,
+        found: found.to_string(),
+    };
+
+    // The fuse is data, and a truncated stub can't be decoded
+    if report.method == PatchMethod::FuseFlip
+        || (report.method == PatchMethod::FunctionStub && written.len() < 5)
+    {
+        if written != report.patched {
+            return Err(unexpected(va, "bytes other than the patch"));
+        }
+        return Ok(());
+    }
+
+    let insns = dump::instructions(written, va, report.is_64)?;
+    let decoded: usize = insns.iter().map(|x| x.bytes.len()).sum();
+    if insns.is_empty() || decoded != written.len() {
+        return Err(unexpected(va + decoded as u64, "undecodable bytes"));
+    }
+
+    let mut expected: Vec<(&str, &str)> = match report.method {
+        PatchMethod::FunctionStub => match insns[0].mnemonic.as_str() {
+            "mov" => vec![("mov", "eax, 1"), ("ret", "*")],
+            "ret" => vec![("ret", "*")],
+            _ => vec![("xor", "eax, eax"), ("ret", "*")],
+        },
+        PatchMethod::CallSite => vec![("nop", "*")],
+        _ if insns.len() == 2 => vec![("nop", ""), ("jmp", "*")],
+        _ => vec![("jmp", "*")],
+    };
+    // only the stub is followed by the fill, everything else pads with single-byte NOPs
+    let padding = match report.method {
+        PatchMethod::FunctionStub => (fill.mnemonic(), "*"),
+        _ => ("nop", ""),
+    };
+    expected.resize(expected.len().max(insns.len()), padding);
+    for (insn, (mnemonic, operands)) in insns.iter().zip(expected) {
+        if insn.mnemonic != mnemonic || (operands != "*" && insn.operands != operands) {
+            return Err(unexpected(
+                insn.address,
+                &format!("{} {}", insn.mnemonic, insn.operands),
+            ));
+        }
+    }
+    Ok(())
+}
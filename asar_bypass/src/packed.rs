@@ -0,0 +1,45 @@
+use goblin::pe::PE;
+
+use crate::layout;
+
+/// Section names left behind by common packers, alongside the packer's name.
+const PACKER_SECTIONS: &[(&str, &str)] = &[
+    ("UPX0", "UPX"),
+    ("UPX1", "UPX"),
+    (".UPX0", "UPX"),
+    (".aspack", "ASPack"),
+    (".adata", "ASPack"),
+    ("MPRESS1", "MPRESS"),
+    ("MPRESS2", "MPRESS"),
+    (".petite", "Petite"),
+    ("PEC2", "PECompact"),
+    ("pec1", "PECompact"),
+    (".nsp0", "NsPack"),
+    (".vmp0", "VMProtect"),
+    (".vmp1", "VMProtect"),
+    (".themida", "Themida"),
+    (".winlice", "WinLicense"),
+    (".enigma1", "Enigma Protector"),
+];
+
+/// Returns the name of the packer `pe` looks to be packed with, if any.
+///
+/// Packed code is compressed on disk, so none of the strings or references
+/// the signatures rely on can be found until it is unpacked.
+pub fn detect_packer(pe: &PE, data: &[u8]) -> Option<&'static str> {
+    for sect in &pe.sections {
+        let Ok(name) = sect.name() else {
+            continue;
+        };
+        if let Some((_, packer)) = PACKER_SECTIONS.iter().find(|(x, _)| *x == name) {
+            return Some(packer);
+        }
+    }
+
+    // UPX writes its magic just before the first section's data, even when the sections are renamed
+    let headers_end = layout::sections(pe).map(|x| x.raw_start).min()?;
+    data[..headers_end.min(data.len())]
+        .windows(4)
+        .any(|x| x == b"UPX!")
+        .then_some("UPX")
+}
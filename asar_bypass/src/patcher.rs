@@ -0,0 +1,179 @@
+//! A builder over [`PatchOptions`], so new options don't turn into a pile of
+//! function arguments.
+//!
+//! ```no_run
+//! let report = asar_bypass::Patcher::new()
+//!     .pattern("E8 ?? ?? ?? ?? 48 8D 0D")?
+//!     .backup("app.exe.bak")
+//!     .run_file("app.exe", None)?;
+//! println!("patched using {}", report.signature);
+//! # Ok::<(), asar_bypass::Error>(())
+//! ```
+
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::limits::ScanLimits;
+use crate::methods::{Confidence, Fill};
+use crate::progress::ProgressSink;
+use crate::strategy::{BytePattern, ManualTarget, Strategy};
+use crate::{PatchOptions, PatchReport, Result};
+
+/// Configures and runs a patch, see the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Patcher {
+    options: PatchOptions,
+}
+
+impl Patcher {
+    /// A patcher with the default [`PatchOptions`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a strategy, tried after the built-in signatures and before [`Self::pattern`].
+    pub fn strategy(mut self, strategy: impl Strategy + 'static) -> Self {
+        self.options.strategies.push(Arc::new(strategy));
+        self
+    }
+
+    /// Whether to try the built-in signatures, which locate the function through xrefs to its strings.
+    pub fn xrefs(mut self, scan: bool) -> Self {
+        self.options.scan_xrefs = scan;
+        self
+    }
+
+    /// An IDA-style byte pattern inside of the function, tried last.
+    ///
+    /// Returns [`crate::Error::InvalidPattern`] if `pattern` is malformed.
+    pub fn pattern(mut self, pattern: &str) -> Result<Self> {
+        BytePattern::new(pattern)?;
+        self.options.pattern = Some(pattern.to_string());
+        Ok(self)
+    }
+
+    /// Where the function is, skipping every other way of locating it.
+    pub fn target(mut self, target: ManualTarget) -> Self {
+        self.options.target = Some(target);
+        self
+    }
+
+    /// A PDB to locate the function by name with.
+    pub fn pdb(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.pdb = Some(path.into());
+        self
+    }
+
+    /// The largest function, in bytes, that will be patched.
+    pub fn max_patch_size(mut self, size: usize) -> Self {
+        self.options.max_patch_size = size;
+        self
+    }
+
+    /// Patch a window around the reference when the function is too large.
+    pub fn allow_heuristic_shrink(mut self, allow: bool) -> Self {
+        self.options.allow_heuristic_shrink = allow;
+        self
+    }
+
+    /// The least confidence a patch needs to be used.
+    pub fn min_confidence(mut self, confidence: Confidence) -> Self {
+        self.options.min_confidence = confidence;
+        self
+    }
+
+    /// How much work each xref scan may do before giving up.
+    pub fn limits(mut self, limits: ScanLimits) -> Self {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Search the other modules beside the input when nothing matches inside of it.
+    pub fn scan_siblings(mut self, scan: bool) -> Self {
+        self.options.scan_siblings = scan;
+        self
+    }
+
+    /// Whether to check the patched range disassembles to what was meant to be written.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    /// Copy the input here before [`Self::run_file`] writes anything.
+    pub fn backup(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.backup = Some(path.into());
+        self
+    }
+
+    /// Record what changed next to the output of [`Self::run_file`], so it can be undone.
+    pub fn write_meta(mut self, write: bool) -> Self {
+        self.options.write_meta = write;
+        self
+    }
+
+    /// Also NOP the call to the routine showing the failure dialog.
+    pub fn patch_dialog(mut self, patch: bool) -> Self {
+        self.options.patch_dialog = patch;
+        self
+    }
+
+    /// Also flip the branch at every other reference to the string, for inlined checks.
+    pub fn multi_site(mut self, multi_site: bool) -> Self {
+        self.options.multi_site = multi_site;
+        self
+    }
+
+    /// What fills a stubbed out function after the stub.
+    pub fn fill(mut self, fill: Fill) -> Self {
+        self.options.fill = fill;
+        self
+    }
+
+    /// Receives progress while patching.
+    pub fn progress(mut self, progress: impl ProgressSink + 'static) -> Self {
+        self.options.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// The options built up so far.
+    pub fn options(&self) -> &PatchOptions {
+        &self.options
+    }
+
+    /// Patches `data` in place, see [`crate::patch_with_options`].
+    pub fn run(&self, data: &mut [u8]) -> Result<PatchReport> {
+        crate::patch_with_options(data, &self.options)
+    }
+
+    /// Reports what [`Self::run`] would change, see [`crate::preview_with_options`].
+    pub fn preview(&self, data: &[u8]) -> Result<PatchReport> {
+        crate::preview_with_options(data, &self.options)
+    }
+
+    /// Patches `input`, writing to `output` or over `input`, see [`crate::patch_file_with_options`].
+    #[cfg(feature = "fs")]
+    pub fn run_file(&self, input: impl AsRef<Path>, output: Option<&Path>) -> Result<PatchReport> {
+        crate::patch_file_with_options(input.as_ref(), output, &self.options)
+    }
+
+    /// Patches `path` where it is while holding an exclusive lock, see [`crate::patch_file_in_place`].
+    #[cfg(feature = "fs")]
+    pub fn run_in_place(&self, path: impl AsRef<Path>) -> Result<PatchReport> {
+        crate::patch_file_in_place(path, &self.options)
+    }
+
+    /// Reports what [`Self::run_file`] would change, without writing anything.
+    #[cfg(feature = "fs")]
+    pub fn preview_file(&self, input: impl AsRef<Path>) -> Result<PatchReport> {
+        crate::preview_file(input, &self.options)
+    }
+}
+
+impl From<PatchOptions> for Patcher {
+    fn from(options: PatchOptions) -> Self {
+        Self { options }
+    }
+}
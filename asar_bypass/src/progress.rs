@@ -0,0 +1,45 @@
+use std::fmt::Debug;
+
+/// A step of the search, reported through [`ProgressSink::phase`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Phase<'a> {
+    /// Parsing the PE headers
+    Parsing,
+    /// Running the named [`crate::strategy::Strategy`]
+    Locating(&'a str),
+    /// Disassembling the executable sections for references to the located string
+    ScanningXrefs,
+    /// Resolving the bounds of the function around the reference
+    ResolvingBounds,
+    /// Writing the patched function
+    Writing,
+}
+
+/// Receives progress while patching, such as to drive a progress bar.
+///
+/// Every method does nothing by default, so only the interesting ones need
+/// implementing. Scanning happens across several threads, so calls may arrive
+/// concurrently and out of order.
+pub trait ProgressSink: Debug + Send + Sync {
+    /// A new phase has started.
+    fn phase(&self, phase: Phase<'_>) {
+        let _ = phase;
+    }
+
+    /// `done` out of `total` bytes of code have been scanned in the current phase.
+    fn bytes_scanned(&self, done: u64, total: u64) {
+        let _ = (done, total);
+    }
+
+    /// The section `name` has been fully scanned, `done` out of `total`.
+    fn section_done(&self, name: &str, done: usize, total: usize) {
+        let _ = (name, done, total);
+    }
+}
+
+/// Ignores all progress, used when [`crate::PatchOptions::progress`] is unset.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
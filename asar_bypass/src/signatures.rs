@@ -0,0 +1,62 @@
+/// How the start of the target function is usually laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Prologue {
+    /// A run of `push` instructions directly before the reference
+    PushRun,
+    /// A `sub rsp, imm` stack allocation, or a `push rbp; mov rbp, rsp` frame setup
+    StackAlloc,
+}
+
+/// A known way of locating `ValidateIntegrityOrDie`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Signature {
+    /// Identifies the entry inside of a [`crate::PatchReport`], see [`crate::strategy::Strategy::name`]
+    pub name: &'static str,
+    /// A string referenced from inside of the target function
+    pub needle: &'static str,
+    /// Which prologue to look for first
+    pub prologue: Prologue,
+    /// Index of the `EnableEmbeddedAsarIntegrityValidation` fuse within the fuse wire
+    pub integrity_fuse: Option<usize>,
+}
+
+/// Every known signature, in the order they are tried.
+///
+/// The entries aren't keyed by Electron version: no range has been verified for
+/// any of them, so every entry is tried on every build, whichever version
+/// [`crate::version::electron_version`] detects. Supporting a new Electron release
+/// should only need an entry here.
+pub static SIGNATURES: &[Signature] = &[
+    Signature {
+        name: "unsupported-hashing-algorithm",
+        needle: "Unsupported hashing algorithm in ValidateIntegrityOrDie",
+        prologue: Prologue::PushRun,
+        integrity_fuse: Some(4),
+    },
+    Signature {
+        name: "integrity-check-failed",
+        needle: "Integrity check failed for asar archive",
+        prologue: Prologue::PushRun,
+        integrity_fuse: Some(4),
+    },
+];
+
+/// Ways of locating the routine some builds show a fatal dialog from, even
+/// when `ValidateIntegrityOrDie` is stubbed, see [`crate::PatchOptions::patch_dialog`].
+pub static DIALOG_SIGNATURES: &[Signature] = &[Signature {
+    name: "integrity-violation-dialog",
+    needle: "ASAR Integrity Violation",
+    prologue: Prologue::StackAlloc,
+    integrity_fuse: None,
+}];
+
+/// Strings worth looking for when none of the [`SIGNATURES`] match, alongside their needles.
+pub static EXTRA_CANDIDATES: &[&str] = &[
+    "ValidateIntegrityOrDie",
+    // the resource holding the expected hashes
+    "ElectronAsarIntegrity",
+    // the fuse wire sentinel, which precedes EnableEmbeddedAsarIntegrityValidation
+    "dL7pKGdnNz796PbbjQWNKmHXBZaB9tsX",
+];
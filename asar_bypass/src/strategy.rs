@@ -0,0 +1,163 @@
+use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::context::PatchContext;
+use crate::signatures::{Prologue, Signature};
+use crate::{
+    CUSTOM_PATTERN, Error, Result, check_pattern, locate_all_strings, locate_pattern, trace,
+};
+
+/// A reference to data from inside of the function to patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Reference {
+    /// Virtual address of an instruction inside of the function
+    pub va: u64,
+    /// Which prologue to look for first, should the bounds need to be guessed
+    pub prologue: Prologue,
+    /// The end of the function, when `va` is known to be its start
+    pub end_va: Option<u64>,
+    /// The data referenced by the instruction at `va`, such as a string
+    pub data_va: Option<u64>,
+}
+
+impl Reference {
+    pub fn new(va: u64, prologue: Prologue) -> Self {
+        Self {
+            va,
+            prologue,
+            end_va: None,
+            data_va: None,
+        }
+    }
+
+    /// Records that the instruction at `va` references `data_va`.
+    pub fn referencing(mut self, data_va: u64) -> Self {
+        self.data_va = Some(data_va);
+        self
+    }
+
+    /// A reference to an entire function, `start_va..end_va`, whose bounds don't need resolving.
+    pub fn with_end(start_va: u64, end_va: u64) -> Self {
+        Self {
+            va: start_va,
+            prologue: Prologue::PushRun,
+            end_va: Some(end_va),
+            data_va: None,
+        }
+    }
+}
+
+/// A way of locating the function responsible for validating the integrity.
+///
+/// Only the reference needs to be found, the bounds of the function around it
+/// are resolved the same way for every strategy.
+pub trait Strategy: Debug + Send + Sync {
+    /// Identifies the strategy inside of a [`crate::PatchReport`]
+    fn name(&self) -> &'static str;
+
+    /// Finds an instruction inside of the function, using the executable
+    /// parsed by `ctx` and reporting any lengthy scans to its progress.
+    fn locate(&self, ctx: &PatchContext) -> Result<Reference>;
+}
+
+impl Strategy for Signature {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn locate(&self, ctx: &PatchContext) -> Result<Reference> {
+        locate_by_string(ctx, self.needle, self.prologue)
+    }
+}
+
+/// Locates the first code referencing `needle`, expecting the function around
+/// it to start with `prologue`.
+///
+/// The string may appear more than once (such as in both `.rdata` and a
+/// resource), so the first occurrence actually referenced from code is used.
+pub(crate) fn locate_by_string(
+    ctx: &PatchContext,
+    needle: &str,
+    prologue: Prologue,
+) -> Result<Reference> {
+    let span = trace::phase("string_search", needle);
+    let occurrences: Vec<u64> = locate_all_strings(ctx.data(), needle)
+        .into_iter()
+        .filter_map(|(file_off, _)| ctx.va_of(file_off))
+        .collect();
+    drop(span);
+    let Some(&first) = occurrences.first() else {
+        return Err(Error::StringNotFound(needle.to_string()));
+    };
+
+    let mut xrefs = ctx.find_xrefs_to_many(&occurrences)?;
+    let found = occurrences.iter().find_map(|&data_va| {
+        let va = *xrefs.remove(&data_va)?.first()?;
+        Some(Reference::new(va, prologue).referencing(data_va))
+    });
+    found.ok_or_else(|| Error::XrefNotFound {
+        data_va: first,
+        section: ctx.section_name(first).unwrap_or("no section").to_string(),
+        occurrences: occurrences.len(),
+        code_size: ctx.code_size(),
+    })
+}
+
+/// Locates the function with an IDA-style byte pattern matching code inside of it.
+#[derive(Debug, Clone)]
+pub struct BytePattern(String);
+
+impl BytePattern {
+    /// Returns [`Error::InvalidPattern`] if `pattern` is not made of hex bytes and `?`/`??` wildcards.
+    pub fn new(pattern: &str) -> Result<Self> {
+        check_pattern(pattern)?;
+        Ok(Self(pattern.to_string()))
+    }
+}
+
+impl Strategy for BytePattern {
+    fn name(&self) -> &'static str {
+        CUSTOM_PATTERN
+    }
+
+    fn locate(&self, ctx: &PatchContext) -> Result<Reference> {
+        let file_off = locate_pattern(ctx.data(), &self.0)?;
+        let va = ctx.va_of(file_off).ok_or(Error::RvaNotFound)?;
+        Ok(Reference::new(va, Prologue::PushRun))
+    }
+}
+
+/// Name reported in [`crate::PatchReport::signature`] when [`crate::PatchOptions::target`] located the function.
+pub const MANUAL_TARGET: &str = "manual-target";
+
+/// Where the user says the function is, skipping discovery entirely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ManualTarget {
+    /// A virtual address inside of the function, whose bounds are still resolved
+    Va(u64),
+    /// A file offset inside of the function, whose bounds are still resolved
+    FileOffset(usize),
+    /// The exact file offsets to patch
+    Range(Range<usize>),
+}
+
+impl Strategy for ManualTarget {
+    fn name(&self) -> &'static str {
+        MANUAL_TARGET
+    }
+
+    fn locate(&self, ctx: &PatchContext) -> Result<Reference> {
+        let to_va = |file_off: usize| ctx.va_of(file_off).ok_or(Error::RvaNotFound);
+        Ok(match self {
+            Self::Va(va) => Reference::new(*va, Prologue::PushRun),
+            Self::FileOffset(file_off) => Reference::new(to_va(*file_off)?, Prologue::PushRun),
+            // the end is exclusive, so may sit just past the section
+            Self::Range(range) => {
+                let start = to_va(range.start)?;
+                Reference::with_end(start, start + range.len() as u64)
+            }
+        })
+    }
+}
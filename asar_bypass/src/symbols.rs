@@ -0,0 +1,102 @@
+//! Locates the function by name inside of a matching PDB, which gives exact
+//! bounds instead of guessed ones.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use pdb::{FallibleIterator, PDB, SymbolData};
+use uuid::Uuid;
+
+use crate::context::PatchContext;
+use crate::signatures::Prologue;
+use crate::strategy::{Reference, Strategy};
+use crate::{Error, Result};
+
+/// Name reported in [`crate::PatchReport::signature`] when a PDB located the function.
+pub const PDB_SYMBOL: &str = "pdb-symbol";
+
+/// The symbol responsible for validating the integrity.
+const SYMBOL_NAME: &str = "ValidateIntegrityOrDie";
+
+/// Locates the function through the debug symbols inside of a PDB.
+#[derive(Debug, Clone)]
+pub struct PdbSymbol {
+    path: PathBuf,
+}
+
+impl PdbSymbol {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Strategy for PdbSymbol {
+    fn name(&self) -> &'static str {
+        PDB_SYMBOL
+    }
+
+    fn locate(&self, ctx: &PatchContext) -> Result<Reference> {
+        let pe = ctx.pe();
+        let mut pdb = PDB::open(File::open(&self.path)?)?;
+
+        // symbols from another build would point at the wrong code
+        let expected = pe
+            .debug_data
+            .as_ref()
+            .and_then(|x| x.codeview_pdb70_debug_info.as_ref())
+            .map(|x| Uuid::from_bytes_le(x.signature));
+        if let Some(guid) = expected
+            && guid != pdb.pdb_information()?.guid
+        {
+            return Err(Error::PdbMismatch(self.path.display().to_string()));
+        }
+
+        let address_map = pdb.address_map()?;
+        let to_va = |offset: pdb::PdbInternalSectionOffset| {
+            offset
+                .to_rva(&address_map)
+                .map(|rva| pe.image_base + rva.0 as u64)
+        };
+
+        // procedures carry their length, but only live inside of the module streams
+        let dbi = pdb.debug_information()?;
+        let mut modules = dbi.modules()?;
+        while let Some(module) = modules.next()? {
+            let Some(info) = pdb.module_info(&module)? else {
+                continue;
+            };
+            let mut symbols = info.symbols()?;
+            while let Some(symbol) = symbols.next()? {
+                if let Ok(SymbolData::Procedure(procedure)) = symbol.parse()
+                    && procedure.name.to_string().contains(SYMBOL_NAME)
+                    && let Some(va) = to_va(procedure.offset)
+                {
+                    debug!(
+                        "Found {} at 0x{va:x} (0x{:x} bytes) in the PDB",
+                        procedure.name, procedure.len
+                    );
+                    return Ok(Reference::with_end(va, va + procedure.len as u64));
+                }
+            }
+        }
+
+        // stripped PDBs only have public symbols, so the end still has to be found
+        let globals = pdb.global_symbols()?;
+        let mut symbols = globals.iter();
+        while let Some(symbol) = symbols.next()? {
+            if let Ok(SymbolData::Public(public)) = symbol.parse()
+                && public.function
+                && public.name.to_string().contains(SYMBOL_NAME)
+                && let Some(va) = to_va(public.offset)
+            {
+                debug!("Found public symbol {} at 0x{va:x} in the PDB", public.name);
+                return Ok(Reference::new(va, Prologue::PushRun));
+            }
+        }
+
+        Err(Error::SymbolNotFound(SYMBOL_NAME))
+    }
+}
@@ -0,0 +1,36 @@
+//! Spans around each phase when the `tracing` feature is enabled, recording
+//! how long it took once it ends. Without the feature they compile to nothing.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// A phase being timed, ended when dropped.
+pub(crate) struct PhaseSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+/// Enters a span named `phase`, with `detail` (such as the strategy) recorded on it.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn phase(phase: &'static str, detail: &str) -> PhaseSpan {
+    PhaseSpan {
+        #[cfg(feature = "tracing")]
+        span: tracing::info_span!("phase", name = phase, detail).entered(),
+        #[cfg(feature = "tracing")]
+        start: Instant::now(),
+    }
+}
+
+// always implemented, so ending a phase early with `drop` means the same with or without the feature
+impl Drop for PhaseSpan {
+    fn drop(&mut self) {
+        // still inside of the span, as it is exited after this runs
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0,
+            "phase finished"
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Detects which Electron release an executable was built from. This is only
+//! informational: it is reported alongside the patch, and doesn't change which
+//! signatures are tried.
+
+use std::fmt;
+
+use memchr::memmem;
+
+/// Precedes the version inside of the user agent Electron embeds, such as `Electron/30.1.2`.
+const USER_AGENT_PREFIX: &[u8] = b"Electron/";
+
+/// A release of Electron, such as `30.1.2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub struct ElectronVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ElectronVersion {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses `major.minor.patch` from the start of `bytes`, ignoring anything after it.
+    fn parse_prefix(bytes: &[u8]) -> Option<Self> {
+        let mut parts = [0u32; 3];
+        let mut rest = bytes;
+        for (i, part) in parts.iter_mut().enumerate() {
+            let digits = rest.iter().take_while(|x| x.is_ascii_digit()).count();
+            if digits == 0 || digits > 9 {
+                return None;
+            }
+            *part = std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+            rest = &rest[digits..];
+            if i < 2 {
+                rest = rest.strip_prefix(b".")?;
+            }
+        }
+        Some(Self::new(parts[0], parts[1], parts[2]))
+    }
+}
+
+impl fmt::Display for ElectronVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The version of Electron `data` was built from, read from the user agent
+/// embedded in every build, or `None` if it couldn't be found.
+pub fn electron_version(data: &[u8]) -> Option<ElectronVersion> {
+    memmem::find_iter(data, USER_AGENT_PREFIX)
+        .find_map(|offset| ElectronVersion::parse_prefix(&data[offset + USER_AGENT_PREFIX.len()..]))
+}
@@ -1,197 +1,229 @@
-use capstone::{arch::{self, x86::X86OperandType, ArchDetail, BuildsCapstone, DetailsArchInsn}, Capstone};
-use goblin::pe::{section_table::SectionTable, PE};
-
-use crate::{Error, Result};
-
-/// Lazily-discover xrefs to a target string VA by disassembling executable
-/// sections on demand. Yields `Result<u64, Error>` where `Ok` contains the
-/// instruction VA that references the string and `Err` is any error during
-/// scanning.
-pub struct XrefIterator<'a> {
-    data: &'a [u8],
-    cs: Capstone,
-    sections: Vec<SectionTable>,
-    image_base: u64,
-    target_va: u64,
-    is_64: bool,
-
-    // scanning state
-    section_idx: usize,
-    section_file_start: usize,
-    section_size: usize,
-    section_va_base: u64,
-    section_pos: usize,
-    finished: bool,
+use capstone::{arch::{self, x86::X86OperandType, ArchDetail, BuildsCapstone, DetailsArchInsn}, Capstone, Insn};
+use goblin::pe::PE;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::layout;
+use crate::limits::{Budget, CHECK_INTERVAL, ScanLimits};
+use crate::progress::{Phase, ProgressSink};
+use crate::{Result, trace};
+
+/// Bytes of code disassembled by each worker.
+const CHUNK_SIZE: usize = 0x10_0000;
+
+/// Instructions decoded at a time, so a chunk isn't disassembled, with every
+/// instruction's details, all at once.
+const DISASM_BATCH: usize = 0x1000;
+
+/// Bytes decoded before the start of each chunk, so the disassembly has fallen
+/// back in step with the instruction stream by the time the chunk starts.
+const CHUNK_LEAD_IN: usize = 0x40;
+
+/// A slice of an executable section, scanned by a single worker.
+struct Chunk {
+    /// Index of the section inside of the scanned sections
+    section: usize,
+    /// File offset of the start of the section
+    sect_start: usize,
+    /// File offset of the end of the section
+    sect_end: usize,
+    /// VA of the start of the section
+    va_base: u64,
+    /// File offset of the first byte in this chunk
+    begin: usize,
+    /// File offset just past the last byte in this chunk
+    end: usize,
 }
 
-impl<'a> XrefIterator<'a> {
-    /// Create a new lazy iterator for `data` and the string located at
-    /// `file_off` (a file offset inside `data`).
-    pub fn new(data: &'a [u8], file_off: usize) -> Result<XrefIterator<'a>> {
-        let pe = PE::parse(data)?;
-        let image_base = pe.image_base;
-        let sections = pe.sections.clone();
-
-        // map file_off -> RVA -> VA
-        let mut rva = None;
-        for sect in &pe.sections {
-            let ptr = sect.pointer_to_raw_data as usize;
-            let size = sect.size_of_raw_data as usize;
-            if file_off >= ptr && file_off < ptr + size {
-                let va_rva = sect.virtual_address as u64 + (file_off as u64 - ptr as u64);
-                rva = Some(va_rva as u32);
-                break;
-            }
-        }
-        let rva = match rva {
-            Some(r) => r,
-            None => {
-                return Err(Error::RvaNotFound);
-            }
-        };
-        let target_va = image_base + rva as u64;
-
-        let is_64 = pe.is_64;
-        let cs = Capstone::new()
-            .x86()
-            .mode(if is_64 {
-                arch::x86::ArchMode::Mode64
-            } else {
-                arch::x86::ArchMode::Mode32
-            })
-            .detail(true)
-            .build()?;
-
-        let mut it = XrefIterator {
-            data,
-            cs,
-            sections,
-            image_base,
-            target_va,
-            is_64,
-            section_idx: 0,
-            section_file_start: 0,
-            section_size: 0,
-            section_va_base: 0,
-            section_pos: 0,
-            finished: false,
-        };
-
-        // advance to first executable section
-        it.advance_to_next_exec_section();
-        Ok(it)
+impl Chunk {
+    fn file_off_to_va(&self, file_off: usize) -> u64 {
+        self.va_base + (file_off - self.sect_start) as u64
     }
 
-    fn advance_to_next_exec_section(&mut self) {
-        while self.section_idx < self.sections.len() {
-            let sect = &self.sections[self.section_idx];
-            self.section_idx += 1;
-            if sect.characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_EXECUTE == 0 {
-                continue;
-            }
-            let start = sect.pointer_to_raw_data as usize;
-            let size = sect.size_of_raw_data as usize;
-            if start + size > self.data.len() {
-                continue;
-            }
-            self.section_file_start = start;
-            self.section_size = size;
-            self.section_va_base = self.image_base + sect.virtual_address as u64;
-            self.section_pos = 0;
-            return;
-        }
-        self.finished = true;
+    fn va_to_file_off(&self, va: u64) -> usize {
+        self.sect_start + (va - self.va_base) as usize
     }
 }
 
-impl<'a> Iterator for XrefIterator<'a> {
-    type Item = Result<u64>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
+fn build_capstone(is_64: bool) -> Result<Capstone> {
+    Ok(Capstone::new()
+        .x86()
+        .mode(if is_64 {
+            arch::x86::ArchMode::Mode64
+        } else {
+            arch::x86::ArchMode::Mode32
+        })
+        .detail(true)
+        .build()?)
+}
 
-        loop {
-            // If we've exhausted current section, advance
-            if self.section_pos >= self.section_size {
-                self.advance_to_next_exec_section();
-                if self.finished {
-                    return None;
-                }
+/// Returns the first of `targets` referenced by an operand of `insn`, either
+/// RIP relative or as an absolute immediate.
+fn referenced(cs: &Capstone, insn: &Insn, targets: &HashSet<u64>, is_64: bool) -> Option<u64> {
+    let detail = cs.insn_detail(insn).ok()?;
+    let ArchDetail::X86Detail(x86_detail) = detail.arch_detail() else {
+        return None;
+    };
+
+    x86_detail.operands().find_map(|op| {
+        let target = match op.op_type {
+            X86OperandType::Mem(mem) if mem.base().0 == capstone::RegId(capstone_sys::x86_reg::X86_REG_RIP as u16).0 => {
+                (insn.address() as i128 + insn.bytes().len() as i128 + mem.disp() as i128) as u64
             }
+            // an absolute address, such as `lea ecx, [str]` on 32-bit
+            X86OperandType::Mem(mem) if !is_64 && mem.base().0 == 0 && mem.index().0 == 0 => (mem.disp() as u32) as u64,
+            X86OperandType::Imm(imm) if is_64 => imm as u64,
+            X86OperandType::Imm(imm) => (imm as u32) as u64,
+            _ => return None,
+        };
+        targets.contains(&target).then_some(target)
+    })
+}
 
-            let file_off = self.section_file_start + self.section_pos;
-            let code = &self.data[file_off..self.section_file_start + self.section_size];
-            if code.is_empty() {
-                self.advance_to_next_exec_section();
-                if self.finished {
-                    return None;
-                }
-                continue;
+/// Disassembles `chunk`, returning every instruction inside of it that
+/// references one of `targets`, as `(target, instruction VA)`.
+fn scan_chunk(
+    cs: &Capstone,
+    data: &[u8],
+    chunk: &Chunk,
+    targets: &HashSet<u64>,
+    is_64: bool,
+    budget: &Budget,
+) -> Result<Vec<(u64, u64)>> {
+    let begin_va = chunk.file_off_to_va(chunk.begin);
+    let end_va = chunk.file_off_to_va(chunk.end);
+    budget.spend_bytes((chunk.end - chunk.begin) as u64)?;
+
+    let mut found = Vec::new();
+    let mut decoded = 0;
+    let mut pos = chunk.begin.saturating_sub(CHUNK_LEAD_IN).max(chunk.sect_start);
+    while pos < chunk.end {
+        // nothing decodable at this position; advance by 1 to avoid infinite loop
+        let mut next = pos + 1;
+        // carries on from the last instruction decoded, whether the batch ran out or Capstone gave up
+        for insn in cs.disasm_count(&data[pos..chunk.sect_end], chunk.file_off_to_va(pos), DISASM_BATCH)?.iter() {
+            // instructions starting past the chunk belong to the next one
+            if insn.address() >= end_va {
+                next = chunk.end;
+                break;
+            }
+            next = chunk.va_to_file_off(insn.address()) + insn.bytes().len();
+            decoded += 1;
+            if decoded == CHECK_INTERVAL {
+                budget.spend_instructions(decoded)?;
+                decoded = 0;
             }
 
-            let vabase = self.section_va_base + self.section_pos as u64;
-            let insns = match self
-                .cs
-                .disasm_count(code, vabase, 1)
-                .map_err(Error::Capstone)
-                .inspect_err(|_| self.finished = true)
+            if insn.address() >= begin_va
+                && let Some(target) = referenced(cs, insn, targets, is_64)
             {
-                Ok(x) => x,
-                Err(err) => return Some(Err(err)),
-            };
-
-            if insns.len() == 0 {
-                // nothing decodable at this position; advance by 1 to avoid infinite loop
-                self.section_pos = self.section_pos.saturating_add(1);
-                continue;
+                found.push((target, insn.address()));
             }
+        }
+        pos = next;
+    }
 
-            let insn = insns.iter().next().unwrap();
-            let insn_len = insn.bytes().len();
-            // default advance
-            self.section_pos = self.section_pos.saturating_add(insn_len);
-
-            // inspect operands for references
-            let Ok(detail) = self.cs.insn_detail(&insn) else {
-                continue;
-            };
-
-            let arch_detail = detail.arch_detail();
-            let ArchDetail::X86Detail(x86_detail) = arch_detail else {
-                continue;
-            };
-
-            for op in x86_detail.operands() {
-                match op.op_type {
-                    X86OperandType::Mem(mem) => {
-                        let base = mem.base().0;
-                        let disp = mem.disp();
-                        let is_rip =
-                            base == capstone::RegId(capstone_sys::x86_reg::X86_REG_RIP as u16).0;
-                        if is_rip {
-                            let target =
-                                (insn.address() as i128 + insn_len as i128 + disp as i128) as u64;
-                            if target == self.target_va {
-                                return Some(Ok(insn.address()));
-                            }
-                        }
-                    }
-                    X86OperandType::Imm(imm) => {
-                        if self.is_64 {
-                            if imm as u64 == self.target_va {
-                                return Some(Ok(insn.address()));
-                            }
-                        } else {
-                            if (imm as u32) as u64 == self.target_va {
-                                return Some(Ok(insn.address()));
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
+    budget.spend_instructions(decoded)?;
+    Ok(found)
+}
+
+/// Finds every instruction inside of `data` (parsed as `pe`) referencing any of
+/// `target_vas` in a single pass, returning the VAs of the instructions
+/// referencing each target in ascending order.
+///
+/// Executable sections are split into chunks which are disassembled in
+/// parallel, giving up once any of `limits` is exceeded.
+pub(crate) fn find_xrefs_to_many(
+    pe: &PE,
+    data: &[u8],
+    target_vas: &[u64],
+    progress: &dyn ProgressSink,
+    limits: &ScanLimits,
+) -> Result<HashMap<u64, Vec<u64>>> {
+    let image_base = pe.image_base;
+    let is_64 = pe.is_64;
+    let targets: HashSet<u64> = target_vas.iter().copied().collect();
+
+    // raw data past the end of a truncated file is skipped, rather than the whole section
+    let sections: Vec<_> = layout::sections(pe)
+        .filter(|sect| sect.is_executable() && sect.raw_start < data.len())
+        .collect();
+    let chunks: Vec<Chunk> = sections
+        .iter()
+        .enumerate()
+        .flat_map(|(section, sect)| {
+            let sect_start = sect.raw_start;
+            let sect_end = sect.raw_end().min(data.len());
+            let va_base = image_base + sect.rva as u64;
+            (sect_start..sect_end).step_by(CHUNK_SIZE).map(move |begin| Chunk {
+                section,
+                sect_start,
+                sect_end,
+                va_base,
+                begin,
+                end: (begin + CHUNK_SIZE).min(sect_end),
+            })
+        })
+        .collect();
+
+    // track what's left of each section, to report when one is finished
+    progress.phase(Phase::ScanningXrefs);
+    let _span = trace::phase("xref_scan", "");
+    let total_bytes: u64 = chunks.iter().map(|chunk| (chunk.end - chunk.begin) as u64).sum();
+    let scanned_bytes = AtomicU64::new(0);
+    let remaining: Vec<AtomicUsize> = (0..sections.len())
+        .map(|idx| AtomicUsize::new(chunks.iter().filter(|chunk| chunk.section == idx).count()))
+        .collect();
+    let sections_done = AtomicUsize::new(0);
+    let budget = Budget::new(limits);
+    let report = |chunk: &Chunk| {
+        let done = scanned_bytes.fetch_add((chunk.end - chunk.begin) as u64, Ordering::Relaxed);
+        progress.bytes_scanned(done + (chunk.end - chunk.begin) as u64, total_bytes);
+        if remaining[chunk.section].fetch_sub(1, Ordering::Relaxed) == 1 {
+            let done = sections_done.fetch_add(1, Ordering::Relaxed) + 1;
+            let name = sections[chunk.section].header.name().unwrap_or_default();
+            progress.section_done(name, done, sections.len());
         }
+    };
+
+    // capstone handles can't be shared between threads, so each worker builds its own,
+    // after one is built here so a failure is returned instead of hit by every worker
+    #[cfg(feature = "parallel")]
+    let found = {
+        build_capstone(is_64)?;
+        chunks
+            .par_iter()
+            .map_init(
+                || build_capstone(is_64).expect("built with the same options above"),
+                |cs, chunk| {
+                    let found = scan_chunk(cs, data, chunk, &targets, is_64, &budget);
+                    report(chunk);
+                    found
+                },
+            )
+            .collect::<Result<Vec<_>>>()?
+    };
+    #[cfg(not(feature = "parallel"))]
+    let found = {
+        let cs = build_capstone(is_64)?;
+        chunks
+            .iter()
+            .map(|chunk| {
+                let found = scan_chunk(&cs, data, chunk, &targets, is_64, &budget);
+                report(chunk);
+                found
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    let mut xrefs = found.concat();
+    xrefs.sort_unstable();
+
+    let mut by_target: HashMap<u64, Vec<u64>> = HashMap::new();
+    for (target, insn_va) in xrefs {
+        by_target.entry(target).or_default().push(insn_va);
     }
-}
\ No newline at end of file
+    Ok(by_target)
+}
@@ -0,0 +1,48 @@
+/* C bindings for asar_bypass, built from the asar_bypass_ffi crate. */
+
+#ifndef ASAR_BYPASS_H
+#define ASAR_BYPASS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The call succeeded. */
+#define ASAR_BYPASS_OK 0
+/* The call failed, see asar_bypass_last_error. */
+#define ASAR_BYPASS_ERROR -1
+/* A required pointer was null. */
+#define ASAR_BYPASS_NULL -2
+
+typedef struct AsarBypassReport {
+	/* File offsets of the patched range */
+	size_t func_start;
+	size_t func_end;
+	/* RVA of the start of the patched range */
+	uint32_t rva;
+	/* VA of the instruction the function was located by */
+	uint64_t reference_va;
+	/* 0 function stub, 1 call site, 2 branch flip, 3 fuse flip */
+	int32_t method;
+	/* 0 low, 1 medium, 2 high */
+	int32_t confidence;
+} AsarBypassReport;
+
+/* Patches the executable in buf in place. out may be null. */
+int32_t asar_bypass_patch(uint8_t *buf, size_t len, AsarBypassReport *out);
+
+/* Reports what asar_bypass_patch would change, leaving buf untouched. out may be null. */
+int32_t asar_bypass_analyze(const uint8_t *buf, size_t len, AsarBypassReport *out);
+
+/* The message of the last error on this thread, or NULL if the last call succeeded.
+ * Valid until the next call on this thread. */
+const char *asar_bypass_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
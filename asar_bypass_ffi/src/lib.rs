@@ -0,0 +1,131 @@
+//! C bindings for [`asar_bypass`], so tools written in other languages can
+//! embed the bypass without shelling out to the CLI.
+//!
+//! See `include/asar_bypass.h` for the C declarations.
+
+use std::cell::RefCell;
+use std::ffi::{CString, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+
+use asar_bypass::PatchReport;
+use asar_bypass::methods::{Confidence, PatchMethod};
+
+/// Returned when the call succeeded.
+pub const ASAR_BYPASS_OK: i32 = 0;
+/// Returned when the call failed, with the reason in [`asar_bypass_last_error`].
+pub const ASAR_BYPASS_ERROR: i32 = -1;
+/// Returned when a required pointer was null.
+pub const ASAR_BYPASS_NULL: i32 = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// What was (or would be) patched, mirroring [`PatchReport`].
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsarBypassReport {
+    /// File offset of the start of the patched range
+    pub func_start: usize,
+    /// File offset of the end of the patched range
+    pub func_end: usize,
+    /// RVA of the start of the patched range
+    pub rva: u32,
+    /// VA of the instruction the function was located by
+    pub reference_va: u64,
+    /// 0 for a function stub, 1 for a call site, 2 for a branch flip and 3 for a fuse flip
+    pub method: i32,
+    /// 0 for low, 1 for medium and 2 for high
+    pub confidence: i32,
+}
+
+impl From<&PatchReport> for AsarBypassReport {
+    fn from(report: &PatchReport) -> Self {
+        Self {
+            func_start: report.func_start,
+            func_end: report.func_end,
+            rva: report.rva,
+            reference_va: report.reference_va,
+            method: match report.method {
+                PatchMethod::FunctionStub => 0,
+                PatchMethod::CallSite => 1,
+                PatchMethod::BranchFlip => 2,
+                PatchMethod::FuseFlip => 3,
+                _ => -1,
+            },
+            confidence: match report.confidence {
+                Confidence::Low => 0,
+                Confidence::Medium => 1,
+                Confidence::High => 2,
+            },
+        }
+    }
+}
+
+/// Runs `f`, recording any error or panic for [`asar_bypass_last_error`] and
+/// writing the report to `out` if it isn't null.
+fn run(out: *mut AsarBypassReport, f: impl FnOnce() -> asar_bypass::Result<PatchReport>) -> i32 {
+    let message = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(report)) => {
+            if !out.is_null() {
+                // SAFETY: the caller promises `out` is valid when it isn't null
+                unsafe { out.write(AsarBypassReport::from(&report)) };
+            }
+            LAST_ERROR.with(|x| x.borrow_mut().take());
+            return ASAR_BYPASS_OK;
+        }
+        Ok(Err(err)) => err.to_string(),
+        Err(_) => "asar_bypass panicked".to_string(),
+    };
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(message));
+    ASAR_BYPASS_ERROR
+}
+
+/// Patches the executable in `buf` in place.
+///
+/// # Safety
+///
+/// `buf` must be valid for reads and writes of `len` bytes, and `out` must be
+/// null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn asar_bypass_patch(
+    buf: *mut u8,
+    len: usize,
+    out: *mut AsarBypassReport,
+) -> i32 {
+    if buf.is_null() {
+        return ASAR_BYPASS_NULL;
+    }
+    // SAFETY: upheld by the caller
+    let data = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+    run(out, || asar_bypass::patch(data))
+}
+
+/// Reports what [`asar_bypass_patch`] would change, leaving `buf` untouched.
+///
+/// # Safety
+///
+/// `buf` must be valid for reads of `len` bytes, and `out` must be null or
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn asar_bypass_analyze(
+    buf: *const u8,
+    len: usize,
+    out: *mut AsarBypassReport,
+) -> i32 {
+    if buf.is_null() {
+        return ASAR_BYPASS_NULL;
+    }
+    // SAFETY: upheld by the caller
+    let data = unsafe { std::slice::from_raw_parts(buf, len) };
+    run(out, || asar_bypass::preview(data))
+}
+
+/// The message of the last error on this thread, or null if the last call
+/// succeeded. Valid until the next call on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn asar_bypass_last_error() -> *const c_char {
+    LAST_ERROR.with(|x| x.borrow().as_ref().map_or(ptr::null(), |x| x.as_ptr()))
+}
@@ -0,0 +1,308 @@
+//! Reads and rebuilds `app.asar` through its JSON header, so everything the
+//! patcher doesn't change, such as the files kept inside of `app.asar.unpacked`,
+//! is written back exactly as it was.
+//!
+//! An asar is a pickled header size, then the pickled header, then the data of
+//! every packed file back to back, each at its `offset` from the end of the header.
+
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::AppFiles;
+use crate::error::*;
+
+/// The block size files are hashed in, as `@electron/asar` writes it.
+const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A parsed `app.asar`, borrowing its data.
+pub struct Archive<'a> {
+    header: Value,
+    /// The header as it was pickled, which Electron's integrity check hashes
+    json: &'a [u8],
+    /// The size of the header pickle, which the header can be rewritten inside of
+    header_size: usize,
+    /// Everything after the header, which the offsets are relative to
+    data: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+    /// Parses the header of the asar in `file`.
+    pub fn parse(file: &'a [u8]) -> Result<Self> {
+        let read_u32 = |offset: usize| {
+            let bytes = file
+                .get(offset..offset + 4)
+                .ok_or(Error::AsarMalformed("truncated header"))?;
+            Ok::<_, Error>(u32::from_le_bytes(bytes.try_into().expect("four bytes")) as usize)
+        };
+
+        // the size pickle holds only the header's size, then the header pickle holds its length and the JSON
+        let header_size = read_u32(4)?;
+        let json_len = read_u32(12)?;
+        let json = file
+            .get(16..16 + json_len)
+            .ok_or(Error::AsarMalformed("header runs past the end"))?;
+        let data = file
+            .get(8 + header_size..)
+            .ok_or(Error::AsarMalformed("header runs past the end"))?;
+        let header: Value = serde_json::from_slice(json)?;
+        if !header.get("files").is_some_and(Value::is_object) {
+            return Err(Error::AsarMalformed("header has no files"));
+        }
+        Ok(Self {
+            header,
+            json,
+            header_size,
+            data,
+        })
+    }
+
+    /// The SHA256 of the header, which the executable expects when it checks the asar's integrity.
+    pub fn header_hash(&self) -> String {
+        hex(&Sha256::digest(self.json))
+    }
+
+    /// Every file packed inside of the asar, leaving out those inside of `app.asar.unpacked`.
+    pub fn files(&self) -> Result<AppFiles> {
+        let mut files = AppFiles::new();
+        for (path, entry) in entries(&self.header) {
+            if let Some(range) = packed_range(entry)? {
+                let data = self
+                    .data
+                    .get(range)
+                    .ok_or(Error::AsarMalformed("file runs past the end"))?;
+                files.insert(path, data.to_vec());
+            }
+        }
+        Ok(files)
+    }
+
+    /// The files kept inside of `app.asar.unpacked`, which are left where they are.
+    pub fn unpacked(&self) -> Vec<PathBuf> {
+        entries(&self.header)
+            .into_iter()
+            .filter(|(_, entry)| is_unpacked(entry))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Writes the asar again with `changed` replacing the data of those packed
+    /// files, keeping every other entry, including the unpacked and linked ones, as it was.
+    pub fn rebuild(&self, changed: &AppFiles) -> Result<Vec<u8>> {
+        let mut header = self.header.clone();
+        let mut data = Vec::with_capacity(self.data.len());
+        for (path, range, entry) in packed_mut(&mut header)? {
+            let offset = data.len();
+            match changed.get(&path) {
+                Some(new) => {
+                    data.extend_from_slice(new);
+                    replace(entry, new);
+                }
+                None => data.extend_from_slice(
+                    self.data
+                        .get(range)
+                        .ok_or(Error::AsarMalformed("file runs past the end"))?,
+                ),
+            }
+            entry.insert("offset".into(), offset.to_string().into());
+        }
+
+        let json = header.to_string();
+        let mut out = pickle(&json, 8 + json.len().next_multiple_of(4));
+        out.extend_from_slice(&data);
+        Ok(out)
+    }
+
+    /// Plans writing only `changed` and the header over a copy of the asar,
+    /// rather than rebuilding all of it in memory. Each file is written over its old data when it fits, or is the
+    /// last file, otherwise after everything else.
+    ///
+    /// Returns `None` when the new header no longer fits where the old one was,
+    /// as every file would have to move, so the asar has to be rebuilt instead.
+    pub fn edit_in_place<'c>(&self, changed: &'c AppFiles) -> Result<Option<InPlaceEdit<'c>>> {
+        let mut header = self.header.clone();
+        let mut end = self.data.len();
+        let mut writes = Vec::new();
+        for (path, range, entry) in packed_mut(&mut header)? {
+            let Some(new) = changed.get(&path) else {
+                continue;
+            };
+            let offset = match new.len() <= range.len() || range.end >= end {
+                true => range.start,
+                false => end,
+            };
+            end = end.max(offset + new.len());
+            replace(entry, new);
+            entry.insert("offset".into(), offset.to_string().into());
+            writes.push((8 + self.header_size + offset, new.as_slice()));
+        }
+
+        let json = header.to_string();
+        if 8 + json.len() > self.header_size {
+            return Ok(None);
+        }
+        Ok(Some(InPlaceEdit {
+            header: pickle(&json, self.header_size),
+            header_hash: hex(&Sha256::digest(&json)),
+            writes,
+        }))
+    }
+}
+
+/// The writes editing an asar in place, from [`Archive::edit_in_place`].
+pub struct InPlaceEdit<'c> {
+    /// The pickled header, written at the start
+    header: Vec<u8>,
+    /// The hash of the new header, as [`Archive::header_hash`]
+    pub header_hash: String,
+    /// The data of each changed file, by where it is written in the asar
+    writes: Vec<(usize, &'c [u8])>,
+}
+
+impl InPlaceEdit<'_> {
+    /// Writes the edit over the asar at `path`, which has to be the one it was planned from.
+    ///
+    /// The writes go to a copy of the asar which is then renamed over it, as
+    /// they overwrite data the old header still points at.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        asar_bypass::edit_file(path, |file| {
+            for (offset, data) in &self.writes {
+                file.seek(SeekFrom::Start(*offset as u64))?;
+                file.write_all(data)?;
+            }
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&self.header)
+        })?;
+        Ok(())
+    }
+}
+
+/// A packed file, by its path, where its data is, and its entry inside of the header.
+type PackedEntry<'h> = (PathBuf, Range<usize>, &'h mut Map<String, Value>);
+
+/// Every packed file inside of `header`, in the order it is laid out.
+fn packed_mut(header: &mut Value) -> Result<Vec<PackedEntry<'_>>> {
+    let mut packed = Vec::new();
+    for (path, entry) in entries_mut(header) {
+        if let Some(range) = packed_range(entry)? {
+            packed.push((path, range, entry));
+        }
+    }
+    // in the order they were, so an untouched asar comes out the same
+    packed.sort_by_key(|(_, range, _)| range.start);
+    Ok(packed)
+}
+
+/// Points `entry` at `data`, leaving where it is to the caller.
+fn replace(entry: &mut Map<String, Value>, data: &[u8]) {
+    entry.insert("size".into(), data.len().into());
+    let block_size = entry
+        .get("integrity")
+        .and_then(|x| x.get("blockSize"))
+        .and_then(Value::as_u64)
+        .map_or(DEFAULT_BLOCK_SIZE, |x| x as usize);
+    entry.insert("integrity".into(), integrity(data, block_size));
+}
+
+/// Every file, link and unpacked file below the root of `header`, by its path inside of the asar.
+fn entries(header: &Value) -> Vec<(PathBuf, &Map<String, Value>)> {
+    fn walk<'a>(dir: &'a Value, path: &Path, out: &mut Vec<(PathBuf, &'a Map<String, Value>)>) {
+        let Some(Value::Object(files)) = dir.get("files") else {
+            return;
+        };
+        for (name, entry) in files {
+            let path = path.join(name);
+            match entry {
+                Value::Object(x) if x.contains_key("files") => walk(entry, &path, out),
+                Value::Object(x) => out.push((path, x)),
+                _ => {}
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(header, Path::new(""), &mut out);
+    out
+}
+
+/// [`entries`], mutably.
+fn entries_mut(header: &mut Value) -> Vec<(PathBuf, &mut Map<String, Value>)> {
+    fn walk<'a>(
+        dir: &'a mut Value,
+        path: &Path,
+        out: &mut Vec<(PathBuf, &'a mut Map<String, Value>)>,
+    ) {
+        let Some(Value::Object(files)) = dir.get_mut("files") else {
+            return;
+        };
+        for (name, entry) in files.iter_mut() {
+            let path = path.join(name);
+            if entry.get("files").is_some() {
+                walk(entry, &path, out);
+            } else if let Value::Object(x) = entry {
+                out.push((path, x));
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(header, Path::new(""), &mut out);
+    out
+}
+
+fn is_unpacked(entry: &Map<String, Value>) -> bool {
+    entry
+        .get("unpacked")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Where the data of `entry` is, or `None` if it isn't packed inside of the asar.
+fn packed_range(entry: &Map<String, Value>) -> Result<Option<Range<usize>>> {
+    if is_unpacked(entry) || entry.contains_key("link") {
+        return Ok(None);
+    }
+    let offset = entry
+        .get("offset")
+        .and_then(Value::as_str)
+        .and_then(|x| x.parse::<usize>().ok())
+        .ok_or(Error::AsarMalformed("file has no offset"))?;
+    let size = entry
+        .get("size")
+        .and_then(Value::as_u64)
+        .ok_or(Error::AsarMalformed("file has no size"))?;
+    Ok(Some(offset..offset + size as usize))
+}
+
+/// The SHA256 of `data`, whole and in blocks, which Electron checks files against.
+fn integrity(data: &[u8], block_size: usize) -> Value {
+    let blocks: Vec<Value> = data
+        .chunks(block_size.max(1))
+        .map(|x| hex(&Sha256::digest(x)).into())
+        .collect();
+    serde_json::json!({
+        "algorithm": "SHA256",
+        "hash": hex(&Sha256::digest(data)),
+        "blockSize": block_size,
+        "blocks": blocks,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// Pickles the header `json` into `header_size` bytes, then the size of those in front of it.
+fn pickle(json: &str, header_size: usize) -> Vec<u8> {
+    // the header pickle is its payload size, the string's length, then the string padded out
+    let payload = header_size - 4;
+    let mut out = Vec::with_capacity(8 + header_size);
+    out.extend(4u32.to_le_bytes());
+    out.extend((header_size as u32).to_le_bytes());
+    out.extend((payload as u32).to_le_bytes());
+    out.extend((json.len() as u32).to_le_bytes());
+    out.extend(json.as_bytes());
+    out.resize(8 + header_size, 0);
+    out
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use oxc::allocator::Allocator;
+use oxc::ast::ast::*;
+use oxc::ast_visit::{Visit, walk};
+use oxc::parser::Parser;
+use oxc::span::{SourceType, Span};
+use oxc::syntax::scope::ScopeFlags;
+
+use crate::main_js::mentions_patches;
+use crate::manifest::Manifest;
+
+/// How many bytes of each region are kept, so the report stays small enough to attach to an issue.
+const MAX_REGION_LEN: usize = 8000;
+
+/// The regions of a script the patches look at, found loosely (by what they
+/// mention, not their shape) so they are still found when the patches aren't.
+struct Regions<'s> {
+    source: &'s str,
+    manifest: &'s Manifest,
+    /// Every `const x = require("...")`
+    requires: Vec<Span>,
+    /// The innermost function mentioning each patch
+    entitlements: Option<Span>,
+    installed: Option<Span>,
+    launch: Option<Span>,
+}
+
+impl<'s> Regions<'s> {
+    fn text(&self, span: Span) -> &'s str {
+        &self.source[span.start as usize..span.end as usize]
+    }
+
+    /// Records the function at `span` for each patch it mentions everything of,
+    /// keeping the smallest.
+    fn record_function(&mut self, span: Span) {
+        let text = self.text(span);
+        let smallest = |slot: &mut Option<Span>| {
+            if slot.is_none_or(|x| x.size() > span.size()) {
+                *slot = Some(span);
+            }
+        };
+        let manifest = self.manifest;
+        if manifest
+            .entitlements
+            .iter()
+            .any(|x| x.guard.iter().all(|x| text.contains(x.as_str())))
+        {
+            smallest(&mut self.entitlements);
+        }
+        if manifest
+            .installed
+            .iter()
+            .any(|x| text.contains(&x.property) && text.contains(&x.sibling))
+        {
+            smallest(&mut self.installed);
+        }
+        if manifest.launch.iter().any(|x| text.contains(&x.url_prefix)) {
+            smallest(&mut self.launch);
+        }
+    }
+}
+
+impl<'a> Visit<'a> for Regions<'_> {
+    fn visit_variable_declaration(&mut self, it: &VariableDeclaration<'a>) {
+        let requires = it.declarations.iter().any(|x| {
+            let Some(Expression::CallExpression(call)) =
+                x.init.as_ref().map(|x| x.without_parentheses())
+            else {
+                return false;
+            };
+            matches!(&call.callee, Expression::Identifier(callee) if callee.name == "require")
+                && matches!(&call.arguments[..], [Argument::StringLiteral(_)])
+        });
+        if requires {
+            self.requires.push(it.span);
+        }
+        walk::walk_variable_declaration(self, it);
+    }
+
+    fn visit_function(&mut self, it: &Function<'a>, flags: ScopeFlags) {
+        self.record_function(it.span);
+        walk::walk_function(self, it, flags);
+    }
+
+    fn visit_arrow_function_expression(&mut self, it: &ArrowFunctionExpression<'a>) {
+        self.record_function(it.span);
+        walk::walk_arrow_function_expression(self, it);
+    }
+}
+
+/// Writes the regions of `entry`, and every other script the patches may be in,
+/// that the patches look at into a Markdown report to attach to a bug report.
+pub fn report(scripts: &BTreeMap<PathBuf, String>, entry: &Path, manifest: &Manifest) -> String {
+    let mut out = format!(
+        "# jackbox_megapicker_patcher v{} capture\n\nEntry: `{}`, manifest version {}\n",
+        env!("CARGO_PKG_VERSION"),
+        entry.display(),
+        manifest.version
+    );
+    let order = scripts
+        .get_key_value(entry)
+        .into_iter()
+        .chain(scripts.iter().filter(|(path, _)| *path != entry));
+    for (path, script) in order {
+        if path != entry && !mentions_patches(script, manifest) {
+            continue;
+        }
+        let _ = write!(out, "\n## `{}`\n", path.display());
+
+        let allocator = Allocator::default();
+        let parsed = Parser::new(&allocator, script.as_str(), SourceType::cjs()).parse();
+        if let Some(err) = parsed.errors.first() {
+            let _ = writeln!(out, "\nCould not parse: {err}");
+            continue;
+        }
+        let mut regions = Regions {
+            source: script,
+            manifest,
+            requires: Vec::new(),
+            entitlements: None,
+            installed: None,
+            launch: None,
+        };
+        regions.visit_program(&parsed.program);
+
+        let requires = regions.requires.first().map(|x| {
+            let text = regions
+                .requires
+                .iter()
+                .map(|x| regions.text(*x))
+                .collect::<Vec<_>>();
+            (*x, text.join("\n"))
+        });
+        let sections = [
+            ("requires", requires),
+            (
+                "entitlements",
+                regions
+                    .entitlements
+                    .map(|x| (x, regions.text(x).to_string())),
+            ),
+            (
+                "installed",
+                regions.installed.map(|x| (x, regions.text(x).to_string())),
+            ),
+            (
+                "launch",
+                regions.launch.map(|x| (x, regions.text(x).to_string())),
+            ),
+        ];
+        for (name, region) in sections {
+            match region {
+                Some((span, text)) => {
+                    let _ = write!(
+                        out,
+                        "\n### {name} (from {})\n\n```js\n{}\n```\n",
+                        span.start,
+                        truncate(&text)
+                    );
+                }
+                None => {
+                    let _ = write!(out, "\n### {name}\n\nNot found.\n");
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Cuts `text` down to [`MAX_REGION_LEN`], saying so if it was.
+fn truncate(text: &str) -> String {
+    if text.len() <= MAX_REGION_LEN {
+        return text.to_string();
+    }
+    let end = (0..=MAX_REGION_LEN)
+        .rev()
+        .find(|x| text.is_char_boundary(*x))
+        .unwrap_or(0);
+    format!(
+        "{}\n/* truncated {} bytes */",
+        &text[..end],
+        text.len() - end
+    )
+}
+
+/// Replaces each of `paths` inside of `report` with its placeholder, as it is
+/// written or escaped inside of a JS string, so the report doesn't give away
+/// the user's name or layout of their drive.
+pub fn anonymize(report: &str, paths: &[(&Path, &str)]) -> String {
+    let mut paths = paths.to_vec();
+    // the longest first, so a games directory inside of the install is replaced whole
+    paths.sort_by_key(|(path, _)| std::cmp::Reverse(path.as_os_str().len()));
+
+    let mut out = report.to_string();
+    for (path, placeholder) in paths {
+        let path = path.to_string_lossy();
+        if path.len() < 2 {
+            continue;
+        }
+        for form in [
+            path.replace('\\', "\\\\"),
+            path.to_string(),
+            path.replace('\\', "/"),
+        ] {
+            out = out.replace(&form, placeholder);
+        }
+    }
+    out
+}
@@ -0,0 +1,176 @@
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+/// The file name of the config, which lives next to the patcher.
+const CONFIG_NAME: &str = "jackbox_megapicker_patcher.toml";
+
+/// How packs installed through Steam are brought into the games directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GamesMode {
+    /// Link to the Steam install, so the pack stays up to date
+    #[default]
+    Link,
+    /// Copy the Steam install, so the pack keeps working if it is uninstalled
+    Copy,
+}
+
+/// How games that exist inside of the games directory are launched.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchPreference {
+    /// Run the executable inside of the games directory directly
+    #[default]
+    Local,
+    /// Always launch through Steam
+    Steam,
+}
+
+/// Snippets of JS injected into `main.js` at fixed points, to extend what the
+/// patches do. They may use the `{{name}}` placeholders of the patch they follow,
+/// and are only injected alongside that patch.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// Runs after the games inside of the games directory are marked as owned
+    pub after_entitlements: Option<String>,
+    /// Runs after each game's installation state is worked out
+    pub after_install_check: Option<String>,
+    /// Runs before a game is launched
+    pub before_launch: Option<String>,
+}
+
+/// Choices persisted between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where games are kept, instead of `./games` inside the install
+    pub games_dir: Option<PathBuf>,
+    /// How packs installed through Steam are brought into the games directory
+    pub games_mode: GamesMode,
+    /// How games inside of the games directory are launched
+    pub launch: LaunchPreference,
+    /// Where backups are written, instead of next to the originals
+    pub backups_dir: Option<PathBuf>,
+    /// The Steam IDs shown as owned, instead of the games inside of the games directory
+    pub spoof: Option<Vec<u32>>,
+    /// Snippets injected into `main.js`, only set by editing the config
+    pub hooks: Hooks,
+}
+
+impl Config {
+    /// Where the config is stored.
+    fn path() -> Result<PathBuf> {
+        let exe = std::env::current_exe()?;
+        Ok(exe.with_file_name(CONFIG_NAME))
+    }
+
+    /// Loads the config, returning `None` if this is the first run.
+    pub fn load() -> Result<Option<Self>> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Writes the config to disk.
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        std::fs::write(&path, toml::to_string_pretty(self)?)?;
+        info!("Saved config to {}", path.display());
+        Ok(())
+    }
+
+    /// Loads the config, walking the user through creating it on the first run.
+    ///
+    /// Nothing is asked when there is no terminal to ask on, the defaults are used instead.
+    pub fn load_or_onboard(reconfigure: bool) -> Result<Self> {
+        let existing = Self::load()?;
+        if !reconfigure && let Some(config) = existing {
+            return Ok(config);
+        }
+        if !std::io::stdin().is_terminal() {
+            return Ok(existing.unwrap_or_default());
+        }
+
+        let config = Self::onboard(existing.unwrap_or_default())?;
+        config.save()?;
+        Ok(config)
+    }
+
+    /// Asks the user for each choice, keeping what isn't asked about (like the hooks and the spoofed games) from `existing`.
+    fn onboard(existing: Self) -> Result<Self> {
+        println!(
+            "Looks like this is your first time running the patcher, so let's set a few things up."
+        );
+        println!("Press enter to accept the default shown in brackets.\n");
+
+        println!(
+            "Steam's \"Verify integrity of game files\" can delete games kept inside of the Megapicker install."
+        );
+        let games_dir = prompt(
+            "Where should your games be kept? Leave empty for ./games inside the install",
+            "",
+        )?;
+
+        let games_mode = prompt_choice(
+            "Should packs installed through Steam be linked (stays up to date) or copied (survives uninstalling)?",
+            &[("link", GamesMode::Link), ("copy", GamesMode::Copy)],
+        )?;
+
+        let launch = prompt_choice(
+            "Should games be launched directly from the games directory, or always through Steam?",
+            &[
+                ("local", LaunchPreference::Local),
+                ("steam", LaunchPreference::Steam),
+            ],
+        )?;
+
+        let backups_dir = prompt(
+            "Where should backups be written? Leave empty to keep them next to the originals",
+            "",
+        )?;
+
+        Ok(Self {
+            games_dir: (!games_dir.is_empty()).then(|| PathBuf::from(games_dir)),
+            games_mode,
+            launch,
+            backups_dir: (!backups_dir.is_empty()).then(|| PathBuf::from(backups_dir)),
+            ..existing
+        })
+    }
+}
+
+/// Asks `question`, returning `default` if nothing was entered.
+fn prompt(question: &str, default: &str) -> Result<String> {
+    print!("{question} [{default}]: ");
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let line = line.trim();
+    Ok(if line.is_empty() { default } else { line }.to_string())
+}
+
+/// Asks `question` until one of `choices` is entered, the first being the default.
+fn prompt_choice<T: Copy>(question: &str, choices: &[(&str, T)]) -> Result<T> {
+    let names: Vec<&str> = choices.iter().map(|(name, _)| *name).collect();
+    loop {
+        let answer = prompt(&format!("{question} ({})", names.join("/")), names[0])?;
+        if let Some((_, value)) = choices
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&answer))
+        {
+            return Ok(*value);
+        }
+        println!("Please enter one of: {}", names.join(", "));
+    }
+}
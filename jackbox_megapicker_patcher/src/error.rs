@@ -1,7 +1,5 @@
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error(transparent)]
-    Asar(#[from] asar::Error),
     #[error(transparent)]
     AsarBypass(#[from] asar_bypass::Error),
     #[error(transparent)]
@@ -10,17 +8,39 @@ pub enum Error {
     IO(#[from] std::io::Error),
     #[error(transparent)]
     Steamworks(#[from] steamworks::SteamAPIInitError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
 
+    #[error("malformed app.asar: {0}")]
+    AsarMalformed(&'static str),
+    #[error("could not find the app.asar hash inside of the executable")]
+    IntegrityHashNotFound,
+    #[error("the executable holds a {expected} character app.asar hash, not one of {found}")]
+    IntegrityHashLength { expected: usize, found: usize },
     #[error("main.js not found in asar")]
     MainJsNotFound,
-    #[error("could not regex match all the requires")]
+    #[error("no backup of app.asar at {}", .0.display())]
+    BackupNotFound(std::path::PathBuf),
+    #[error("could not parse main.js: {0}")]
+    MainJsParse(String),
+    #[error("the patch manifest is missing a module to require")]
     RequireMatch,
-    #[error("could not regex match the entitlements")]
+    #[error("could not find the entitlements check")]
     EntitlementsMatch,
-    #[error("could not regex match the installation check")]
+    #[error("could not find the installation check")]
     InstallationMatch,
-    #[error("could not regex match the launch behaviour")]
+    #[error("could not find the launch behaviour")]
     LaunchMatch,
+    #[error("main.js was already patched by v{0}, pass --from-backup to patch the original again")]
+    AlreadyPatched(String),
+    #[error("unsupported patch manifest version {0}, expected {1}")]
+    ManifestVersion(u32, u32),
+    #[error("unknown placeholder {{{{{0}}}}} inside of the patch manifest")]
+    UnknownPlaceholder(String),
 }
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
\ No newline at end of file
@@ -0,0 +1,444 @@
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{MEGAPICKER_APP_ID, config::GamesMode, error::*, steam};
+
+/// The file inside of the games directory that records what was linked.
+const LINKS_NAME: &str = "links.toml";
+
+/// A pack brought into the games directory from its Steam install.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkRecord {
+    pub name: String,
+    /// The Steam install the pack came from
+    pub source: PathBuf,
+    /// The Steam build ID of the source at the time it was linked
+    pub build_id: String,
+    pub mode: GamesMode,
+}
+
+/// Every linked pack, keyed by Steam ID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Links {
+    #[serde(flatten)]
+    pub records: BTreeMap<String, LinkRecord>,
+}
+
+impl Links {
+    pub fn load(games_dir: &Path) -> Result<Self> {
+        let path = games_dir.join(LINKS_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, games_dir: &Path) -> Result<()> {
+        std::fs::write(games_dir.join(LINKS_NAME), toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The file inside of the games directory pointing games at folders outside of it.
+const DIRS_NAME: &str = "games.toml";
+
+/// Where each game kept outside of the games directory is, keyed by Steam ID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GameDirs {
+    #[serde(flatten)]
+    pub dirs: BTreeMap<String, PathBuf>,
+}
+
+impl GameDirs {
+    /// Loads the folders, with relative ones resolved against `games_dir`.
+    pub fn load(games_dir: &Path) -> Result<Self> {
+        let path = games_dir.join(DIRS_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut dirs: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        for dir in dirs.dirs.values_mut() {
+            *dir = std::path::absolute(games_dir.join(&dir))?;
+        }
+        Ok(dirs)
+    }
+}
+
+/// The file Steamworks reads the app ID from, when a game isn't launched by Steam.
+const STEAM_APPID_NAME: &str = "steam_appid.txt";
+
+/// How many folders deep inside of a pack its executables are looked for, as the launch patch does.
+const EXE_SEARCH_DEPTH: usize = 3;
+
+/// The Steam API copied into packs without one.
+const STEAM_API_NAME: &str = "steam_api64.dll";
+
+/// A pack shipping any of these already has a Steam API.
+const STEAM_API_NAMES: [&str; 3] = ["steam_api64.dll", "steam_api.dll", "libsteam_api.so"];
+
+/// The Visual C++ runtime the packs are built against.
+#[cfg(windows)]
+const VC_RUNTIME_NAMES: [&str; 2] = ["vcruntime140.dll", "msvcp140.dll"];
+
+/// The extensions of the executables a pack may be launched from.
+const EXE_EXTENSIONS: [&str; 3] = ["exe", "x86_64", "x86"];
+
+/// Every pack inside of `games_dir`, or pointed elsewhere by its `games.toml`, with its Steam ID.
+fn packs(games_dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+    let mut packs = Vec::new();
+    if games_dir.exists() {
+        for entry in std::fs::read_dir(games_dir)? {
+            let entry = entry?;
+            if let Some(app_id) = entry.file_name().to_str().and_then(|x| x.parse().ok())
+                && entry.path().is_dir()
+            {
+                packs.push((app_id, entry.path()));
+            }
+        }
+    }
+    for (app_id, dir) in GameDirs::load(games_dir)?.dirs {
+        if let Ok(app_id) = app_id.parse()
+            && dir.is_dir()
+        {
+            packs.push((app_id, dir));
+        }
+    }
+    Ok(packs)
+}
+
+/// Whether `path` is a symlink, or a junction on Windows, like the packs linked in from Steam.
+fn is_link(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_symlink())
+}
+
+/// Collects the folders inside of `dir` holding an executable, not following links.
+///
+/// A folder that can't be read is logged and skipped, so one doesn't stop the rest being fixed.
+fn exe_folders(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return warn!("Skipped {}: {err}", dir.display()),
+    };
+    let mut has_exe = false;
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                warn!("Skipped an entry inside of {}: {err}", dir.display());
+                continue;
+            }
+        };
+        if is_link(&path) {
+            continue;
+        }
+        if path.is_dir() && depth < EXE_SEARCH_DEPTH {
+            exe_folders(&path, depth + 1, out);
+        } else if path
+            .extension()
+            .is_some_and(|x| EXE_EXTENSIONS.iter().any(|ext| x.eq_ignore_ascii_case(ext)))
+        {
+            has_exe = true;
+        }
+    }
+    if has_exe {
+        out.push(dir.to_path_buf());
+    }
+}
+
+/// Whether a file named any of `names` is inside of `dir`, searching as deep as the executables are.
+fn contains_file(dir: &Path, names: &[&str], depth: usize) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        match path.is_dir() {
+            true => depth < EXE_SEARCH_DEPTH && contains_file(&path, names, depth + 1),
+            false => entry
+                .file_name()
+                .to_str()
+                .is_some_and(|x| names.iter().any(|name| x.eq_ignore_ascii_case(name))),
+        }
+    })
+}
+
+/// Fixes what stops packs from starting outside of Steam, reporting what was fixed:
+/// - a `steam_appid.txt` is written beside the executables of every pack missing
+///   one, as packs set up Steamworks, and fail to without it
+/// - `steam_api64.dll` is copied from beside the patcher, or the Megapicker, into
+///   packs without a Steam API at all
+/// - the Visual C++ runtime the packs need is checked for, as it can't be copied in
+///
+/// Packs linked in from Steam are left alone, as writing into them writes into the Steam install.
+pub fn fix(app_path: &Path, games_dir: &Path) -> Result<()> {
+    let steam_api = [
+        std::env::current_exe()?.with_file_name(STEAM_API_NAME),
+        app_path.join(STEAM_API_NAME),
+    ]
+    .into_iter()
+    .find(|x| x.is_file());
+    let mut fixed = Vec::new();
+
+    for (app_id, dir) in packs(games_dir)? {
+        if is_link(&dir) {
+            info!("Skipped {app_id}, as it is linked to {}", dir.display());
+            continue;
+        }
+        let mut folders = Vec::new();
+        exe_folders(&dir, 0, &mut folders);
+        if folders.is_empty() {
+            folders.push(dir.clone());
+        }
+
+        for folder in &folders {
+            let path = folder.join(STEAM_APPID_NAME);
+            if path.exists() {
+                continue;
+            }
+            std::fs::write(&path, app_id.to_string())?;
+            fixed.push(format!("Wrote {}", path.display()));
+        }
+
+        if contains_file(&dir, &STEAM_API_NAMES, 0) {
+            continue;
+        }
+        match &steam_api {
+            Some(steam_api) => {
+                for folder in &folders {
+                    std::fs::copy(steam_api, folder.join(STEAM_API_NAME))?;
+                    fixed.push(format!("Copied {STEAM_API_NAME} into {}", folder.display()));
+                }
+            }
+            None => warn!(
+                "{app_id} has no {STEAM_API_NAME}, and there is none beside the patcher to copy in"
+            ),
+        }
+    }
+
+    #[cfg(windows)]
+    if let Some(root) = std::env::var_os("SystemRoot") {
+        let system = PathBuf::from(root).join("System32");
+        for dll in VC_RUNTIME_NAMES
+            .iter()
+            .filter(|x| !system.join(x).is_file())
+        {
+            warn!(
+                "{dll} is missing, install the latest Visual C++ Redistributable (x64) from Microsoft for packs to start"
+            );
+        }
+    }
+
+    match fixed.is_empty() {
+        true => info!("Nothing to fix inside of the games directory"),
+        false => {
+            for line in &fixed {
+                info!("{line}");
+            }
+            info!(
+                "Fixed {} thing(s) inside of the games directory",
+                fixed.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Creates a directory link at `link` pointing to `target`.
+fn link_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    #[cfg(windows)]
+    return junction::create(target, link);
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+}
+
+/// Brings `source` into `dest`, either by linking or copying it.
+fn bring_in(source: &Path, dest: &Path, mode: GamesMode) -> Result<()> {
+    match mode {
+        GamesMode::Link => link_dir(source, dest)?,
+        GamesMode::Copy => copy_dir_all(source, dest)?,
+    }
+    Ok(())
+}
+
+/// Recursively copies the directory `from` into `to`.
+pub fn copy_dir_all(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves every game inside `from` into `to`, removing `from` once it is empty.
+///
+/// Games that already exist in `to` are left where they are.
+pub fn migrate(from: &Path, to: &Path) -> Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if target.exists() {
+            warn!(
+                "Not migrating {}, it already exists in {}",
+                entry.path().display(),
+                to.display()
+            );
+            continue;
+        }
+
+        // Renaming fails across drives, so fall back to copying
+        if std::fs::rename(entry.path(), &target).is_err() {
+            if entry.file_type()?.is_dir() {
+                copy_dir_all(&entry.path(), &target)?;
+                std::fs::remove_dir_all(entry.path())?;
+            } else {
+                std::fs::copy(entry.path(), &target)?;
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        info!(
+            "Migrated {} to {}",
+            entry.path().display(),
+            target.display()
+        );
+    }
+
+    // Only remove the old directory if everything was moved out of it
+    if std::fs::read_dir(from)?.next().is_none() {
+        std::fs::remove_dir(from)?;
+    }
+
+    Ok(())
+}
+
+/// Links (or copies) every Jackbox game installed through Steam into `games_dir`,
+/// recording the build of each so [`verify`] can tell when a copy goes stale.
+pub fn link(app_path: &Path, games_dir: &Path, mode: GamesMode) -> Result<()> {
+    std::fs::create_dir_all(games_dir)?;
+    let mut links = Links::load(games_dir)?;
+
+    let apps = steam::installed_apps(app_path)
+        .into_iter()
+        .filter(|app| app.app_id != MEGAPICKER_APP_ID && app.name.contains("Jackbox"));
+    for app in apps {
+        let dest = games_dir.join(app.app_id.to_string());
+        if dest.exists() {
+            info!(
+                "Skipping {} ({}), it is already inside of the games directory",
+                app.name, app.app_id
+            );
+            continue;
+        }
+
+        bring_in(&app.install_dir, &dest, mode)?;
+        info!(
+            "Brought in {} ({}) at build {}",
+            app.name, app.app_id, app.build_id
+        );
+        links.records.insert(
+            app.app_id.to_string(),
+            LinkRecord {
+                name: app.name,
+                source: app.install_dir,
+                build_id: app.build_id,
+                mode,
+            },
+        );
+    }
+
+    links.save(games_dir)
+}
+
+/// Warns about every pack whose Steam install has been updated since it was
+/// linked, offering to refresh stale copies.
+pub fn verify(games_dir: &Path) -> Result<()> {
+    let mut links = Links::load(games_dir)?;
+    let interactive = std::io::stdin().is_terminal();
+
+    for (app_id, record) in links.records.iter_mut() {
+        let Some(steamapps) = record.source.parent().and_then(Path::parent) else {
+            continue;
+        };
+        let Some(installed) = app_id
+            .parse()
+            .ok()
+            .and_then(|id| steam::read_manifest(steamapps, id))
+        else {
+            warn!(
+                "{} ({app_id}) is no longer installed through Steam",
+                record.name
+            );
+            continue;
+        };
+        if installed.build_id == record.build_id {
+            continue;
+        }
+
+        // Links always point at the latest files, so only the record is outdated
+        if record.mode == GamesMode::Link {
+            record.build_id = installed.build_id;
+            continue;
+        }
+
+        warn!(
+            "{} ({app_id}) was updated through Steam (build {} -> {}), but the copy inside of the games directory was not",
+            record.name, record.build_id, installed.build_id
+        );
+        if !interactive {
+            continue;
+        }
+        print!("Refresh the copy of {}? [y/N]: ", record.name);
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            continue;
+        }
+
+        let dest = games_dir.join(app_id);
+        std::fs::remove_dir_all(&dest)?;
+        copy_dir_all(&installed.install_dir, &dest)?;
+        record.build_id = installed.build_id;
+        info!("Refreshed {} ({app_id})", record.name);
+    }
+
+    links.save(games_dir)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fix_skips_linked_packs() {
+        let root = std::env::temp_dir().join(format!("megapicker_fix_{}", std::process::id()));
+        let steam = root.join("steam");
+        let games_dir = root.join("games");
+        std::fs::create_dir_all(&steam).unwrap();
+        std::fs::create_dir_all(&games_dir).unwrap();
+        std::fs::write(steam.join("Game.exe"), b"").unwrap();
+        std::fs::write(root.join(STEAM_API_NAME), b"").unwrap();
+        link_dir(&steam, &games_dir.join("1234")).unwrap();
+
+        fix(&root, &games_dir).unwrap();
+        let written = [STEAM_APPID_NAME, STEAM_API_NAME].map(|x| steam.join(x).exists());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(written, [false, false]);
+    }
+}
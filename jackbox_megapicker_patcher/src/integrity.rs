@@ -0,0 +1,90 @@
+//! Updates the hash of `app.asar`'s header the Megapicker checks it against, so
+//! the integrity check can stay on rather than being stubbed out of the executable.
+//!
+//! Electron keeps the hashes inside of the executable's `ELECTRONASAR` resource,
+//! as JSON like `[{"file":"resources\\app.asar","alg":"SHA256","value":"..."}]`.
+
+use std::ops::Range;
+use std::path::Path;
+
+use log::info;
+use memchr::memmem;
+use serde::Deserialize;
+
+use crate::error::*;
+
+/// What the resource starts with.
+const RESOURCE_START: &[u8] = b"[{\"file\":";
+
+/// How far the end of the resource is looked for, as it only lists a file or two.
+const MAX_RESOURCE_LEN: usize = 4096;
+
+/// The file name of the backup of the executable, written before its hash is first updated.
+const EXECUTABLE_BACKUP_NAME: &str = "The Jackbox Megapicker.exe.bak";
+
+#[derive(Deserialize)]
+struct Entry {
+    file: String,
+    alg: String,
+    value: String,
+}
+
+/// Where the hash of `app.asar` is inside of `exe`, alongside the hash itself.
+pub fn find_hash(exe: &[u8]) -> Result<(Range<usize>, String)> {
+    for start in memmem::find_iter(exe, RESOURCE_START) {
+        let rest = &exe[start..exe.len().min(start + MAX_RESOURCE_LEN)];
+        let Some(end) = rest.iter().position(|x| *x == b']') else {
+            continue;
+        };
+        let Ok(entries) = serde_json::from_slice::<Vec<Entry>>(&rest[..=end]) else {
+            continue;
+        };
+        let Some(entry) = entries.iter().find(|x| {
+            x.alg == "SHA256"
+                && !x.value.is_empty()
+                && x.file
+                    .replace('/', "\\")
+                    .eq_ignore_ascii_case("resources\\app.asar")
+        }) else {
+            continue;
+        };
+
+        // the hash is only ever hex, so it is written inside of the resource as it is
+        let file = serde_json::to_string(&entry.file)?;
+        let after = memmem::find(&rest[..end], file.as_bytes()).unwrap_or(0);
+        if let Some(at) = memmem::find(&rest[after..end], entry.value.as_bytes()) {
+            let at = start + after + at;
+            return Ok((at..at + entry.value.len(), entry.value.clone()));
+        }
+    }
+    Err(Error::IntegrityHashNotFound)
+}
+
+/// Replaces the hash of `app.asar` inside of the executable at `exe_path` with
+/// `hash`, backing the executable up to `backups_dir` the first time.
+pub fn update_hash(exe_path: &Path, hash: &str, backups_dir: &Path) -> Result<()> {
+    let mut exe = std::fs::read(exe_path)?;
+    let (range, old) = find_hash(&exe)?;
+    if old.eq_ignore_ascii_case(hash) {
+        info!("The executable already expects the patched app.asar");
+        return Ok(());
+    }
+    // the resource is patched where it is, so the hash can't grow or shrink
+    if old.len() != hash.len() {
+        return Err(Error::IntegrityHashLength {
+            expected: old.len(),
+            found: hash.len(),
+        });
+    }
+
+    // only the first time, so the backup is the executable Steam installed
+    let backup = backups_dir.join(EXECUTABLE_BACKUP_NAME);
+    if !backup.exists() {
+        std::fs::create_dir_all(backups_dir)?;
+        asar_bypass::replace_file(&backup, &exe)?;
+    }
+    exe[range].copy_from_slice(hash.as_bytes());
+    asar_bypass::replace_file(exe_path, &exe)?;
+    info!("Updated the app.asar hash inside of the executable from {old} to {hash}");
+    Ok(())
+}
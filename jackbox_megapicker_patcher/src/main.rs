@@ -1,24 +1,38 @@
-use std::{fs::OpenOptions, path::{Path, PathBuf}};
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
 
-use asar::{AsarReader, AsarWriter};
-use clap::Parser;
-use log::{debug, info};
-use once_cell::sync::Lazy;
-use regex::{Captures, Regex};
+use clap::{Args, Parser, Subcommand};
+use log::info;
+use similar::TextDiff;
 
+mod archive;
+use archive::Archive;
+mod capture;
+mod config;
+use config::{Config, LaunchPreference};
 mod error;
 use error::*;
+mod games;
+mod integrity;
+mod main_js;
+use main_js::{MainJsOptions, beautify_scripts, escape_template_literal, minify_script, patch_scripts};
+mod manifest;
+use manifest::Manifest;
+mod steam;
 use steamworks::{AppId, Client};
 
-static REQUIRES_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?ms)(\w+?)\s*?=\s*?require\("(node:path|node:fs|child_process)"\)"#).unwrap());
-static ENTITLEMENTS_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?ms)if\s*?\(!(\w+?)\.entitlements\s*?\|\|\s*?!(\w+?)\.products\s*?\|\|\s*?!(\w+?)\.storage\)\s*?return\s*?null;.*?const.*?];").unwrap());
-static INSTALLED_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)\[(\w+)\.steamId\]\s*?=\s*?\{\s*?isInstalled:\s*?(\w+?),\s*?installDir:\s*?(\w+?)\s*?\}").unwrap());
-static LAUNCH_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?ms)(\w+)\s*?=\s*?`steam://run/\$\{(\w+)\.data\.steamId}// -launchTo \$\{(\w+)\} -jbg\.config isBundle=false`;(.*?)(if\s*?\(await\s*?(\w+)\.)(.+?)!(\w+)\.user(.+?);").unwrap());
+/// The file name of the Megapicker executable.
+const EXECUTABLE_NAME: &str = "The Jackbox Megapicker.exe";
+
+/// The Steam ID of the Megapicker.
+const MEGAPICKER_APP_ID: u32 = 2828500;
 
 /// Patches the [Jackbox Megapicker](https://store.steampowered.com/app/2828500/The_Jackbox_Megapicker/) to support launching games installed in different directories, includes an ASAR integrity check bypass.
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The path to your Steam install of Jackbox Megapicker.
     /// 
     /// If not specified, automatically query Steam for the install location.
@@ -30,162 +44,562 @@ struct Cli {
 
     /// Disable executable patch
     #[arg(short, long)]
-    executable: bool
-}
-
-/// Returns the capture group at index `i` as a string slice.
-fn get_capture_str<'a>(caps: &'a Captures<'_>, i: usize) -> &'a str {
-    caps.get(i).map(|x| x.as_str()).unwrap_or_default()
-}
-
-/// Patches the `main.js` file to allow the launching of custom directories within `./games/{steam_id}`.
-fn patch_main_js(main: &mut String) -> Result<()> {
-    // Resolve the require names
-    let mut node_path = String::new();
-    let mut node_fs = String::new();
-    let mut child_process = String::new();
-    for mat in REQUIRES_RE.captures_iter(&main) {
-        match mat.get(2).map(|x| x.as_str()) {
-            Some("node:path") => node_path.push_str(get_capture_str(&mat, 1)),
-            Some("node:fs") => node_fs.push_str(get_capture_str(&mat, 1)),
-            Some("child_process") => child_process.push_str(get_capture_str(&mat, 1)),
-            _ => {}
-        };
+    executable: bool,
+
+    /// Keep the integrity check, updating the app.asar hash the executable expects instead of stubbing the check out
+    #[arg(short, long, conflicts_with_all = ["asar", "executable"])]
+    keep_integrity: bool,
+
+    /// Look for games inside of this directory, instead of `./games` inside the install.
+    ///
+    /// Keeps your games safe from Steam's "Verify integrity of game files".
+    #[arg(short, long)]
+    games_dir: Option<PathBuf>,
+
+    /// Move any games inside `./games` to the directory given by `--games-dir`
+    #[arg(short, long, requires = "games_dir")]
+    migrate_games: bool,
+
+    /// Append "(patched vX)" to the Megapicker window title
+    #[arg(short, long)]
+    title: bool,
+
+    /// Launch games inside of the games directory even when no Steam user is logged in
+    #[arg(long, conflicts_with = "no_launch_patch")]
+    offline: bool,
+
+    /// Skip the intro video when the Megapicker starts
+    #[arg(long)]
+    skip_intro: bool,
+
+    /// Look for games inside of the games directory again with F5 or Ctrl+R, without restarting the Megapicker
+    #[arg(long)]
+    rescan_shortcut: bool,
+
+    /// Badge the packs that launch from the games directory, rather than through Steam
+    #[arg(long)]
+    local_badge: bool,
+
+    /// Allow the devtools to be opened with F12 or Ctrl+Shift+I, to debug the patches
+    #[arg(long)]
+    dev_tools: bool,
+
+    /// Drop the crash reporting and analytics, so the patched Megapicker doesn't report home
+    #[arg(long)]
+    no_telemetry: bool,
+
+    /// Stop the Megapicker updating itself, as an update undoes the patches
+    #[arg(long)]
+    disable_updater: bool,
+
+    /// Hide the Megapicker to the tray while a game launched from the games directory runs, restoring it once the game exits
+    #[arg(long)]
+    tray: bool,
+
+    /// Leave the entitlements alone, so only the games you own are shown as owned
+    #[arg(long)]
+    no_entitlements: bool,
+
+    /// Mark every game as owned, not just those inside of the games directory, to see the whole catalog
+    #[arg(long, conflicts_with = "no_entitlements")]
+    spoof_all: bool,
+
+    /// Mark exactly these games as owned, by Steam ID, instead of those inside of the games directory
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["spoof_all", "no_entitlements"])]
+    spoof: Option<Vec<u32>>,
+
+    /// Leave the installation checks alone, so only games installed through Steam are shown as installed
+    #[arg(long)]
+    no_installed_check: bool,
+
+    /// Leave the launch behaviour alone, so games are always launched through Steam
+    #[arg(long)]
+    no_launch_patch: bool,
+
+    /// Pretty-print main.js before patching it, so the patched code and --dry-run's diff are readable
+    #[arg(long)]
+    beautify: bool,
+
+    /// Keep main.js pretty-printed inside of the patched app.asar, instead of minifying it again
+    #[arg(long, requires = "beautify")]
+    keep_beautified: bool,
+
+    /// Print where each patch matched inside of main.js, and what it captured, to debug a patch failing on a new version
+    #[arg(long)]
+    explain: bool,
+
+    /// Patch main.js with this manifest, instead of the one beside the patcher or the embedded one
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Patch from the app.asar.bak written by a previous run, to re-patch an already patched install
+    #[arg(short, long)]
+    from_backup: bool,
+
+    /// Print what would change inside of main.js as a unified diff, without writing anything
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Run the first-run setup again, even if a config already exists
+    #[arg(short, long)]
+    configure: bool,
+
+    /// Patch the running Megapicker process instead of the executable on disk
+    #[cfg(windows)]
+    #[arg(short, long, conflicts_with = "keep_integrity")]
+    live: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Link (or copy, depending on your config) every Jackbox game installed through Steam into the games directory
+    LinkGames(GamesArgs),
+    /// Warn about copies inside of the games directory that are older than their Steam install
+    VerifyGames(GamesArgs),
+    /// Fix what stops the games inside of the games directory starting outside of Steam, like a missing steam_appid.txt or steam_api64.dll
+    FixGames(GamesArgs),
+    /// Write the parts of main.js the patches look at to a report, to attach to a bug report when patching fails
+    Capture(CaptureArgs),
+}
+
+#[derive(Args)]
+struct GamesArgs {
+    /// The path to your Steam install of Jackbox Megapicker.
+    ///
+    /// If not specified, automatically query Steam for the install location.
+    path: Option<PathBuf>,
+
+    /// The games directory, defaults to the one in your config, or `./games` inside the install
+    #[arg(short, long)]
+    games_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CaptureArgs {
+    /// The path to your Steam install of Jackbox Megapicker.
+    ///
+    /// If not specified, automatically query Steam for the install location.
+    path: Option<PathBuf>,
+
+    /// Where the report is written
+    #[arg(short, long, default_value = "megapicker_capture.md")]
+    output: PathBuf,
+
+    /// Look for what this manifest patches, instead of the one beside the patcher or the embedded one
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Capture from the app.asar.bak written by a previous run, as the patched main.js can't be captured
+    #[arg(short, long)]
+    from_backup: bool,
+}
+
+/// Resolves the path to the application, using steamworks if not provided.
+fn resolve_app_path(path: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path);
     }
 
-    if node_path.is_empty() || node_fs.is_empty() || child_process.is_empty() {
-        return Err(Error::RequireMatch)?;
-    }
-
-    debug!("Successfully resolved all requires");
-
-    // Trick the application that you own the installed games
-    let func_def = ENTITLEMENTS_RE.captures_iter(&main).next().ok_or(Error::EntitlementsMatch)?;
-    let func_arg = get_capture_str(&func_def, 1);
-    let insert_at = func_def.get_match().end();
-    main.insert_str(insert_at, &format!("for (const theProduct of {func_arg}.products){{if ({node_fs}.existsSync(`./games/${{theProduct.steamId}}`)){{{func_arg}.entitlements.appsOwned.push(theProduct.steamId)}}}}"));
-    debug!("Patched entitlements");
-
-    // Mark the application as installed, if we do
-    let matched = INSTALLED_RE.captures_iter(&main).next().ok_or(Error::InstallationMatch)?;
-    let a = get_capture_str(&matched, 1);
-    let n = matched.get(2).unwrap().end();
-    main.insert_str(n, &format!("||{node_fs}.existsSync(`./games/${{{a}.steamId}}`)"));
-    debug!("Patched installation checks");
-    
-    // Modify the launch behaviour to use local files
-    let captures = LAUNCH_RE.captures_iter(&main).next().ok_or(Error::LaunchMatch)?;
-    let s = get_capture_str(&captures, 1);
-    let a = get_capture_str(&captures, 2);
-    let r = get_capture_str(&captures, 3);
-    let u = get_capture_str(&captures, 6);
-    let o = get_capture_str(&captures, 8);
-    let range = captures.get(5).unwrap().start()..captures.get_match().end();
-    main.replace_range(range, &format!(r#"
-        if (!{o}.user) return console.warn("No user. Are you logged in?"), {s};
-        let exePath = null;
-        try {{
-            const gameDir = `./games/${{{a}.data.steamId}}`;
-            const findExe = (dir) => {{
-                let list;
-                try {{
-                    list = {node_fs}.readdirSync(dir, {{ withFileTypes: true }});
-                }} catch (err) {{
-                                    return null;
-                }}
-                for (const entry of list) {{
-                    const p = {node_path}.join(dir, entry.name);
-                    if (entry.isFile() && /\.exe$/i.test(entry.name) && !/crashpad_handler\.exe$/i.test(entry.name)) return p;
-                }}
-                return null;
-            }};
-            exePath = findExe(gameDir);
-        }} catch (err) {{ }}
-        // If we found an exe path, spawn it directly with arguments so Windows runs the app
-        if (exePath && {node_fs}.existsSync(exePath)) {{
-            const args = ["-launchTo", {r}, "-jbg.config", "isBundle=false"];
-
-            const exePathResolved = {node_path}.resolve(exePath);
-            const child = {child_process}.execFile(exePathResolved, args, {{ detached: true, stdio: "ignore", cwd: {node_path}.resolve(`./games/${{{a}.data.steamId}}`) }});
-        }} else {{
-            // No exe found; launch via Steam so it handles the app (overlay, cloud, etc.)
-            {s} = `steam://run/${{{a}.data.steamId}}// -launchTo ${{{r}}} -jbg.config isBundle=false`;
-            await {u}.shell.openExternal({s});
-        }}
-    "#));
-    debug!("Patched launch behaviour");
+    let app_id = AppId(MEGAPICKER_APP_ID);
+    let steamworks_client = Client::init_app(app_id)?;
+    let apps = steamworks_client.apps();
+    let install_dir = apps.app_install_dir(app_id);
+    info!("Resolved application installation from Steam as: {install_dir}");
+    Ok(PathBuf::from(install_dir))
+}
 
-    Ok(())
+/// Resolves the games directory, preferring `games_dir` over the config, then `./games` inside the install.
+fn resolve_games_dir(app_path: &Path, games_dir: Option<&PathBuf>, config: &Config) -> Result<PathBuf> {
+    match games_dir.or(config.games_dir.as_ref()) {
+        Some(games_dir) => Ok(std::path::absolute(games_dir)?),
+        None => Ok(app_path.join("games")),
+    }
 }
 
-/// Handles the entire process of finding and patching the `app.asar` file.
-fn patch_asar(app_path: &Path) -> Result<()> {
-    // Read the main asar file
+/// The file name of the backup of `app.asar`, written before it is patched.
+const ASAR_BACKUP_NAME: &str = "app.asar.bak";
+
+/// Where the unpatched `app.asar` is read from: the backup written by a
+/// previous run with `from_backup`, otherwise the install's own.
+fn asar_source(app_path: &Path, backups_dir: Option<&Path>, from_backup: bool) -> Result<PathBuf> {
     let resources = app_path.join("resources");
-    let asar_file_path = resources.join("app.asar");
-    let asar_file = std::fs::read(&asar_file_path)?;
-    let asar = AsarReader::new(&asar_file, None)?;
-    info!("Successfully opened app.asar");
+    if !from_backup {
+        return Ok(resources.join("app.asar"));
+    }
+    let backup = backups_dir.unwrap_or(resources.as_path()).join(ASAR_BACKUP_NAME);
+    if !backup.is_file() {
+        return Err(Error::BackupNotFound(backup));
+    }
+    info!("Patching from the backup at {}", backup.display());
+    Ok(backup)
+}
+
+/// The directory some Electron apps ship inside of `resources` instead of `app.asar`.
+const APP_DIR_NAME: &str = "app";
+
+/// The directory the scripts patched inside of an unpacked app are backed up to,
+/// at the same paths as inside of the app.
+const APP_BACKUP_NAME: &str = "app.bak";
+
+/// The files of the Megapicker's app, by their path inside of it.
+type AppFiles = BTreeMap<PathBuf, Vec<u8>>;
+
+/// The unpacked `resources/app` of the install, if it ships one instead of `app.asar`.
+fn unpacked_app(app_path: &Path) -> Option<PathBuf> {
+    let resources = app_path.join("resources");
+    let dir = resources.join(APP_DIR_NAME);
+    (!resources.join("app.asar").exists() && dir.is_dir()).then_some(dir)
+}
+
+/// Where the scripts of an unpacked app are backed up, failing with `from_backup` if they haven't been.
+fn unpacked_backup(app_path: &Path, backups_dir: Option<&Path>, from_backup: bool) -> Result<PathBuf> {
+    let resources = app_path.join("resources");
+    let backup = backups_dir.unwrap_or(resources.as_path()).join(APP_BACKUP_NAME);
+    if from_backup && !backup.is_dir() {
+        return Err(Error::BackupNotFound(backup));
+    }
+    Ok(backup)
+}
+
+/// Every file inside of the unpacked app at `dir`, other than its dependencies,
+/// read from `backup` instead where it has been backed up there.
+fn unpacked_files(dir: &Path, backup: Option<&Path>) -> Result<AppFiles> {
+    fn walk(root: &Path, dir: &Path, backup: Option<&Path>, out: &mut AppFiles) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() != "node_modules" {
+                    walk(root, &path, backup, out)?;
+                }
+                continue;
+            }
+            let relative = path.strip_prefix(root).expect("walked from the root").to_path_buf();
+            let source = backup.map(|x| x.join(&relative)).filter(|x| x.is_file()).unwrap_or(path);
+            out.insert(relative, std::fs::read(source)?);
+        }
+        Ok(())
+    }
+    let mut files = AppFiles::new();
+    walk(dir, dir, backup, &mut files)?;
+    Ok(files)
+}
+
+/// Reads the files of the app, from `app.asar` or the unpacked app, or what was
+/// backed up before the last patch with `from_backup`.
+fn read_app(app_path: &Path, backups_dir: Option<&Path>, from_backup: bool) -> Result<AppFiles> {
+    if let Some(dir) = unpacked_app(app_path) {
+        let backup = unpacked_backup(app_path, backups_dir, from_backup)?;
+        return unpacked_files(&dir, from_backup.then_some(backup.as_path()));
+    }
+    let asar_file = std::fs::read(asar_source(app_path, backups_dir, from_backup)?)?;
+    Archive::parse(&asar_file)?.files()
+}
+
+/// Finds the entry file inside of the app: where the manifest says it is, then the
+/// `main` of its `package.json`, then whichever script has the launch block in it.
+fn resolve_main_js(files: &AppFiles, manifest: &Manifest) -> Result<PathBuf> {
+    if files.contains_key(&manifest.path) {
+        return Ok(manifest.path.clone());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Package {
+        main: Option<String>,
+    }
+    let main = files
+        .get(Path::new("package.json"))
+        .and_then(|x| serde_json::from_slice::<Package>(x).ok())
+        .and_then(|x| x.main)
+        .map(|x| PathBuf::from(x.trim_start_matches("./")))
+        .filter(|x| files.contains_key(x));
+    if let Some(main) = main {
+        info!("Found main.js at {} through package.json", main.display());
+        return Ok(main);
+    }
 
-    // Extract the `main.js` file
-    let mainjs_file = PathBuf::from(".vite/build/main.js");
-    let mainjs = asar.files().get(&mainjs_file).ok_or(Error::MainJsNotFound)?;
-    let mut data = String::from_utf8_lossy(mainjs.data()).to_string();
+    let has_prefix = |data: &[u8]| {
+        manifest.launch.iter().map(|x| x.url_prefix.as_bytes()).any(|prefix| data.windows(prefix.len()).any(|x| x == prefix))
+    };
+    let mut scripts: Vec<_> = files
+        .iter()
+        .filter(|(path, data)| path.extension().is_some_and(|x| x == "js") && has_prefix(data))
+        .map(|(path, _)| path.clone())
+        .collect();
+    scripts.sort();
+    let main = scripts.into_iter().next().ok_or(Error::MainJsNotFound)?;
+    info!("Found main.js at {} by its launch block", main.display());
+    Ok(main)
+}
+
+/// Reads the scripts the patches may be in: `entry`, and the chunks Vite split
+/// out of it, which sit beside it.
+fn read_scripts(files: &AppFiles, entry: &Path) -> BTreeMap<PathBuf, String> {
+    let dir = entry.parent().unwrap_or(Path::new(""));
+    files
+        .iter()
+        .filter(|(path, _)| {
+            *path == entry
+                || (path.starts_with(dir)
+                    && path.extension().is_some_and(|x| x == "js")
+                    && !path.components().any(|x| x.as_os_str() == "node_modules"))
+        })
+        .map(|(path, data)| (path.clone(), String::from_utf8_lossy(data).to_string()))
+        .collect()
+}
+
+/// Patches the scripts in memory, printing what would change as a unified diff.
+fn preview_app(files: &AppFiles, options: &MainJsOptions) -> Result<()> {
+    let mainjs_file = resolve_main_js(files, &options.manifest)?;
+    let mut original = read_scripts(files, &mainjs_file);
+    if options.beautify {
+        beautify_scripts(&mut original, &mainjs_file, &options.manifest)?;
+    }
+
+    let mut patched = original.clone();
+    for path in patch_scripts(&mut patched, &mainjs_file, options)? {
+        let diff = TextDiff::from_lines(&original[&path], &patched[&path]);
+        let name = path.display();
+        print!("{}", diff.unified_diff().header(&format!("a/{name}"), &format!("b/{name}")));
+    }
+    Ok(())
+}
+
+/// Writes the regions of the scripts the patches look at to `output`, with
+/// `app_path` and `games_dir` taken out of them.
+fn capture_app(files: &AppFiles, manifest: &Manifest, app_path: &Path, games_dir: &Path, output: &Path) -> Result<()> {
+    let mainjs_file = resolve_main_js(files, manifest)?;
+    let mut scripts = read_scripts(files, &mainjs_file);
+    // minified, each region would be a single line
+    beautify_scripts(&mut scripts, &mainjs_file, manifest)?;
+
+    let home = std::env::var_os("USERPROFILE").or_else(|| std::env::var_os("HOME")).map(PathBuf::from);
+    let mut paths = vec![(app_path, "<install>"), (games_dir, "<games>")];
+    paths.extend(home.as_deref().map(|x| (x, "<home>")));
+    let report = capture::anonymize(&capture::report(&scripts, &mainjs_file, manifest), &paths);
+    std::fs::write(output, report)?;
+    info!("Wrote the capture to {}, check it over before sharing it", output.display());
+    Ok(())
+}
+
+/// Patches `main.js`, and any chunks split out of it, from `files`, returning
+/// every script read alongside those that were changed.
+fn patch_app(files: &AppFiles, options: &MainJsOptions) -> Result<(BTreeMap<PathBuf, String>, Vec<PathBuf>)> {
+    // Extract the `main.js` file, and any chunks split out of it
+    let mainjs_file = resolve_main_js(files, &options.manifest)?;
+    let mut scripts = read_scripts(files, &mainjs_file);
     info!("Retrieved initial main.js data");
+    if options.beautify {
+        beautify_scripts(&mut scripts, &mainjs_file, &options.manifest)?;
+        info!("Beautified main.js");
+    }
 
     // Patch the main file
-    patch_main_js(&mut data)?;
+    let changed = patch_scripts(&mut scripts, &mainjs_file, options)?;
+    if options.beautify && !options.keep_beautified {
+        for path in &changed {
+            minify_script(scripts.get_mut(path).expect("patched scripts exist"))?;
+        }
+    }
     info!("Patched main.js");
+    Ok((scripts, changed))
+}
 
-    // Also output the patched `main.js` file and a backup of the `app.asar` file
-    std::fs::write(resources.join("main.js"), &data)?;
-    std::fs::write(resources.join("app.asar.bak"), &asar_file)?;
+/// Patches the scripts of the unpacked app at `app_dir` in place, backing the
+/// originals up to `backup` first.
+fn patch_unpacked(app_dir: &Path, files: &AppFiles, options: &MainJsOptions, backup: &Path) -> Result<()> {
+    let (scripts, changed) = patch_app(files, options)?;
+    for path in &changed {
+        // what was read, which is the backup itself when patching from it
+        let backup = backup.join(path);
+        if let Some(parent) = backup.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&backup, &files[path])?;
+        std::fs::write(app_dir.join(path), &scripts[path])?;
+        info!("Patched {}", path.display());
+    }
+    Ok(())
+}
 
-    // Reconstruct the asar with our modified `main.js` file
-    let mut writer = AsarWriter::new();
-    for (path, file) in asar.files() {
-        if *path != mainjs_file {
-            writer.write_file(path, file.data(), false)?;
+/// Handles the entire process of finding and patching the `app.asar` file.
+///
+/// `app.asar` is rebuilt from `source`, which is itself unless patching from the
+/// backup, leaving the files inside of `app.asar.unpacked` where they are. When
+/// patching `app.asar` itself, only the header and the changed scripts are
+/// written over it, if the header still fits. Returns the hash of the new header.
+fn patch_asar(app_path: &Path, source: &Path, options: &MainJsOptions, backups_dir: Option<&Path>) -> Result<String> {
+    // Read the main asar file
+    let resources = app_path.join("resources");
+    let asar_file_path = resources.join("app.asar");
+    let asar_file = std::fs::read(source)?;
+    let asar = Archive::parse(&asar_file)?;
+    info!("Successfully opened app.asar");
+    let (scripts, changed) = patch_app(&asar.files()?, options)?;
+
+    // Also output the patched scripts and a backup of the `app.asar` file
+    for path in &changed {
+        if let Some(name) = path.file_name() {
+            std::fs::write(resources.join(name), &scripts[path])?;
         }
     }
-    writer.write_file(mainjs_file, data, false)?;
-    
+    let backups_dir = backups_dir.unwrap_or(resources.as_path());
+    std::fs::create_dir_all(backups_dir)?;
+    std::fs::write(backups_dir.join(ASAR_BACKUP_NAME), &asar_file)?;
+
+    let changed: AppFiles = changed.iter().map(|x| (x.clone(), scripts[x].clone().into_bytes())).collect();
+    let unpacked = asar.unpacked();
+    if !unpacked.is_empty() {
+        info!("Kept {} files inside of app.asar.unpacked", unpacked.len());
+    }
+
+    // Write only our modified scripts over the asar, when it is what was read
+    if source == asar_file_path
+        && let Some(edit) = asar.edit_in_place(&changed)?
+    {
+        edit.apply(&asar_file_path)?;
+        info!("Edited app.asar in place");
+        return Ok(edit.header_hash);
+    }
+
+    // Otherwise reconstruct the asar with our modified scripts
+    let rebuilt = asar.rebuild(&changed)?;
+
     // Output to file system
-    let asar_file_handle = OpenOptions::new().write(true).open(asar_file_path)?;
-    writer.finalize(asar_file_handle)?;
+    // truncated, as the previously patched asar may be longer when patching from the backup
+    asar_bypass::replace_file(asar_file_path, &rebuilt)?;
     info!("Finalised the asar write");
 
-    Ok(())
+    Archive::parse(&rebuilt).map(|x| x.header_hash())
 }
 
 fn main() -> Result<()> {
     // Initialise
     env_logger::init();
     let cli = Cli::parse();
+    let config = Config::load_or_onboard(cli.configure)?;
 
-    // Attempt to resolve the path to the application, using steamworks if not provided
-    let app_path = match cli.path {
-        Some(x) => x,
-        None => {
-            let app_id = AppId(2828500);
-            let steamworks_client = Client::init_app(app_id)?;
-            let apps = steamworks_client.apps();
-            let install_dir = apps.app_install_dir(app_id);
-            info!("Resolved application installation from Steam as: {install_dir}");
-            PathBuf::from(install_dir)
+    match cli.command {
+        Some(Command::LinkGames(args)) => {
+            let app_path = resolve_app_path(args.path)?;
+            let games_dir = resolve_games_dir(&app_path, args.games_dir.as_ref(), &config)?;
+            games::link(&app_path, &games_dir, config.games_mode)?;
+            return Ok(());
         }
-    };
+        Some(Command::VerifyGames(args)) => {
+            let app_path = resolve_app_path(args.path)?;
+            let games_dir = resolve_games_dir(&app_path, args.games_dir.as_ref(), &config)?;
+            games::verify(&games_dir)?;
+            return Ok(());
+        }
+        Some(Command::FixGames(args)) => {
+            let app_path = resolve_app_path(args.path)?;
+            let games_dir = resolve_games_dir(&app_path, args.games_dir.as_ref(), &config)?;
+            games::fix(&app_path, &games_dir)?;
+            return Ok(());
+        }
+        Some(Command::Capture(args)) => {
+            let app_path = resolve_app_path(args.path)?;
+            let games_dir = resolve_games_dir(&app_path, None, &config)?;
+            let manifest = Manifest::load(args.manifest.as_deref())?;
+            let files = read_app(&app_path, config.backups_dir.as_deref(), args.from_backup)?;
+            capture_app(&files, &manifest, &app_path, &games_dir, &args.output)?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let app_path = resolve_app_path(cli.path)?;
 
     // Patch whatever
-    if !cli.executable {
-        let executable_path = app_path.join("The Jackbox Megapicker.exe");
-        asar_bypass::patch_file(executable_path, None)?;
-        info!("Patched executable.");
+    if cli.keep_integrity && cli.dry_run {
+        // only locate the hash, so a failure still shows up before anything is written
+        let (range, hash) = integrity::find_hash(&std::fs::read(app_path.join(EXECUTABLE_NAME))?)?;
+        info!("Would update the app.asar hash {hash} inside of the executable at file 0x{:x}.", range.start);
+    } else if cli.keep_integrity {
+        info!("Keeping the integrity check, the app.asar hash is updated once it is patched.");
+    } else if !cli.executable && cli.dry_run {
+        // only locate the integrity check, so a failure still shows up before anything is written
+        let data = std::fs::read(app_path.join(EXECUTABLE_NAME))?;
+        let report = asar_bypass::preview(&data)?;
+        info!(
+            "Would patch the executable at file 0x{:x}-0x{:x} using {:?}.",
+            report.func_start, report.func_end, report.method
+        );
+    } else if !cli.executable {
+        let executable_path = app_path.join(EXECUTABLE_NAME);
+
+        #[cfg(windows)]
+        if cli.live {
+            let pid = asar_bypass::live::find_process(EXECUTABLE_NAME)?;
+            asar_bypass::live::patch_process(pid, executable_path, &Default::default())?;
+            info!("Patched running process.");
+        } else {
+            asar_bypass::patch_file(executable_path, None)?;
+            info!("Patched executable.");
+        }
+
+        #[cfg(not(windows))]
+        {
+            asar_bypass::patch_file(executable_path, None)?;
+            info!("Patched executable.");
+        }
     }
 
     if !cli.asar {
-        patch_asar(&app_path)?;
-        info!("Patched asar file.")
+        let mut options = MainJsOptions {
+            launch_local: config.launch == LaunchPreference::Local,
+            title_marker: cli.title,
+            offline: cli.offline,
+            skip_intro: cli.skip_intro,
+            rescan_shortcut: cli.rescan_shortcut,
+            local_badge: cli.local_badge,
+            dev_tools: cli.dev_tools,
+            strip_telemetry: cli.no_telemetry,
+            disable_updater: cli.disable_updater,
+            tray: cli.tray,
+            entitlements: !cli.no_entitlements,
+            spoof_all: cli.spoof_all,
+            spoof: cli.spoof.or_else(|| config.spoof.clone()),
+            installed_check: !cli.no_installed_check,
+            launch_patch: !cli.no_launch_patch,
+            hooks: config.hooks.clone(),
+            beautify: cli.beautify,
+            keep_beautified: cli.keep_beautified,
+            explain: cli.explain,
+            manifest: Manifest::load(cli.manifest.as_deref())?,
+            ..Default::default()
+        };
+
+        // Relocate the games, if wanted
+        let games_dir = resolve_games_dir(&app_path, cli.games_dir.as_ref(), &config)?;
+        options.game_dirs = games::GameDirs::load(&games_dir)?.dirs;
+        if cli.games_dir.is_some() || config.games_dir.is_some() {
+            if cli.migrate_games && !cli.dry_run {
+                games::migrate(&app_path.join("games"), &games_dir)?;
+            }
+            options.games_dir = escape_template_literal(&games_dir.to_string_lossy());
+        }
+
+        let backups_dir = config.backups_dir.as_deref();
+        if cli.dry_run {
+            preview_app(&read_app(&app_path, backups_dir, cli.from_backup)?, &options)?;
+        } else if let Some(app_dir) = unpacked_app(&app_path) {
+            // shipped without an asar, so the scripts are patched where they are
+            let backup = unpacked_backup(&app_path, backups_dir, cli.from_backup)?;
+            let files = unpacked_files(&app_dir, cli.from_backup.then_some(backup.as_path()))?;
+            patch_unpacked(&app_dir, &files, &options, &backup)?;
+            info!("Patched the unpacked app.")
+        } else {
+            let source = asar_source(&app_path, backups_dir, cli.from_backup)?;
+            let hash = patch_asar(&app_path, &source, &options, backups_dir)?;
+            info!("Patched asar file.");
+            if cli.keep_integrity {
+                let resources = app_path.join("resources");
+                let backups_dir = backups_dir.unwrap_or(resources.as_path());
+                integrity::update_hash(&app_path.join(EXECUTABLE_NAME), &hash, backups_dir)?;
+            }
+        }
     }
 
     // Done!
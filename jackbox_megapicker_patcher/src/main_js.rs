@@ -0,0 +1,1115 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use oxc::allocator::{Allocator, Vec as AstVec};
+use oxc::ast::ast::*;
+use oxc::ast_visit::{Visit, walk};
+use oxc::codegen::{Codegen, CodegenOptions};
+use oxc::parser::Parser;
+use oxc::span::{GetSpan, SourceType, Span};
+
+use crate::config::Hooks;
+use crate::error::*;
+use crate::manifest::{Manifest, render};
+
+/// Which optional patches to apply to `main.js`.
+pub struct MainJsOptions {
+    /// Where the games are looked up, already escaped for a JS template literal.
+    pub games_dir: String,
+    /// Where each game kept outside of the games directory is, by Steam ID.
+    pub game_dirs: BTreeMap<String, PathBuf>,
+    /// Run the executable inside of the games directory, rather than launching through Steam.
+    pub launch_local: bool,
+    /// Append "(patched vX)" to the window title.
+    pub title_marker: bool,
+    /// Launch games inside of the games directory without being logged in.
+    pub offline: bool,
+    /// Skip the intro video when the Megapicker starts.
+    pub skip_intro: bool,
+    /// Look for games inside of the games directory again with F5 or Ctrl+R.
+    pub rescan_shortcut: bool,
+    /// Badge the packs that launch from the games directory.
+    pub local_badge: bool,
+    /// Allow the devtools to be opened, with F12 or Ctrl+Shift+I.
+    pub dev_tools: bool,
+    /// Drop the crash reporting and analytics.
+    pub strip_telemetry: bool,
+    /// Stop the Megapicker updating itself.
+    pub disable_updater: bool,
+    /// Hide the Megapicker to the tray while a game launched from the games directory runs.
+    pub tray: bool,
+    /// Mark the games inside of the games directory as owned.
+    pub entitlements: bool,
+    /// Mark every game as owned, whether or not it is inside of the games directory.
+    pub spoof_all: bool,
+    /// Mark exactly these games as owned, by Steam ID, instead of those inside of the games directory.
+    pub spoof: Option<Vec<u32>>,
+    /// Mark the games inside of the games directory as installed.
+    pub installed_check: bool,
+    /// Launch games from the games directory.
+    pub launch_patch: bool,
+    /// What is looked for, and what it is patched with.
+    pub manifest: Manifest,
+    /// Snippets injected alongside the patches.
+    pub hooks: Hooks,
+    /// Pretty-print the scripts before patching them.
+    pub beautify: bool,
+    /// Leave the patched scripts pretty-printed, rather than minifying them again.
+    pub keep_beautified: bool,
+    /// Print what each patch matched, and where.
+    pub explain: bool,
+}
+
+impl Default for MainJsOptions {
+    fn default() -> Self {
+        Self {
+            games_dir: "./games".to_string(),
+            game_dirs: BTreeMap::new(),
+            launch_local: true,
+            title_marker: false,
+            offline: false,
+            skip_intro: false,
+            rescan_shortcut: false,
+            local_badge: false,
+            dev_tools: false,
+            strip_telemetry: false,
+            disable_updater: false,
+            tray: false,
+            entitlements: true,
+            spoof_all: false,
+            spoof: None,
+            installed_check: true,
+            launch_patch: true,
+            manifest: Manifest::default(),
+            hooks: Hooks::default(),
+            beautify: false,
+            keep_beautified: false,
+            explain: false,
+        }
+    }
+}
+
+/// Starts the comment placed at the top of a patched `main.js`, followed by the patcher's version.
+const PATCH_MARKER: &str = "/* Patched by jackbox_megapicker_patcher v";
+
+/// How many characters of each match `--explain` shows.
+const EXPLAIN_SNIPPET_LEN: usize = 80;
+
+/// Start the comments pointing the devtools at a script's source map, the second being the deprecated form.
+const SOURCE_MAP_PREFIXES: [&str; 2] = ["//# sourceMappingURL=", "//@ sourceMappingURL="];
+
+/// The version of the patcher `main` was patched by, if it has been.
+pub fn patched_version(main: &str) -> Option<&str> {
+    let rest = &main[main.find(PATCH_MARKER)? + PATCH_MARKER.len()..];
+    rest.split_once(" */").map(|(version, _)| version)
+}
+
+/// Escapes `s` so it can be placed inside a JS template literal.
+pub fn escape_template_literal(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace("${", "\\${")
+}
+
+/// The parts of `main.js` that get patched, found by walking its syntax tree so
+/// renamed identifiers and reformatting between Megapicker updates don't matter.
+struct Finder<'s> {
+    source: &'s str,
+    manifest: &'s Manifest,
+    node_path: Option<&'s str>,
+    node_fs: Option<&'s str>,
+    child_process: Option<&'s str>,
+    entitlements: Option<Entitlements<'s>>,
+    installed: Option<Installed<'s>>,
+    launch_url: Option<LaunchUrl<'s>>,
+    launch: Option<Launch<'s>>,
+    /// The `false` of every `devTools: false`
+    dev_tools: Vec<Span>,
+    /// Every call initialising telemetry, like `Sentry.init({ dsn })`
+    telemetry: Vec<Span>,
+    /// Every call to the updater, like `autoUpdater.checkForUpdates()`
+    updater: Vec<Span>,
+}
+
+/// `if (!e.entitlements || !e.products || !e.storage) return null;`, with the
+/// patch going after the first `const` following it.
+struct Entitlements<'s> {
+    /// Which of the manifest's entitlements patches matched
+    variant: usize,
+    /// The object holding the entitlements, `e` above
+    object: &'s str,
+    insert_at: u32,
+    /// The `if` statement
+    guard: Span,
+}
+
+/// `x[a.steamId] = { isInstalled: n, installDir: r }`
+struct Installed<'s> {
+    /// Which of the manifest's installed patches matched
+    variant: usize,
+    /// The game, `a` above
+    game: &'s str,
+    /// The value of `isInstalled`
+    value: Span,
+    /// The whole assignment
+    assignment: Span,
+}
+
+/// ``s = `steam://run/${a.data.steamId}// -launchTo ${r} -jbg.config isBundle=false` ``
+struct LaunchUrl<'s> {
+    /// Which of the manifest's launch patches matched
+    variant: usize,
+    /// The variable holding the URL, `s` above
+    url: &'s str,
+    /// The game, `a` above
+    game: &'s str,
+    /// Where the game launches to, `r` above
+    launch_to: &'s str,
+    /// The template literal
+    template: Span,
+}
+
+/// `if (await u.shell.openExternal(s), !o.user) return ...;`, the first
+/// statement after the [`LaunchUrl`] opening it, which gets replaced.
+struct Launch<'s> {
+    url: LaunchUrl<'s>,
+    /// Electron's module, `u` above
+    electron: &'s str,
+    /// The state holding the user, `o` above
+    state: &'s str,
+    span: Span,
+}
+
+impl<'s> Finder<'s> {
+    fn new(source: &'s str, manifest: &'s Manifest) -> Self {
+        Self {
+            source,
+            manifest,
+            node_path: None,
+            node_fs: None,
+            child_process: None,
+            entitlements: None,
+            installed: None,
+            launch_url: None,
+            launch: None,
+            dev_tools: Vec::new(),
+            telemetry: Vec::new(),
+            updater: Vec::new(),
+        }
+    }
+
+    fn text(&self, span: Span) -> &'s str {
+        &self.source[span.start as usize..span.end as usize]
+    }
+
+    /// The start of `span` on a single line, for [`Self::explain`].
+    fn snippet(&self, span: Span) -> String {
+        let text = self
+            .text(span)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        match text.char_indices().nth(EXPLAIN_SNIPPET_LEN) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text,
+        }
+    }
+
+    /// Prints what was found inside of `path`, and where, for `--explain`.
+    fn explain(&self, path: &Path) {
+        fn found(name: Option<&str>) -> &str {
+            name.unwrap_or("not found, required inline")
+        }
+        println!("{}:", path.display());
+        println!(
+            "  requires: path = {}, fs = {}, child_process = {}",
+            found(self.node_path),
+            found(self.node_fs),
+            found(self.child_process)
+        );
+        match &self.entitlements {
+            Some(x) => println!(
+                "  entitlements (alternative {}) at {}..{}: `{}`\n    entitlements = {}, patched in at {}",
+                x.variant,
+                x.guard.start,
+                x.guard.end,
+                self.snippet(x.guard),
+                x.object,
+                x.insert_at
+            ),
+            None => println!("  entitlements: no guard found"),
+        }
+        match &self.installed {
+            Some(x) => println!(
+                "  installed (alternative {}) at {}..{}: `{}`\n    game = {}",
+                x.variant,
+                x.assignment.start,
+                x.assignment.end,
+                self.snippet(x.assignment),
+                x.game
+            ),
+            None => println!("  installed: no assignment found"),
+        }
+        match (&self.launch, &self.launch_url) {
+            (Some(x), _) => println!(
+                "  launch (alternative {}) at {}..{}: `{}`\n    url = {}, game = {}, launch_to = {}, electron = {}, state = {}",
+                x.url.variant,
+                x.span.start,
+                x.span.end,
+                self.snippet(x.span),
+                x.url.url,
+                x.url.game,
+                x.url.launch_to,
+                x.electron,
+                x.state
+            ),
+            (None, Some(x)) => println!(
+                "  launch: found the URL at {}..{}: `{}`, but no statement checking the user after it",
+                x.template.start,
+                x.template.end,
+                self.snippet(x.template)
+            ),
+            (None, None) => println!("  launch: no launch URL found"),
+        }
+    }
+
+    /// Records `name = require("...")` for the modules the patches use.
+    fn record_require(&mut self, name: Span, init: &Expression) {
+        let Expression::CallExpression(call) = init.without_parentheses() else {
+            return;
+        };
+        let (Expression::Identifier(callee), [Argument::StringLiteral(module)]) =
+            (&call.callee, &call.arguments[..])
+        else {
+            return;
+        };
+        if callee.name != "require" {
+            return;
+        }
+        let (name, requires) = (self.text(name), &self.manifest.requires);
+        let module = module.value.as_str();
+        let slot = match () {
+            _ if requires.path.iter().any(|x| x == module) => &mut self.node_path,
+            _ if requires.fs.iter().any(|x| x == module) => &mut self.node_fs,
+            _ if requires.child_process.iter().any(|x| x == module) => &mut self.child_process,
+            _ => return,
+        };
+        slot.get_or_insert(name);
+    }
+
+    /// Records ``name = `steam://run/...` `` as the start of the launch block,
+    /// matched by the first of the launch patches that can.
+    fn record_launch_url(&mut self, name: Span, init: &Expression) {
+        let Expression::TemplateLiteral(template) = init.without_parentheses() else {
+            return;
+        };
+        let [steam_id, launch_to, ..] = &template.expressions[..] else {
+            return;
+        };
+        for (variant, launch) in self.manifest.launch.iter().enumerate() {
+            if !template
+                .quasis
+                .first()
+                .is_some_and(|x| x.value.raw.starts_with(launch.url_prefix.as_str()))
+            {
+                continue;
+            }
+            // the game is whatever `.data.steamId` is read from
+            let Some(data) = launch
+                .steam_id
+                .rsplit('.')
+                .try_fold(steam_id, |x, property| static_member(x, property))
+            else {
+                continue;
+            };
+            self.launch_url = Some(LaunchUrl {
+                variant,
+                url: self.text(name),
+                game: self.text(data.span()),
+                launch_to: self.text(launch_to.span()),
+                template: template.span,
+            });
+            return;
+        }
+    }
+
+    /// Whether `test` is `!e.entitlements || !e.products || !e.storage`, or
+    /// whichever properties are in `guard`, returning `e`.
+    fn entitlements_object(&self, test: &Expression, guard: &[String]) -> Option<&'s str> {
+        let mut operands = Vec::new();
+        flatten_or(test, &mut operands);
+        let mut object = None;
+        let mut found = vec![false; guard.len()];
+        for operand in operands {
+            let Expression::UnaryExpression(not) = operand else {
+                return None;
+            };
+            if not.operator != UnaryOperator::LogicalNot {
+                return None;
+            }
+            let Expression::StaticMemberExpression(member) = not.argument.without_parentheses()
+            else {
+                return None;
+            };
+            let index = guard
+                .iter()
+                .position(|x| member.property.name == x.as_str())?;
+            found[index] = true;
+            let text = self.text(member.object.span());
+            if object.is_some_and(|x| x != text) {
+                return None;
+            }
+            object = Some(text);
+        }
+        object.filter(|_| !found.is_empty() && found.iter().all(|x| *x))
+    }
+}
+
+impl<'a> Visit<'a> for Finder<'_> {
+    fn visit_statements(&mut self, it: &AstVec<'a, Statement<'a>>) {
+        // a guard matched by an earlier entitlements patch is preferred, wherever it is
+        if self.entitlements.as_ref().is_none_or(|x| x.variant > 0) {
+            for (i, statement) in it.iter().enumerate() {
+                let Statement::IfStatement(guard) = statement else {
+                    continue;
+                };
+                let returns = match &guard.consequent {
+                    Statement::ReturnStatement(_) => true,
+                    Statement::BlockStatement(block) => {
+                        matches!(block.body.first(), Some(Statement::ReturnStatement(_)))
+                    }
+                    _ => false,
+                };
+                if !returns {
+                    continue;
+                }
+                let best = self.entitlements.as_ref().map_or(usize::MAX, |x| x.variant);
+                let Some((variant, object)) = self.manifest.entitlements
+                    [..best.min(self.manifest.entitlements.len())]
+                    .iter()
+                    .enumerate()
+                    .find_map(|(variant, patch)| {
+                        Some((
+                            variant,
+                            self.entitlements_object(&guard.test, &patch.guard)?,
+                        ))
+                    })
+                else {
+                    continue;
+                };
+                let after = it[i + 1..].iter().find_map(|x| match x {
+                    Statement::VariableDeclaration(x)
+                        if x.kind == VariableDeclarationKind::Const =>
+                    {
+                        Some(x.span.end)
+                    }
+                    _ => None,
+                });
+                self.entitlements = Some(Entitlements {
+                    variant,
+                    object,
+                    insert_at: after.unwrap_or(guard.span.end),
+                    guard: guard.span,
+                });
+            }
+        }
+        walk::walk_statements(self, it);
+    }
+
+    fn visit_variable_declarator(&mut self, it: &VariableDeclarator<'a>) {
+        if let Some(binding) = it.id.get_binding_identifier()
+            && let Some(init) = &it.init
+        {
+            self.record_require(binding.span, init);
+            self.record_launch_url(binding.span, init);
+        }
+        walk::walk_variable_declarator(self, it);
+    }
+
+    fn visit_assignment_expression(&mut self, it: &AssignmentExpression<'a>) {
+        match &it.left {
+            AssignmentTarget::AssignmentTargetIdentifier(target) => {
+                self.record_require(target.span, &it.right);
+                self.record_launch_url(target.span, &it.right);
+            }
+            AssignmentTarget::ComputedMemberExpression(target)
+                if self.installed.as_ref().is_none_or(|x| x.variant > 0) =>
+            {
+                let best = self.installed.as_ref().map_or(usize::MAX, |x| x.variant);
+                let patches = &self.manifest.installed[..best.min(self.manifest.installed.len())];
+                if let Expression::ObjectExpression(object) = it.right.without_parentheses()
+                    && let Some((variant, game, value)) =
+                        patches.iter().enumerate().find_map(|(variant, patch)| {
+                            let game = static_member(&target.expression, &patch.key)?;
+                            let value = property(object, &patch.property)?;
+                            property(object, &patch.sibling)?;
+                            Some((variant, game, value))
+                        })
+                {
+                    self.installed = Some(Installed {
+                        variant,
+                        game: self.text(game.span()),
+                        value: value.span(),
+                        assignment: it.span,
+                    });
+                }
+            }
+            _ => {}
+        }
+        walk::walk_assignment_expression(self, it);
+    }
+
+    fn visit_call_expression(&mut self, it: &CallExpression<'a>) {
+        let properties = &self.manifest.telemetry.init_properties;
+        let initialises = it.arguments.iter().any(|x| match x {
+            Argument::ObjectExpression(object) => {
+                properties.iter().any(|x| property(object, x).is_some())
+            }
+            _ => false,
+        });
+        // calls inside of one already dropped go with it
+        let inside = self
+            .telemetry
+            .last()
+            .is_some_and(|x| x.start <= it.span.start && it.span.end <= x.end);
+        if initialises && !inside {
+            self.telemetry.push(it.span);
+        }
+
+        if let Expression::StaticMemberExpression(callee) = it.callee.without_parentheses()
+            && self
+                .manifest
+                .updater
+                .calls
+                .iter()
+                .any(|x| callee.property.name == x.as_str())
+            && !self
+                .updater
+                .last()
+                .is_some_and(|x| x.start <= it.span.start && it.span.end <= x.end)
+        {
+            self.updater.push(it.span);
+        }
+        walk::walk_call_expression(self, it);
+    }
+
+    fn visit_object_property(&mut self, it: &ObjectProperty<'a>) {
+        // `false`, or `!1` once minified
+        let disabled = match it.value.without_parentheses() {
+            Expression::BooleanLiteral(x) => !x.value,
+            Expression::UnaryExpression(x) if x.operator == UnaryOperator::LogicalNot => {
+                match x.argument.without_parentheses() {
+                    Expression::NumericLiteral(x) => x.value != 0.0,
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+        if disabled && it.key.static_name().is_some_and(|x| x == "devTools") {
+            self.dev_tools.push(it.value.span());
+        }
+        walk::walk_object_property(self, it);
+    }
+
+    fn visit_if_statement(&mut self, it: &IfStatement<'a>) {
+        if let Some(url) = &self.launch_url
+            && self
+                .launch
+                .as_ref()
+                .is_none_or(|x| url.variant < x.url.variant)
+            && let (Some(electron), Some(state)) = (
+                awaited_root(&it.test, self.source),
+                user_check(
+                    &it.test,
+                    &self.manifest.launch[url.variant].user,
+                    self.source,
+                ),
+            )
+        {
+            self.launch = self.launch_url.take().map(|url| Launch {
+                url,
+                electron,
+                state,
+                span: it.span,
+            });
+        }
+        walk::walk_if_statement(self, it);
+    }
+}
+
+/// Whether `inner` lies inside of `outer`, so replacing `outer` replaces it too.
+/// Insertions on either edge of `outer` stay outside of it.
+fn nested(outer: Span, inner: Span) -> bool {
+    outer != inner
+        && !outer.is_empty()
+        && outer.start <= inner.start
+        && inner.end <= outer.end
+        && (!inner.is_empty() || (outer.start < inner.start && inner.start < outer.end))
+}
+
+/// The object `expr` reads `property` from, when it is `object.property`.
+fn static_member<'e, 'a>(expr: &'e Expression<'a>, property: &str) -> Option<&'e Expression<'a>> {
+    match expr.without_parentheses() {
+        Expression::StaticMemberExpression(member) if member.property.name == property => {
+            Some(&member.object)
+        }
+        _ => None,
+    }
+}
+
+/// The value of the `key` property of `object`.
+fn property<'e, 'a>(object: &'e ObjectExpression<'a>, key: &str) -> Option<&'e Expression<'a>> {
+    object.properties.iter().find_map(|x| match x {
+        ObjectPropertyKind::ObjectProperty(x)
+            if x.key.static_name().is_some_and(|name| name == key) =>
+        {
+            Some(&x.value)
+        }
+        _ => None,
+    })
+}
+
+/// Collects the operands of a chain of `||`.
+fn flatten_or<'e, 'a>(expr: &'e Expression<'a>, out: &mut Vec<&'e Expression<'a>>) {
+    match expr.without_parentheses() {
+        Expression::LogicalExpression(x) if x.operator == LogicalOperator::Or => {
+            flatten_or(&x.left, out);
+            flatten_or(&x.right, out);
+        }
+        x => out.push(x),
+    }
+}
+
+/// The object at the root of the first awaited method call inside of `expr`, `u` in `await u.shell.openExternal(s)`.
+fn awaited_root<'s>(expr: &Expression, source: &'s str) -> Option<&'s str> {
+    struct Awaited(Option<Span>);
+    impl<'a> Visit<'a> for Awaited {
+        fn visit_await_expression(&mut self, it: &AwaitExpression<'a>) {
+            if self.0.is_none()
+                && let Expression::CallExpression(call) = it.argument.without_parentheses()
+            {
+                let mut root = &call.callee;
+                while let Expression::StaticMemberExpression(member) = root.without_parentheses() {
+                    root = &member.object;
+                }
+                if let Expression::Identifier(root) = root.without_parentheses()
+                    && matches!(
+                        call.callee.without_parentheses(),
+                        Expression::StaticMemberExpression(_)
+                    )
+                {
+                    self.0 = Some(root.span);
+                }
+            }
+            walk::walk_await_expression(self, it);
+        }
+    }
+    let mut awaited = Awaited(None);
+    awaited.visit_expression(expr);
+    awaited.0.map(|x| &source[x.start as usize..x.end as usize])
+}
+
+/// The object of the first `!o.user` inside of `expr`, with `user` being the property.
+fn user_check<'s>(expr: &Expression, user: &str, source: &'s str) -> Option<&'s str> {
+    struct User<'u>(&'u str, Option<Span>);
+    impl<'a> Visit<'a> for User<'_> {
+        fn visit_unary_expression(&mut self, it: &UnaryExpression<'a>) {
+            if self.1.is_none()
+                && it.operator == UnaryOperator::LogicalNot
+                && let Some(object) = static_member(&it.argument, self.0)
+            {
+                self.1 = Some(object.span());
+            }
+            walk::walk_unary_expression(self, it);
+        }
+    }
+    let mut check = User(user, None);
+    check.visit_expression(expr);
+    check.1.map(|x| &source[x.start as usize..x.end as usize])
+}
+
+/// Which of the patches have been applied (or were disabled), while going through the scripts.
+#[derive(Default)]
+struct Applied {
+    entitlements: bool,
+    installed: bool,
+    launch: bool,
+}
+
+/// Patches the scripts inside of `app.asar` to allow the launching of custom
+/// directories within `{games_dir}/{steam_id}`.
+///
+/// Vite may split the logic out of `main.js` into chunks, so each patch goes
+/// into whichever of `scripts` it is found in, looking inside of `entry` (the
+/// `main.js`) first. Returns the paths of the scripts that were changed.
+///
+/// Fails with [`Error::AlreadyPatched`] if any of them has been patched before,
+/// as the patched code no longer matches what is looked for.
+pub fn patch_scripts(
+    scripts: &mut BTreeMap<PathBuf, String>,
+    entry: &Path,
+    options: &MainJsOptions,
+) -> Result<Vec<PathBuf>> {
+    if let Some(version) = scripts.values().find_map(|x| patched_version(x)) {
+        return Err(Error::AlreadyPatched(version.to_string()));
+    }
+    if !scripts.contains_key(entry) {
+        return Err(Error::MainJsNotFound);
+    }
+
+    let order = std::iter::once(entry.to_path_buf())
+        .chain(scripts.keys().filter(|x| *x != entry).cloned())
+        .collect::<Vec<_>>();
+    // disabled patches count as applied, so aren't looked for
+    let mut applied = Applied {
+        entitlements: !options.entitlements,
+        installed: !options.installed_check,
+        launch: !options.launch_patch,
+    };
+    let mut changed = Vec::new();
+    for path in order {
+        let script = scripts.get_mut(&path).expect("listed from the scripts");
+        // what is removed is removed from every script, long after the other patches are found
+        let pending = !(applied.entitlements && applied.installed && applied.launch);
+        if path != entry
+            && !(pending && mentions_patches(script, &options.manifest))
+            && !mentions_removals(script, options)
+        {
+            continue;
+        }
+        match patch_script(&path, script, options, &mut applied) {
+            Ok(true) => {
+                debug!("Patched {}", path.display());
+                changed.push(path);
+            }
+            Ok(false) => {}
+            // a chunk merely mentioning what is looked for may not parse, and is only skipped
+            Err(err @ Error::MainJsParse(_)) if path != entry => {
+                debug!("Skipped {}: {err}", path.display())
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if !applied.entitlements {
+        return Err(Error::EntitlementsMatch);
+    }
+    if !applied.installed {
+        return Err(Error::InstallationMatch);
+    }
+    if !applied.launch {
+        return Err(Error::LaunchMatch);
+    }
+
+    // Mark every window as patched, so it is obvious which build is running, then the quality of life patches
+    let appended = [
+        (
+            "window title",
+            options.title_marker,
+            &options.manifest.title.template,
+        ),
+        (
+            "intro",
+            options.skip_intro,
+            &options.manifest.skip_intro.template,
+        ),
+        (
+            "devtools shortcut",
+            options.dev_tools,
+            &options.manifest.dev_tools.template,
+        ),
+        (
+            "telemetry",
+            options.strip_telemetry,
+            &options.manifest.telemetry.template,
+        ),
+        (
+            "rescan shortcut",
+            options.rescan_shortcut,
+            &options.manifest.rescan.template,
+        ),
+        (
+            "local badge",
+            options.local_badge,
+            &options.manifest.badge.template,
+        ),
+    ];
+    let hosts = serde_json::to_string(&options.manifest.telemetry.hosts)?;
+    let game_dirs = serde_json::to_string(&options.game_dirs)?;
+    let selector = serde_json::to_string(&options.manifest.badge.selector)?;
+    let values = [
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("hosts", &hosts),
+        ("games_dir", &options.games_dir),
+        ("game_dirs", &game_dirs),
+        ("selector", &selector),
+    ];
+    for (name, _, template) in appended.into_iter().filter(|(_, enabled, _)| *enabled) {
+        let main = scripts.get_mut(entry).expect("checked above");
+        if !changed.iter().any(|x| x == entry) {
+            stamp(main);
+            changed.push(entry.to_path_buf());
+        }
+        main.push_str(&render(template, &values)?);
+        debug!("Patched {name}");
+    }
+
+    Ok(changed)
+}
+
+/// Pretty-prints `entry` and every other script the patches may be in, so what
+/// gets patched (and the diff of it) is readable. Chunks that don't parse are left as is.
+///
+/// Fails with [`Error::AlreadyPatched`] like [`patch_scripts`], as printing the
+/// scripts back out loses the [`PATCH_MARKER`].
+pub fn beautify_scripts(
+    scripts: &mut BTreeMap<PathBuf, String>,
+    entry: &Path,
+    manifest: &Manifest,
+) -> Result<()> {
+    if let Some(version) = scripts.values().find_map(|x| patched_version(x)) {
+        return Err(Error::AlreadyPatched(version.to_string()));
+    }
+    for (path, script) in scripts.iter_mut() {
+        if path != entry && !mentions_patches(script, manifest) {
+            continue;
+        }
+        match reprint(script, CodegenOptions::default()) {
+            Ok(()) => debug!("Beautified {}", path.display()),
+            Err(err @ Error::MainJsParse(_)) if path != entry => {
+                debug!("Skipped beautifying {}: {err}", path.display())
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Strips the whitespace back out of a script beautified by [`beautify_scripts`],
+/// keeping the [`PATCH_MARKER`] if it has one.
+pub fn minify_script(script: &mut String) -> Result<()> {
+    let patched = patched_version(script).is_some();
+    reprint(script, CodegenOptions::minify())?;
+    if patched {
+        stamp(script);
+    }
+    Ok(())
+}
+
+/// Parses `script` and prints it back out with `options`.
+fn reprint(script: &mut String, options: CodegenOptions) -> Result<()> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, script.as_str(), SourceType::cjs()).parse();
+    if let Some(err) = parsed.errors.first() {
+        return Err(Error::MainJsParse(err.to_string()));
+    }
+    let printed = Codegen::new()
+        .with_options(options)
+        .build(&parsed.program)
+        .code;
+    // the syntax tree borrows `script`, so has to go before it can be replaced
+    drop(parsed);
+    *script = printed;
+    Ok(())
+}
+
+/// Whether `script` mentions anything the patches look for, so the scripts
+/// that can't contain any of them aren't parsed.
+pub fn mentions_patches(script: &str, manifest: &Manifest) -> bool {
+    let launch = manifest.launch.iter().map(|x| x.url_prefix.as_str());
+    let installed = manifest.installed.iter().map(|x| x.property.as_str());
+    let entitlements = manifest
+        .entitlements
+        .iter()
+        .flat_map(|x| x.guard.iter().map(String::as_str));
+    launch
+        .chain(installed)
+        .chain(entitlements)
+        .any(|x| script.contains(x))
+}
+
+/// Whether `script` mentions the telemetry or the updater, when they are being removed.
+fn mentions_removals(script: &str, options: &MainJsOptions) -> bool {
+    let manifest = &options.manifest;
+    let telemetry = manifest
+        .telemetry
+        .init_properties
+        .iter()
+        .filter(|_| options.strip_telemetry);
+    let updater = manifest
+        .updater
+        .calls
+        .iter()
+        .filter(|_| options.disable_updater);
+    telemetry.chain(updater).any(|x| script.contains(x.as_str()))
+}
+
+/// Places the [`PATCH_MARKER`] at the top of `script`, and drops its source map
+/// reference, as the map no longer lines up and would mislead the devtools.
+fn stamp(script: &mut String) {
+    strip_source_map(script);
+    script.insert_str(
+        0,
+        &format!("{PATCH_MARKER}{} */\n", env!("CARGO_PKG_VERSION")),
+    );
+}
+
+/// Removes the `//# sourceMappingURL=` comments from `script`.
+fn strip_source_map(script: &mut String) {
+    let is_reference = |line: &str| {
+        let line = line.trim_start();
+        SOURCE_MAP_PREFIXES.iter().any(|x| line.starts_with(x))
+    };
+    if script.lines().any(is_reference) {
+        *script = script
+            .split_inclusive('\n')
+            .filter(|x| !is_reference(x))
+            .collect();
+        debug!("Removed the source map reference");
+    }
+}
+
+/// Applies the patches not yet in `applied` that are found inside of `script`,
+/// returning whether any were.
+fn patch_script(
+    path: &Path,
+    script: &mut String,
+    options: &MainJsOptions,
+    applied: &mut Applied,
+) -> Result<bool> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, script.as_str(), SourceType::cjs()).parse();
+    if let Some(err) = parsed.errors.first() {
+        return Err(Error::MainJsParse(err.to_string()));
+    }
+    let mut finder = Finder::new(script.as_str(), &options.manifest);
+    finder.visit_program(&parsed.program);
+    if options.explain {
+        finder.explain(path);
+    }
+
+    // chunks may not require everything themselves, so fall back to requiring it inline
+    let manifest = &options.manifest;
+    let require = |name: Option<&str>, modules: &[String]| match (name, modules.first()) {
+        (Some(name), _) => Ok(name.to_string()),
+        (None, Some(module)) => Ok(format!("require({module:?})")),
+        (None, None) => Err(Error::RequireMatch),
+    };
+    let node_path = require(finder.node_path, &manifest.requires.path)?;
+    let node_fs = require(finder.node_fs, &manifest.requires.fs)?;
+    let child_process = require(finder.child_process, &manifest.requires.child_process)?;
+
+    let games_dir = options.games_dir.as_str();
+    let launch_local = options.launch_local.to_string();
+    let tray = options.tray.to_string();
+    let game_dirs = serde_json::to_string(&options.game_dirs)?;
+    let offline = options.offline.to_string();
+    let spoof_all = options.spoof_all.to_string();
+    let spoof = serde_json::to_string(
+        &options
+            .spoof
+            .as_ref()
+            .map(|x| x.iter().map(u32::to_string).collect::<Vec<_>>()),
+    )?;
+    let known_exes = serde_json::to_string(&manifest.known_exes)?;
+    let mut edits: Vec<(Span, String)> = Vec::new();
+    let mut found = Applied::default();
+
+    // Trick the application that you own the installed games
+    if let Some(entitlements) = finder.entitlements.take_if(|_| !applied.entitlements) {
+        let values = [
+            ("entitlements", entitlements.object),
+            ("fs", &node_fs),
+            ("games_dir", games_dir),
+            ("game_dirs", &game_dirs),
+            ("spoof_all", &spoof_all),
+            ("spoof", &spoof),
+        ];
+        let mut patch = render(
+            &manifest.entitlements[entitlements.variant].template,
+            &values,
+        )?;
+        if let Some(hook) = &options.hooks.after_entitlements {
+            patch.push_str(&format!("\n{}\n", render(hook, &values)?));
+        }
+        edits.push((Span::empty(entitlements.insert_at), patch));
+        found.entitlements = true;
+    }
+
+    // Mark the application as installed, if we do
+    if let Some(installed) = finder.installed.take_if(|_| !applied.installed) {
+        let values = [
+            ("game", installed.game),
+            ("fs", &node_fs),
+            ("games_dir", games_dir),
+            ("game_dirs", &game_dirs),
+        ];
+        let check = render(&manifest.installed[installed.variant].template, &values)?;
+        edits.push((Span::empty(installed.value.start), "(".to_string()));
+        edits.push((Span::empty(installed.value.end), format!("){check}")));
+
+        // the assignment is an expression, so the hook runs inside of an arrow function following it
+        if let Some(hook) = &options.hooks.after_install_check {
+            let hook = render(hook, &values)?;
+            edits.push((Span::empty(installed.assignment.start), "(".to_string()));
+            edits.push((
+                Span::empty(installed.assignment.end),
+                format!(", (() => {{\n{hook}\n}})())"),
+            ));
+        }
+        found.installed = true;
+    }
+
+    // Modify the launch behaviour to use local files
+    if let Some(launch) = finder.launch.take_if(|_| !applied.launch) {
+        let values = [
+            ("url", launch.url.url),
+            ("game", launch.url.game),
+            ("launch_to", launch.url.launch_to),
+            ("electron", launch.electron),
+            ("state", launch.state),
+            ("path", &node_path),
+            ("fs", &node_fs),
+            ("child_process", &child_process),
+            ("games_dir", games_dir),
+            ("game_dirs", &game_dirs),
+            ("launch_local", &launch_local),
+            ("known_exes", &known_exes),
+            ("tray", &tray),
+            ("offline", &offline),
+        ];
+        let mut patch = match &options.hooks.before_launch {
+            Some(hook) => format!("\n{}\n", render(hook, &values)?),
+            None => String::new(),
+        };
+        patch.push_str(&render(
+            &manifest.launch[launch.url.variant].template,
+            &values,
+        )?);
+        edits.push((launch.span, patch));
+        found.launch = true;
+    }
+
+    // Let the devtools be opened, for debugging what was injected
+    if options.dev_tools {
+        edits.extend(finder.dev_tools.iter().map(|x| (*x, "true".to_string())));
+    }
+
+    // Stop the telemetry from being set up
+    if options.strip_telemetry {
+        edits.extend(finder.telemetry.iter().map(|x| (*x, "void 0".to_string())));
+    }
+
+    // Stop the Megapicker updating itself, which would undo the patches. Whatever awaits the update finds none
+    if options.disable_updater {
+        edits.extend(
+            finder
+                .updater
+                .iter()
+                .map(|x| (*x, "Promise.resolve(null)".to_string())),
+        );
+    }
+    if edits.is_empty() {
+        return Ok(false);
+    }
+
+    // An edit inside of another's span, like a call inside of the replaced launch branch, goes with it
+    let spans = edits.iter().map(|(x, _)| *x).collect::<Vec<_>>();
+    edits.retain(|(inner, _)| !spans.iter().any(|outer| nested(*outer, *inner)));
+
+    // Apply from the end, so the spans of the earlier edits stay valid
+    edits.sort_by_key(|(span, _)| std::cmp::Reverse((span.start, span.end)));
+    let mut patched = script.clone();
+    for (span, text) in edits {
+        patched.replace_range(span.start as usize..span.end as usize, &text);
+    }
+    // the syntax tree borrows `script`, so has to go before it can be replaced
+    drop(parsed);
+    *script = patched;
+
+    // Stamp the file, so it isn't patched twice
+    stamp(script);
+    applied.entitlements |= found.entitlements;
+    applied.installed |= found.installed;
+    applied.launch |= found.launch;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN_JS: &str = r#"async function launch(a, r, e) {
+    const s = `steam://run/${a.data.steamId}// -launchTo ${r}`;
+    if (!(await e.getState()).user) {
+        Sentry.init({ dsn: "https://key@sentry.io/1" });
+        return s;
+    }
+    return s;
+}
+"#;
+
+    const CHUNK_JS: &str = r#"Sentry.init({ dsn: "https://key@sentry.io/2" });
+"#;
+
+    const UPDATER_JS: &str = r#"autoUpdater.checkForUpdatesAndNotify();
+"#;
+
+    fn options() -> MainJsOptions {
+        MainJsOptions {
+            entitlements: false,
+            installed_check: false,
+            strip_telemetry: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn telemetry_inside_of_the_launch_branch() {
+        let entry = PathBuf::from("main.js");
+        let mut scripts = BTreeMap::from([(entry.clone(), MAIN_JS.to_string())]);
+        patch_scripts(&mut scripts, &entry, &options()).unwrap();
+
+        let patched = &scripts[&entry];
+        assert!(!patched.contains("Sentry.init"));
+        assert!(!patched.contains("void 0"));
+        reprint(&mut patched.clone(), CodegenOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn telemetry_in_every_chunk() {
+        let entry = PathBuf::from("main.js");
+        let chunk = PathBuf::from("chunks/sentry.js");
+        let mut scripts = BTreeMap::from([
+            (entry.clone(), MAIN_JS.to_string()),
+            (chunk.clone(), CHUNK_JS.to_string()),
+        ]);
+        let changed = patch_scripts(&mut scripts, &entry, &options()).unwrap();
+
+        assert!(changed.contains(&chunk));
+        assert!(!scripts[&chunk].contains("Sentry.init"));
+    }
+
+    #[test]
+    fn updater_only_when_disabled() {
+        let entry = PathBuf::from("main.js");
+        let chunk = PathBuf::from("chunks/updater.js");
+        let scripts = BTreeMap::from([
+            (entry.clone(), MAIN_JS.to_string()),
+            (chunk.clone(), UPDATER_JS.to_string()),
+        ]);
+
+        let mut kept = scripts.clone();
+        patch_scripts(&mut kept, &entry, &options()).unwrap();
+        assert_eq!(kept[&chunk], UPDATER_JS);
+
+        let mut disabled = scripts;
+        let options = MainJsOptions {
+            disable_updater: true,
+            ..options()
+        };
+        patch_scripts(&mut disabled, &entry, &options).unwrap();
+        assert!(!disabled[&chunk].contains("checkForUpdatesAndNotify"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+/// The file name of a manifest replacing the embedded one, which lives next to the patcher.
+const MANIFEST_NAME: &str = "jackbox_megapicker_patcher.patches.toml";
+
+/// The patches for the current Megapicker, used when there is no manifest beside the patcher.
+const DEFAULT_MANIFEST: &str = include_str!("../patches.toml");
+
+/// The manifest version this patcher understands.
+const MANIFEST_VERSION: u32 = 2;
+
+/// What is looked for inside of `main.js`, and what it is patched with, so a
+/// new Megapicker version can be supported by shipping a manifest instead of a new patcher.
+///
+/// Each patch is a list of alternatives, tried in order, so a small change
+/// between builds can be handled by another alternative rather than failing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Where `main.js` is inside of `app.asar`, otherwise it is looked for
+    /// through `package.json`, then by which script has the launch block
+    pub path: PathBuf,
+    pub requires: Requires,
+    pub entitlements: Vec<EntitlementsPatch>,
+    pub installed: Vec<InstalledPatch>,
+    pub launch: Vec<LaunchPatch>,
+    pub title: AppendPatch,
+    /// Added after version 2, so these are taken from the embedded manifest when missing
+    #[serde(default = "default_skip_intro")]
+    pub skip_intro: AppendPatch,
+    #[serde(default = "default_dev_tools")]
+    pub dev_tools: AppendPatch,
+    #[serde(default = "default_telemetry")]
+    pub telemetry: TelemetryPatch,
+    #[serde(default = "default_updater")]
+    pub updater: UpdaterPatch,
+    #[serde(default = "default_rescan")]
+    pub rescan: AppendPatch,
+    #[serde(default = "default_badge")]
+    pub badge: BadgePatch,
+    /// The exe each pack is launched from, keyed by Steam ID
+    #[serde(default)]
+    pub known_exes: BTreeMap<String, KnownExe>,
+}
+
+/// The modules the patches use, by any of what they may be required as. The
+/// first is required inline by scripts which don't require the module themselves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Requires {
+    pub path: Vec<String>,
+    pub fs: Vec<String>,
+    pub child_process: Vec<String>,
+}
+
+/// Marks every game inside of the games directory as owned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntitlementsPatch {
+    /// The properties checked by `if (!e.entitlements || ...) return null;`
+    pub guard: Vec<String>,
+    pub template: String,
+}
+
+/// Marks every game inside of the games directory as installed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledPatch {
+    /// The property of the game keying the installation state, `steamId`
+    pub key: String,
+    /// The property holding whether the game is installed, `isInstalled`
+    pub property: String,
+    /// Another property of the installation state, to be sure it is the right object
+    pub sibling: String,
+    pub template: String,
+}
+
+/// Launches games from the games directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LaunchPatch {
+    /// What the launch URL starts with
+    pub url_prefix: String,
+    /// The properties the first part of the launch URL reads off the game, dot separated
+    pub steam_id: String,
+    /// The property checked for a logged in user
+    pub user: String,
+    pub template: String,
+}
+
+/// Where a pack is launched from, so the launch patch doesn't have to guess.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownExe {
+    /// Relative to the pack's folder
+    pub exe: String,
+    /// The folder it is run from, relative to the pack's folder, otherwise the exe's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+}
+
+/// Code appended to the end of `main.js`, such as marking every window as patched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendPatch {
+    pub template: String,
+}
+
+/// Stops the Megapicker reporting home.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryPatch {
+    /// Calls passed an object with any of these, like Sentry's `dsn`, are dropped
+    pub init_properties: Vec<String>,
+    /// URL patterns, as Electron's `webRequest` filters them, whose requests are cancelled
+    pub hosts: Vec<String>,
+    pub template: String,
+}
+
+/// Stops the Megapicker updating itself, and losing the patches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdaterPatch {
+    /// The updater's methods, whose calls are replaced
+    pub calls: Vec<String>,
+}
+
+/// Badges the packs that launch from the games directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadgePatch {
+    /// Finds a pack's tile, with `{id}` being its Steam ID
+    pub selector: String,
+    pub template: String,
+}
+
+fn default_skip_intro() -> AppendPatch {
+    Manifest::default().skip_intro
+}
+
+fn default_dev_tools() -> AppendPatch {
+    Manifest::default().dev_tools
+}
+
+fn default_telemetry() -> TelemetryPatch {
+    Manifest::default().telemetry
+}
+
+fn default_updater() -> UpdaterPatch {
+    Manifest::default().updater
+}
+
+fn default_rescan() -> AppendPatch {
+    Manifest::default().rescan
+}
+
+fn default_badge() -> BadgePatch {
+    Manifest::default().badge
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_MANIFEST).expect("the embedded manifest is valid")
+    }
+}
+
+impl Manifest {
+    /// Loads the manifest at `path`, or the one beside the patcher, falling back to the embedded one.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = std::env::current_exe()?.with_file_name(MANIFEST_NAME);
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+
+        let manifest: Self = toml::from_str(&std::fs::read_to_string(&path)?)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(Error::ManifestVersion(manifest.version, MANIFEST_VERSION));
+        }
+        info!("Loaded patch manifest from {}", path.display());
+        Ok(manifest)
+    }
+}
+
+/// Fills in the `{{name}}` placeholders of `template` with `values`.
+pub fn render(template: &str, values: &[(&str, &str)]) -> Result<String> {
+    let mut out = template.to_string();
+    for (name, value) in values {
+        out = out.replace(&format!("{{{{{name}}}}}"), value);
+    }
+
+    // anything left over is a typo inside of the manifest
+    for (start, _) in out.match_indices("{{") {
+        let rest = &out[start + 2..];
+        if let Some(end) = rest.find("}}")
+            && end > 0
+            && rest[..end]
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || x == '_')
+        {
+            return Err(Error::UnknownPlaceholder(rest[..end].to_string()));
+        }
+    }
+    Ok(out)
+}
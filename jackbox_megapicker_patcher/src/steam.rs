@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Matches a `"key" "value"` pair inside of a VDF/ACF file.
+static KEY_VALUE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#""(\w+)"\s+"((?:[^"\\]|\\.)*)""#).unwrap());
+
+/// A game installed through Steam, read from its `appmanifest_{app_id}.acf`.
+#[derive(Debug, Clone)]
+pub struct InstalledApp {
+    pub app_id: u32,
+    pub name: String,
+    pub build_id: String,
+    pub install_dir: PathBuf,
+}
+
+/// Returns the values of every `key` inside of the VDF/ACF `contents`, unescaped.
+fn values<'a>(contents: &'a str, key: &'a str) -> impl Iterator<Item = String> + 'a {
+    KEY_VALUE_RE
+        .captures_iter(contents)
+        .filter(move |caps| &caps[1] == key)
+        .map(|caps| caps[2].replace("\\\\", "\\"))
+}
+
+/// Returns the `steamapps` directory of every Steam library, starting with the
+/// one the app at `app_path` (`{library}/steamapps/common/{app}`) is installed in.
+pub fn library_folders(app_path: &Path) -> Vec<PathBuf> {
+    let Some(steamapps) = app_path.parent().and_then(Path::parent) else {
+        return Vec::new();
+    };
+
+    let mut libraries = vec![steamapps.to_path_buf()];
+    if let Ok(contents) = std::fs::read_to_string(steamapps.join("libraryfolders.vdf")) {
+        for path in values(&contents, "path") {
+            let library = PathBuf::from(path).join("steamapps");
+            if !libraries.contains(&library) {
+                libraries.push(library);
+            }
+        }
+    }
+    libraries
+}
+
+/// Reads the manifest for `app_id` inside of the library `steamapps`.
+pub fn read_manifest(steamapps: &Path, app_id: u32) -> Option<InstalledApp> {
+    let contents =
+        std::fs::read_to_string(steamapps.join(format!("appmanifest_{app_id}.acf"))).ok()?;
+    let install_dir = values(&contents, "installdir").next()?;
+    Some(InstalledApp {
+        app_id,
+        name: values(&contents, "name").next().unwrap_or_default(),
+        build_id: values(&contents, "buildid").next().unwrap_or_default(),
+        install_dir: steamapps.join("common").join(install_dir),
+    })
+}
+
+/// Returns every app installed across all of the Steam libraries.
+pub fn installed_apps(app_path: &Path) -> Vec<InstalledApp> {
+    let mut apps = Vec::new();
+    for steamapps in library_folders(app_path) {
+        let Ok(entries) = std::fs::read_dir(&steamapps) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(app_id) = file_name
+                .strip_prefix("appmanifest_")
+                .and_then(|x| x.strip_suffix(".acf"))
+                .and_then(|x| x.parse().ok())
+            else {
+                continue;
+            };
+            apps.extend(read_manifest(&steamapps, app_id));
+        }
+    }
+    apps
+}